@@ -0,0 +1,340 @@
+use log::{error, warn};
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use crate::config::AccessLogConfig;
+
+// ---------------------------------------------------------------------------
+// Access log
+//
+// One record per connection, written when it closes: who connected where,
+// how it was routed, which upstream carried it, how many bytes went each way,
+// how long it took and why it ended. Records are a line of `key=value` pairs,
+// or a JSON object with `log-format: json`, on stderr or in `access_log.file`.
+// Health server connections are not logged.
+// ---------------------------------------------------------------------------
+
+/// Identifies one accepted connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ConnectionId(u64);
+
+impl ConnectionId {
+    pub fn new() -> Self {
+        ConnectionId(fastrand::u64(..))
+    }
+}
+
+impl Default for ConnectionId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for ConnectionId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// Parses the 16 hex digits `Display` writes.
+impl FromStr for ConnectionId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != 16 || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(format!("invalid connection ID {:?}", s));
+        }
+        u64::from_str_radix(s, 16)
+            .map(ConnectionId)
+            .map_err(|e| e.to_string())
+    }
+}
+
+impl Serialize for ConnectionId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// What the upstream side learns while it sets up and runs a tunnel.
+#[derive(Debug, Default, Clone)]
+pub struct TunnelDetails {
+    /// Name of the upstream that carried the tunnel.
+    pub upstream: Option<String>,
+    /// Address of the proxy or backend it connected to.
+    pub upstream_addr: Option<String>,
+    /// Status of the upstream proxy's CONNECT response.
+    pub connect_status: Option<u16>,
+    /// Time to establish the TCP connection to the upstream.
+    pub connect_time: Option<Duration>,
+    pub bytes_tx: u64,
+    pub bytes_rx: u64,
+}
+
+/// `TunnelDetails` shared between the accept task and the upstream code.
+/// The byte counts are counted live, so they can be read while the tunnel
+/// is still open.
+#[derive(Debug, Default)]
+pub struct TunnelInfo {
+    details: Mutex<TunnelDetails>,
+    bytes_tx: Arc<AtomicU64>,
+    bytes_rx: Arc<AtomicU64>,
+}
+
+impl TunnelInfo {
+    fn lock(&self) -> MutexGuard<'_, TunnelDetails> {
+        self.details.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn update(&self, f: impl FnOnce(&mut TunnelDetails)) {
+        f(&mut self.lock());
+    }
+
+    /// The (tx, rx) counters the relay advances as data flows.
+    pub fn byte_counters(&self) -> (Arc<AtomicU64>, Arc<AtomicU64>) {
+        (self.bytes_tx.clone(), self.bytes_rx.clone())
+    }
+
+    pub fn get(&self) -> TunnelDetails {
+        TunnelDetails {
+            bytes_tx: self.bytes_tx.load(Ordering::Relaxed),
+            bytes_rx: self.bytes_rx.load(Ordering::Relaxed),
+            ..self.lock().clone()
+        }
+    }
+}
+
+/// One access log record.
+#[derive(Debug, Default, Serialize)]
+pub struct AccessRecord {
+    pub ts: String,
+    pub conn_id: ConnectionId,
+    pub client: Option<SocketAddr>,
+    pub listen: Option<SocketAddr>,
+    pub server: String,
+    pub sni: Option<String>,
+    pub route: Option<String>,
+    pub upstream: Option<String>,
+    pub upstream_addr: Option<String>,
+    /// CONNECT target requested from the upstream proxy.
+    pub target: Option<String>,
+    pub connect_status: Option<u16>,
+    pub bytes_tx: u64,
+    pub bytes_rx: u64,
+    pub connect_ms: Option<f64>,
+    pub duration_ms: f64,
+    pub reason: CloseReason,
+    pub error: Option<String>,
+}
+
+/// Why a connection ended.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CloseReason {
+    /// Closed by either side after being handled.
+    #[default]
+    Closed,
+    /// The handler or the upstream failed.
+    Error,
+    InvalidProxyHeader,
+    ClientHelloTimeout,
+    ConnectRequestRejected,
+    ConnectRequestTimeout,
+    /// Closed through the admin API.
+    Killed,
+}
+
+impl CloseReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CloseReason::Closed => "closed",
+            CloseReason::Error => "error",
+            CloseReason::InvalidProxyHeader => "invalid_proxy_header",
+            CloseReason::ClientHelloTimeout => "client_hello_timeout",
+            CloseReason::ConnectRequestRejected => "connect_request_rejected",
+            CloseReason::ConnectRequestTimeout => "connect_request_timeout",
+            CloseReason::Killed => "killed",
+        }
+    }
+}
+
+impl AccessRecord {
+    pub fn new(id: ConnectionId, server: &str) -> Self {
+        AccessRecord {
+            conn_id: id,
+            server: server.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Take over what the upstream side recorded.
+    pub fn set_tunnel(&mut self, tunnel: TunnelDetails) {
+        self.upstream = tunnel.upstream.or(self.upstream.take());
+        self.upstream_addr = tunnel.upstream_addr;
+        self.connect_status = tunnel.connect_status;
+        self.connect_ms = tunnel.connect_time.map(millis);
+        self.bytes_tx = tunnel.bytes_tx;
+        self.bytes_rx = tunnel.bytes_rx;
+    }
+
+    pub fn fail(&mut self, error: &dyn fmt::Display) {
+        self.reason = CloseReason::Error;
+        self.error = Some(error.to_string());
+    }
+
+    /// Stamp the record at close, `elapsed` after the connection was accepted.
+    pub fn finish(&mut self, elapsed: Duration) {
+        self.ts = time::OffsetDateTime::now_utc()
+            .format(&time::format_description::well_known::Rfc3339)
+            .unwrap_or_default();
+        self.duration_ms = millis(elapsed);
+    }
+
+    fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    fn to_txt(&self) -> String {
+        fn opt<T: ToString>(value: &Option<T>) -> String {
+            value
+                .as_ref()
+                .map_or("-".to_string(), |v| txt(&v.to_string()))
+        }
+        let mut line = format!(
+            "{} conn={} client={} listen={} server={} sni={} route={} upstream={} \
+             upstream_addr={} target={} status={} tx={} rx={} connect_ms={} duration_ms={} \
+             reason={}",
+            self.ts,
+            self.conn_id,
+            opt(&self.client),
+            opt(&self.listen),
+            txt(&self.server),
+            opt(&self.sni),
+            opt(&self.route),
+            opt(&self.upstream),
+            opt(&self.upstream_addr),
+            opt(&self.target),
+            opt(&self.connect_status),
+            self.bytes_tx,
+            self.bytes_rx,
+            opt(&self.connect_ms),
+            self.duration_ms,
+            self.reason.as_str()
+        );
+        if let Some(error) = &self.error {
+            line.push_str(&format!(" error={:?}", error));
+        }
+        line
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    (duration.as_secs_f64() * 1000.0 * 1000.0).round() / 1000.0
+}
+
+/// Quote values a reader could not tell apart from the separators.
+fn txt(value: &str) -> String {
+    if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '"' || c == '=') {
+        format!("{:?}", value)
+    } else {
+        value.to_string()
+    }
+}
+
+/// Where records go. Lines are written by a thread of their own, so a slow
+/// disk never stalls the connection tasks; it exits once the log is replaced
+/// and every line sent to it is written.
+pub(crate) struct AccessLog {
+    json: bool,
+    lines: Sender<String>,
+}
+
+impl AccessLog {
+    pub fn open(config: &AccessLogConfig) -> io::Result<Self> {
+        let file = match &config.file {
+            Some(path) => Some(OpenOptions::new().create(true).append(true).open(path)?),
+            None => None,
+        };
+        Self::start(config.json, file)
+    }
+
+    /// `file` = `None` writes to stderr.
+    fn start(json: bool, mut file: Option<File>) -> io::Result<Self> {
+        let (lines, received) = mpsc::channel::<String>();
+        thread::Builder::new()
+            .name("access-log".to_string())
+            .spawn(move || {
+                for line in received {
+                    let written = match &mut file {
+                        Some(file) => file.write_all(line.as_bytes()),
+                        None => io::stderr().lock().write_all(line.as_bytes()),
+                    };
+                    if let Err(e) = written {
+                        warn!("Could not write access log record: {}", e);
+                    }
+                }
+            })?;
+        Ok(AccessLog { json, lines })
+    }
+
+    pub fn write(&self, record: &AccessRecord) {
+        let mut line = if self.json {
+            record.to_json()
+        } else {
+            record.to_txt()
+        };
+        line.push('\n');
+        if self.lines.send(line).is_err() {
+            warn!("Access log writer has stopped, dropping record");
+        }
+    }
+}
+
+static ACCESS_LOG: RwLock<Option<Arc<AccessLog>>> = RwLock::new(None);
+
+/// Replace the process-wide access log. The file is opened again, so a
+/// reload also picks up a rotated file.
+pub(crate) fn configure(config: &AccessLogConfig) {
+    let log = if config.is_enabled() {
+        match AccessLog::open(config) {
+            Ok(log) => Some(Arc::new(log)),
+            Err(e) => {
+                error!(
+                    "Could not open access log {:?}, writing to stderr: {}",
+                    config.file, e
+                );
+                match AccessLog::start(config.json, None) {
+                    Ok(log) => Some(Arc::new(log)),
+                    Err(e) => {
+                        error!("Could not start the access log writer: {}", e);
+                        None
+                    }
+                }
+            }
+        }
+    } else {
+        None
+    };
+    *ACCESS_LOG.write().unwrap_or_else(|e| e.into_inner()) = log;
+}
+
+/// Write `record` to the configured access log, if any.
+pub(crate) fn write(record: &AccessRecord) {
+    let log = ACCESS_LOG.read().unwrap_or_else(|e| e.into_inner()).clone();
+    if let Some(log) = log {
+        log.write(record);
+    }
+}
+
+#[cfg(test)]
+#[path = "access_log_tests.rs"]
+mod tests;
@@ -0,0 +1,125 @@
+use super::*;
+
+fn sample_record() -> AccessRecord {
+    let mut record = AccessRecord::new(ConnectionId(0xbeef), "https_server");
+    record.client = Some("192.0.2.1:50000".parse().unwrap());
+    record.listen = Some("127.0.0.1:8443".parse().unwrap());
+    record.sni = Some("www.example.com".to_string());
+    record.route = Some("*.example.com".to_string());
+    record.upstream = Some("corp".to_string());
+    record.target = Some("www.example.com:443".to_string());
+    record.set_tunnel(TunnelDetails {
+        upstream: Some("corp_backup".to_string()),
+        upstream_addr: Some("10.0.0.1:3128".to_string()),
+        connect_status: Some(200),
+        connect_time: Some(Duration::from_micros(2500)),
+        bytes_tx: 517,
+        bytes_rx: 4096,
+    });
+    record.finish(Duration::from_millis(1200));
+    record
+}
+
+#[test]
+fn test_connection_id_is_fixed_width_hex() {
+    assert_eq!(ConnectionId(0xbeef).to_string(), "000000000000beef");
+    assert_eq!(ConnectionId::new().to_string().len(), 16);
+}
+
+#[test]
+fn test_connection_id_parses_display_form() {
+    let id = ConnectionId::new();
+    assert_eq!(id.to_string().parse::<ConnectionId>(), Ok(id));
+    assert_eq!("000000000000beef".parse(), Ok(ConnectionId(0xbeef)));
+    for invalid in ["beef", "000000000000beeg", "+00000000000beef", ""] {
+        assert!(invalid.parse::<ConnectionId>().is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn test_set_tunnel_keeps_routed_upstream_without_details() {
+    let mut record = AccessRecord::new(ConnectionId(1), "s");
+    record.upstream = Some("corp".to_string());
+    record.set_tunnel(TunnelDetails::default());
+    assert_eq!(record.upstream.as_deref(), Some("corp"));
+
+    // The fallback that carried the tunnel wins over the routed name.
+    assert_eq!(sample_record().upstream.as_deref(), Some("corp_backup"));
+}
+
+#[test]
+fn test_txt_record() {
+    let line = sample_record().to_txt();
+    assert!(
+        line.ends_with(
+            " conn=000000000000beef client=192.0.2.1:50000 listen=127.0.0.1:8443 \
+             server=https_server sni=www.example.com route=*.example.com upstream=corp_backup \
+             upstream_addr=10.0.0.1:3128 target=www.example.com:443 status=200 tx=517 rx=4096 \
+             connect_ms=2.5 duration_ms=1200 reason=closed"
+        ),
+        "{}",
+        line
+    );
+}
+
+#[test]
+fn test_txt_record_quotes_and_placeholders() {
+    let mut record = AccessRecord::new(ConnectionId(1), "s");
+    record.route = Some("^(a|b) c$".to_string());
+    record.fail(&"connection refused");
+    let line = record.to_txt();
+    assert!(line.contains(r#" route="^(a|b) c$" "#), "{}", line);
+    assert!(line.contains(" sni=- "), "{}", line);
+    assert!(line.contains(" status=- "), "{}", line);
+    assert!(
+        line.ends_with(r#" reason=error error="connection refused""#),
+        "{}",
+        line
+    );
+}
+
+#[test]
+fn test_json_record() {
+    let mut record = sample_record();
+    record.reason = CloseReason::ClientHelloTimeout;
+    let value: serde_json::Value = serde_json::from_str(&record.to_json()).unwrap();
+    assert_eq!(value["conn_id"], "000000000000beef");
+    assert_eq!(value["client"], "192.0.2.1:50000");
+    assert_eq!(value["sni"], "www.example.com");
+    assert_eq!(value["connect_status"], 200);
+    assert_eq!(value["bytes_rx"], 4096);
+    assert_eq!(value["connect_ms"], 2.5);
+    assert_eq!(value["reason"], "client_hello_timeout");
+    assert!(value["error"].is_null());
+    assert!(value["ts"].as_str().unwrap().contains('T'));
+}
+
+#[test]
+fn test_write_appends_to_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("access.log");
+    let config = AccessLogConfig {
+        enabled: Some(true),
+        file: Some(path.to_string_lossy().into_owned()),
+        json: true,
+    };
+
+    AccessLog::open(&config).unwrap().write(&sample_record());
+    AccessLog::open(&config).unwrap().write(&sample_record());
+
+    // Written by the logs' own threads.
+    let mut contents = String::new();
+    for _ in 0..50 {
+        contents = std::fs::read_to_string(&path).unwrap();
+        if contents.lines().count() == 2 {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let lines: Vec<&str> = contents.lines().collect();
+    assert_eq!(lines.len(), 2);
+    for line in lines {
+        let value: serde_json::Value = serde_json::from_str(line).unwrap();
+        assert_eq!(value["server"], "https_server");
+    }
+}
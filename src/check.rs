@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use crate::config::{BaseConfig, Config};
+use crate::servers::upstream_address;
+use crate::upstreams::Upstream;
+
+// ---------------------------------------------------------------------------
+// Config check (`tpt --check`)
+//
+// Loads and validates a config without binding anything, and reports every
+// problem instead of stopping at the first — meant for CI and for testing an
+// edit before sending SIGHUP.
+// ---------------------------------------------------------------------------
+
+/// Validate the config at `path`. With `resolve`, also look up every upstream
+/// host name. Returns one message per problem; empty means the config is OK.
+pub(crate) fn check_config(path: &str, resolve: bool) -> Vec<String> {
+    let config = match Config::load(path) {
+        Ok(config) => config,
+        Err(e) => {
+            let mut errors = e.messages();
+            errors.extend(check_listen_addresses(path));
+            return errors;
+        }
+    };
+    let mut errors = check_listen_addresses(path);
+    if !resolve {
+        return errors;
+    }
+
+    let runtime = match tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            errors.push(format!("Could not start DNS resolver: {}", e));
+            return errors;
+        }
+    };
+    errors.extend(runtime.block_on(async {
+        upstream_address::configure(&config.base.dns);
+        resolve_upstreams(&config.base.upstream).await
+    }));
+    errors
+}
+
+/// Listen addresses that won't bind. The server only logs and skips these at
+/// startup, so they are reported here rather than by the loader.
+fn check_listen_addresses(path: &str) -> Vec<String> {
+    // A file that doesn't read or parse was already reported by the loader.
+    let Ok(base) = BaseConfig::read(path) else {
+        return Vec::new();
+    };
+    let mut servers: Vec<_> = base.servers.iter().collect();
+    servers.sort_by_key(|(name, _)| *name);
+
+    let mut errors = Vec::new();
+    for (name, server) in servers {
+        for listen in &server.listen {
+            if listen.parse::<SocketAddr>().is_err() {
+                errors.push(format!(
+                    "Invalid listen address {} in server {}: expected ip:port",
+                    listen, name
+                ));
+            }
+        }
+    }
+    errors
+}
+
+/// Resolve every proxy, pool member and SRV name, in upstream name order.
+async fn resolve_upstreams(upstreams: &HashMap<String, Upstream>) -> Vec<String> {
+    let mut names: Vec<&String> = upstreams.keys().collect();
+    names.sort();
+
+    let mut errors = Vec::new();
+    for name in names {
+        let upstream = &upstreams[name];
+        for proxy in upstream.proxies() {
+            if let Err(e) = proxy.resolve_dns().await {
+                errors.push(format!(
+                    "Upstream {}: cannot resolve {}: {}",
+                    name, proxy.addr, e
+                ));
+            }
+        }
+        if let Upstream::Srv(srv) = upstream
+            && let Err(e) = srv.refresh().await
+        {
+            errors.push(format!(
+                "Upstream {}: cannot look up SRV {}: {}",
+                name, srv.name, e
+            ));
+        }
+    }
+    errors
+}
+
+#[cfg(test)]
+#[path = "check_tests.rs"]
+mod tests;
@@ -0,0 +1,43 @@
+use super::*;
+
+#[test]
+fn test_check_valid_config() {
+    assert_eq!(
+        check_config("tests/config.yaml", false),
+        Vec::<String>::new()
+    );
+}
+
+#[test]
+fn test_check_lists_every_error() {
+    let errors = check_config("tests/config_check_errors.yaml", false);
+    assert_eq!(errors.len(), 4, "{:?}", errors);
+    assert!(errors[0].contains("ftp"), "{:?}", errors);
+    assert_eq!(errors[1], "Duplicate listen address 127.0.0.1:56117");
+    assert_eq!(errors[2], "Upstream nonexistent_upstream not found");
+    assert_eq!(
+        errors[3],
+        "Invalid listen address 0.0.0.0:http in server second: expected ip:port"
+    );
+}
+
+#[test]
+fn test_check_rejects_unparsable_listen_address() {
+    let errors = check_config("tests/config_bad_listen.yaml", false);
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+    assert!(errors[0].contains("localhost:56116"), "{:?}", errors);
+}
+
+#[test]
+fn test_check_missing_file() {
+    let errors = check_config("tests/nonexistent.yaml", false);
+    assert_eq!(errors.len(), 1);
+}
+
+#[test]
+fn test_check_resolves_ip_literal_upstreams() {
+    assert_eq!(
+        check_config("tests/config_check_resolve.yaml", true),
+        Vec::<String>::new()
+    );
+}
@@ -0,0 +1,85 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use url::Url;
+
+use super::types::{
+    AccessLogConfig, AdminConfig, ConfigWatch, DnsConfig, ParsedConfig, ServerConfig,
+    TracingConfig, UpstreamConfig,
+};
+
+// ---------------------------------------------------------------------------
+// Effective config dump
+//
+// The running configuration with every default filled in, as served on the
+// admin API's `/admin/config`. Secrets are replaced: the admin token, every
+// `headers` value (any of them may carry a credential) and passwords in URLs.
+// ---------------------------------------------------------------------------
+
+const REDACTED: &str = "redacted";
+
+#[derive(Serialize)]
+struct Effective<'a> {
+    version: i32,
+    log: &'a str,
+    #[serde(rename = "log-format")]
+    log_format: &'a str,
+    dns: &'a DnsConfig,
+    config_watch: &'a ConfigWatch,
+    access_log: &'a AccessLogConfig,
+    tracing: &'a TracingConfig,
+    admin: &'a AdminConfig,
+    servers: &'a HashMap<String, ServerConfig>,
+    upstream: &'a HashMap<String, UpstreamConfig>,
+}
+
+impl ParsedConfig {
+    /// This config as JSON, with secrets redacted.
+    pub fn to_json(&self) -> Value {
+        let effective = Effective {
+            version: self.version,
+            log: self.log.as_deref().unwrap_or("info"),
+            log_format: &self.log_format,
+            dns: &self.dns,
+            config_watch: &self.config_watch,
+            access_log: &self.access_log,
+            tracing: &self.tracing,
+            admin: &self.admin,
+            servers: &self.servers,
+            upstream: &self.upstream_config,
+        };
+        let mut value = serde_json::to_value(effective).unwrap_or_default();
+        if let Some(token) = value.pointer_mut("/admin/token")
+            && token.is_string()
+        {
+            *token = REDACTED.into();
+        }
+        redact(&mut value);
+        value
+    }
+}
+
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                match value {
+                    Value::Object(headers) if key == "headers" => {
+                        headers.values_mut().for_each(|v| *v = REDACTED.into());
+                    }
+                    _ => redact(value),
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact),
+        Value::String(s) => {
+            if let Ok(mut url) = Url::parse(s)
+                && url.password().is_some()
+                && url.set_password(Some(REDACTED)).is_ok()
+            {
+                *s = url.to_string();
+            }
+        }
+        _ => {}
+    }
+}
@@ -6,6 +6,18 @@ pub enum ConfigError {
     IO(IOError),
     Yaml(serde_yaml_ng::Error),
     Custom(String),
+    /// Several problems found while validating one config.
+    Invalid(Vec<String>),
+}
+
+impl ConfigError {
+    /// One message per problem.
+    pub fn messages(&self) -> Vec<String> {
+        match self {
+            ConfigError::Invalid(errors) => errors.clone(),
+            other => vec![other.to_string()],
+        }
+    }
 }
 
 impl fmt::Display for ConfigError {
@@ -14,6 +26,7 @@ impl fmt::Display for ConfigError {
             ConfigError::IO(e) => write!(f, "IO error: {}", e),
             ConfigError::Yaml(e) => write!(f, "YAML parse error: {}", e),
             ConfigError::Custom(msg) => write!(f, "{}", msg),
+            ConfigError::Invalid(errors) => write!(f, "{}", errors.join("; ")),
         }
     }
 }
@@ -23,7 +36,7 @@ impl std::error::Error for ConfigError {
         match self {
             ConfigError::IO(e) => Some(e),
             ConfigError::Yaml(e) => Some(e),
-            ConfigError::Custom(_) => None,
+            ConfigError::Custom(_) | ConfigError::Invalid(_) => None,
         }
     }
 }
@@ -88,4 +101,15 @@ mod tests {
         let err = ConfigError::Custom("something went wrong".to_string());
         assert_eq!(err.to_string(), "something went wrong");
     }
+
+    #[test]
+    fn test_invalid_lists_every_message() {
+        let err = ConfigError::Invalid(vec!["first".to_string(), "second".to_string()]);
+        assert_eq!(err.to_string(), "first; second");
+        assert_eq!(err.messages(), vec!["first", "second"]);
+        assert_eq!(
+            ConfigError::Custom("one".to_string()).messages(),
+            vec!["one"]
+        );
+    }
 }
@@ -1,5 +1,10 @@
+mod dump;
 mod error;
 mod loader;
 mod types;
 
-pub(crate) use types::{Config, ParsedConfig, SniTarget, ViaUpstream};
+pub(crate) use types::{
+    AccessLogConfig, AdminConfig, BaseConfig, CircuitBreakerConfig, ClientHelloConfig,
+    ClientHelloTimeoutAction, Config, ConfigWatch, DnsConfig, HealthCheckConfig, ParsedConfig,
+    PoolStrategy, SniRule, SniTarget, TracingConfig, ViaUpstream,
+};
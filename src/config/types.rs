@@ -1,8 +1,12 @@
-use serde::Deserialize;
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 use std::time::Duration;
+use url::Url;
 
+use crate::proxy_protocol::ProxyProtocolVersion;
 use crate::upstreams::Upstream;
 
 // ---------------------------------------------------------------------------
@@ -19,8 +23,16 @@ pub struct Config {
 pub struct ParsedConfig {
     pub version: i32,
     pub log: Option<String>,
+    pub log_format: String,
+    pub dns: DnsConfig,
+    pub config_watch: ConfigWatch,
+    pub access_log: AccessLogConfig,
+    pub tracing: TracingConfig,
+    pub admin: AdminConfig,
     pub servers: HashMap<String, ServerConfig>,
     pub upstream: HashMap<String, Upstream>,
+    /// `upstream` as configured, for the effective config dump.
+    pub upstream_config: HashMap<String, UpstreamConfig>,
 }
 
 /// Raw YAML representation — deserialized directly from the config file.
@@ -30,20 +42,363 @@ pub struct BaseConfig {
     pub log: Option<String>,
     #[serde(rename = "log-format")]
     pub log_format: Option<String>,
+    #[serde(default)]
+    pub dns: DnsConfig,
+    #[serde(default)]
+    pub config_watch: ConfigWatch,
+    #[serde(default)]
+    pub access_log: AccessLogConfig,
+    #[serde(default)]
+    pub tracing: TracingConfig,
+    #[serde(default)]
+    pub admin: AdminConfig,
     pub servers: HashMap<String, ServerConfig>,
     #[serde(default)]
-    pub upstream: HashMap<String, String>,
+    pub upstream: HashMap<String, UpstreamConfig>,
     /// Top-level `via:` block used as a YAML anchor target only — not read in code.
     #[serde(default)]
     #[allow(dead_code)]
     pub via: ViaUpstream,
 }
 
+// ---------------------------------------------------------------------------
+// DnsConfig — resolver for upstream host names
+//
+// dns:
+//   nameservers: ["10.0.0.53", "10.0.1.53:5353"]   # default: /etc/resolv.conf
+//   min_ttl: 5s
+//   max_ttl: 5m
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DnsConfig {
+    /// `ip` or `ip:port` (default port 53). Empty = system configuration.
+    #[serde(default)]
+    pub nameservers: Vec<String>,
+    /// Lower bound for the TTL of an answer.
+    #[serde(default = "default_dns_min_ttl", with = "humantime_serde")]
+    pub min_ttl: Duration,
+    /// Upper bound for the TTL of an answer.
+    #[serde(default = "default_dns_max_ttl", with = "humantime_serde")]
+    pub max_ttl: Duration,
+    /// How long a failed lookup is remembered before it is retried.
+    #[serde(default = "default_dns_negative_ttl", with = "humantime_serde")]
+    pub negative_ttl: Duration,
+}
+
+impl Default for DnsConfig {
+    fn default() -> Self {
+        DnsConfig {
+            nameservers: Vec::new(),
+            min_ttl: default_dns_min_ttl(),
+            max_ttl: default_dns_max_ttl(),
+            negative_ttl: default_dns_negative_ttl(),
+        }
+    }
+}
+
+impl DnsConfig {
+    /// Parse `nameservers`, returning the first invalid entry as error.
+    pub fn nameserver_addrs(&self) -> Result<Vec<SocketAddr>, String> {
+        self.nameservers
+            .iter()
+            .map(|ns| {
+                ns.parse::<SocketAddr>()
+                    .or_else(|_| ns.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+                    .map_err(|_| ns.clone())
+            })
+            .collect()
+    }
+}
+
+pub(super) fn default_dns_min_ttl() -> Duration {
+    Duration::from_secs(5)
+}
+
+pub(super) fn default_dns_max_ttl() -> Duration {
+    Duration::from_secs(300)
+}
+
+pub(super) fn default_dns_negative_ttl() -> Duration {
+    Duration::from_secs(3)
+}
+
+// ---------------------------------------------------------------------------
+// ConfigWatch — reload when the config file changes
+//
+// config_watch:
+//   enabled: true
+//   interval: 2s
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ConfigWatch {
+    #[serde(default)]
+    pub enabled: bool,
+    /// How often the file is checked.
+    #[serde(default = "default_config_watch_interval", with = "humantime_serde")]
+    pub interval: Duration,
+}
+
+impl Default for ConfigWatch {
+    fn default() -> Self {
+        ConfigWatch {
+            enabled: false,
+            interval: default_config_watch_interval(),
+        }
+    }
+}
+
+pub(super) fn default_config_watch_interval() -> Duration {
+    Duration::from_secs(2)
+}
+
+// ---------------------------------------------------------------------------
+// AccessLogConfig — one record per connection when it closes
+//
+// access_log:
+//   enabled: true                    # default: on unless `log: disable`
+//   file: /var/log/tpt/access.log    # default: stderr, with the other logs
+//
+// Records are text or JSON following `log-format`.
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+pub struct AccessLogConfig {
+    #[serde(default)]
+    pub enabled: Option<bool>,
+    /// Append records to this file instead of writing them to stderr.
+    #[serde(default)]
+    pub file: Option<String>,
+    /// Set from `log-format` when the config is loaded.
+    #[serde(skip)]
+    pub json: bool,
+}
+
+impl AccessLogConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// TracingConfig — export a trace per connection over OTLP/HTTP
+//
+// tracing:
+//   otlp_endpoint: http://127.0.0.1:4318   # default: none, no export
+//   service_name: tls-proxy-tunnel          # default
+//   export_interval: 5s                     # default
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct TracingConfig {
+    /// Base URL of the collector; spans are posted to `/v1/traces` below it.
+    #[serde(default)]
+    pub otlp_endpoint: Option<Url>,
+    #[serde(default = "default_tracing_service_name")]
+    pub service_name: String,
+    /// How often finished spans are sent.
+    #[serde(default = "default_tracing_export_interval", with = "humantime_serde")]
+    pub export_interval: Duration,
+}
+
+impl Default for TracingConfig {
+    fn default() -> Self {
+        TracingConfig {
+            otlp_endpoint: None,
+            service_name: default_tracing_service_name(),
+            export_interval: default_tracing_export_interval(),
+        }
+    }
+}
+
+pub(super) fn default_tracing_service_name() -> String {
+    env!("CARGO_PKG_NAME").to_string()
+}
+
+pub(super) fn default_tracing_export_interval() -> Duration {
+    Duration::from_secs(5)
+}
+
+// ---------------------------------------------------------------------------
+// AdminConfig — admin endpoints of `health` servers
+//
+// admin:
+//   token: "change-me"   # default: none, admin endpoints disabled
+//   allow_remote: false  # default: loopback listeners only
+//
+// Requests must carry it as `Authorization: Bearer <token>`.
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+pub struct AdminConfig {
+    #[serde(default)]
+    pub token: Option<String>,
+    /// Also answer on `health` servers that listen on a non-loopback address.
+    #[serde(default)]
+    pub allow_remote: bool,
+}
+
+// ---------------------------------------------------------------------------
+// UpstreamConfig — one URL or a pool of URLs
+//
+// upstream:
+//   web: "tcp://127.0.0.1:8080"
+//   corp_proxies:
+//     strategy: least-connections
+//     members:
+//       - "tcp://proxy-a.corp:3128"
+//       - url: "tcp://proxy-b.corp:3128"
+//         weight: 2
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum UpstreamConfig {
+    Url(String),
+    Pool(PoolConfig),
+    /// A single URL with extra settings.
+    Extended {
+        url: String,
+        health_check: Option<HealthCheckConfig>,
+        circuit_breaker: Option<CircuitBreakerConfig>,
+    },
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct PoolConfig {
+    #[serde(default)]
+    pub strategy: PoolStrategy,
+    pub members: Vec<PoolMemberConfig>,
+    /// Probe every member; unhealthy members are skipped.
+    pub health_check: Option<HealthCheckConfig>,
+    /// One breaker per member; members with an open breaker are skipped.
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum PoolMemberConfig {
+    Url(String),
+    Weighted {
+        url: String,
+        #[serde(default = "default_pool_weight")]
+        weight: u32,
+    },
+}
+
+impl PoolMemberConfig {
+    pub fn url(&self) -> &str {
+        match self {
+            PoolMemberConfig::Url(url) | PoolMemberConfig::Weighted { url, .. } => url,
+        }
+    }
+
+    pub fn weight(&self) -> u32 {
+        match self {
+            PoolMemberConfig::Url(_) => default_pool_weight(),
+            PoolMemberConfig::Weighted { weight, .. } => *weight,
+        }
+    }
+}
+
+/// How a pool picks the member for a new connection.
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PoolStrategy {
+    #[default]
+    RoundRobin,
+    LeastConnections,
+    /// Random, proportional to `weight`.
+    Random,
+    /// Smooth weighted round-robin.
+    Weighted,
+}
+
+pub(super) fn default_pool_weight() -> u32 {
+    1
+}
+
+// ---------------------------------------------------------------------------
+// HealthCheckConfig — active probing of proxy upstreams
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct HealthCheckConfig {
+    #[serde(default = "default_health_interval", with = "humantime_serde")]
+    pub interval: Duration,
+    #[serde(default = "default_health_timeout", with = "humantime_serde")]
+    pub timeout: Duration,
+    /// Consecutive successful probes before an unhealthy upstream is used again.
+    #[serde(default = "default_health_rise")]
+    pub rise: u32,
+    /// Consecutive failed probes before an upstream is marked unhealthy.
+    #[serde(default = "default_health_fall")]
+    pub fall: u32,
+    /// Tunnel to this host:port through the upstream (HTTP CONNECT, or SOCKS5
+    /// for `socks5://`) instead of only opening a TCP connection.
+    #[serde(default)]
+    pub connect_target: Option<String>,
+    /// Headers for the CONNECT probe, e.g. `Proxy-Authorization`.
+    #[serde(default)]
+    pub headers: Arc<HashMap<String, String>>,
+}
+
+pub(super) fn default_health_interval() -> Duration {
+    Duration::from_secs(10)
+}
+
+pub(super) fn default_health_timeout() -> Duration {
+    Duration::from_secs(3)
+}
+
+pub(super) fn default_health_rise() -> u32 {
+    2
+}
+
+pub(super) fn default_health_fall() -> u32 {
+    3
+}
+
+// ---------------------------------------------------------------------------
+// CircuitBreakerConfig — stop connecting to an upstream that keeps failing
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CircuitBreakerConfig {
+    /// Open after this many consecutive failed connects. 0 = disabled.
+    #[serde(default = "default_breaker_consecutive_failures")]
+    pub consecutive_failures: u32,
+    /// Open when at least this share (0.0–1.0] of the last `window` connects
+    /// failed. Unset = disabled.
+    #[serde(default)]
+    pub error_rate: Option<f64>,
+    /// Number of recent connects `error_rate` is computed over.
+    #[serde(default = "default_breaker_window")]
+    pub window: u32,
+    /// How long an open breaker rejects connects before letting one trial
+    /// connect through (half-open).
+    #[serde(default = "default_breaker_cooldown", with = "humantime_serde")]
+    pub cooldown: Duration,
+}
+
+pub(super) fn default_breaker_consecutive_failures() -> u32 {
+    5
+}
+
+pub(super) fn default_breaker_window() -> u32 {
+    20
+}
+
+pub(super) fn default_breaker_cooldown() -> Duration {
+    Duration::from_secs(30)
+}
+
 // ---------------------------------------------------------------------------
 // ViaUpstream — HTTP CONNECT proxy settings
 // ---------------------------------------------------------------------------
 
-#[derive(Debug, Default, Deserialize, Clone)]
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
 pub struct ViaUpstream {
     #[serde(default)]
     pub headers: Arc<HashMap<String, String>>,
@@ -52,6 +407,10 @@ pub struct ViaUpstream {
     pub target: String,
     #[serde(default = "default_connect_timeout", with = "humantime_serde")]
     pub connect_timeout: Duration,
+    /// Happy Eyeballs (RFC 8305) delay before racing the next resolved
+    /// address of the upstream. `Duration::ZERO` = try them one at a time.
+    #[serde(default = "default_happy_eyeballs_delay", with = "humantime_serde")]
+    pub happy_eyeballs_delay: Duration,
     /// Derive the CONNECT target dynamically from the TLS SNI instead of `target`.
     #[serde(default)]
     pub use_sni_as_target: bool,
@@ -61,12 +420,20 @@ pub struct ViaUpstream {
     /// How often to log in-flight rx/tx byte counters. `Duration::ZERO` = disabled.
     #[serde(default, with = "humantime_serde")]
     pub stats_interval: Duration,
+    /// Send a PROXY protocol header with the client address to the upstream
+    /// (after CONNECT, if any) before relaying.
+    #[serde(default)]
+    pub proxy_protocol: Option<ProxyProtocolVersion>,
 }
 
 pub(super) fn default_connect_timeout() -> Duration {
     Duration::from_secs(30)
 }
 
+pub(super) fn default_happy_eyeballs_delay() -> Duration {
+    Duration::from_millis(250)
+}
+
 fn default_target_port() -> u16 {
     443
 }
@@ -85,25 +452,50 @@ fn default_target_port() -> u16 {
 ///     via:
 ///       use_sni_as_target: true
 ///       target_port: 443
+///   www.corp.org:                              # first entry whose ALPN matches
+///     - upstream: acme_responder
+///       alpn: [acme-tls/1]
+///     - web_server
+///   api.corp.org:
+///     upstream: [corp_proxy_a, corp_proxy_b, direct]   # tried in order
 /// ```
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(untagged)]
 pub enum SniTarget {
     /// Just an upstream name — inherits server-level `via`.
     Simple(String),
     /// Upstream name plus an optional per-SNI `via` override.
     Extended {
-        upstream: String,
+        upstream: UpstreamRef,
         #[serde(default)]
         via: Option<ViaUpstream>,
+        /// Only match if the client offers one of these ALPN protocols.
+        /// Empty = match regardless of ALPN.
+        #[serde(default)]
+        alpn: Vec<String>,
     },
+    /// Ordered alternatives for one SNI, typically distinguished by `alpn`.
+    Alternatives(Vec<SniTarget>),
 }
 
 impl SniTarget {
+    /// Upstream name of this target; for `Alternatives` the first entry's.
     pub fn upstream_name(&self) -> &str {
         match self {
             SniTarget::Simple(name) => name,
-            SniTarget::Extended { upstream, .. } => upstream,
+            SniTarget::Extended { upstream, .. } => upstream.primary(),
+            SniTarget::Alternatives(targets) => {
+                targets.first().map(|t| t.upstream_name()).unwrap_or("")
+            }
+        }
+    }
+
+    /// Upstreams to try, in order, when `upstream_name` cannot be reached.
+    pub fn fallbacks(&self) -> &[String] {
+        match self {
+            SniTarget::Simple(_) => &[],
+            SniTarget::Extended { upstream, .. } => upstream.fallbacks(),
+            SniTarget::Alternatives(targets) => targets.first().map_or(&[], |t| t.fallbacks()),
         }
     }
 
@@ -111,25 +503,218 @@ impl SniTarget {
         match self {
             SniTarget::Simple(_) => None,
             SniTarget::Extended { via, .. } => via.as_ref(),
+            SniTarget::Alternatives(targets) => targets.first().and_then(|t| t.via_override()),
+        }
+    }
+
+    /// Resolve this target against the ALPN protocols offered by the client.
+    /// Returns `None` if no entry accepts them.
+    pub fn select(&self, offered_alpn: &[String]) -> Option<&SniTarget> {
+        match self {
+            SniTarget::Simple(_) => Some(self),
+            SniTarget::Extended { alpn, .. } => alpn_matches(alpn, offered_alpn).then_some(self),
+            SniTarget::Alternatives(targets) => targets.iter().find_map(|t| t.select(offered_alpn)),
+        }
+    }
+
+    /// All upstream names this target can route to.
+    pub fn upstream_names(&self) -> Vec<&str> {
+        match self {
+            SniTarget::Alternatives(targets) => {
+                targets.iter().flat_map(|t| t.upstream_names()).collect()
+            }
+            SniTarget::Simple(name) => vec![name],
+            SniTarget::Extended { upstream, .. } => upstream.names(),
+        }
+    }
+}
+
+/// `upstream:` of an SNI route: one name, or an ordered list whose later
+/// entries are only used when the earlier ones cannot be reached.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum UpstreamRef {
+    Name(String),
+    Fallbacks(Vec<String>),
+}
+
+impl UpstreamRef {
+    pub fn primary(&self) -> &str {
+        match self {
+            UpstreamRef::Name(name) => name,
+            UpstreamRef::Fallbacks(names) => names.first().map_or("", String::as_str),
+        }
+    }
+
+    pub fn fallbacks(&self) -> &[String] {
+        match self {
+            UpstreamRef::Name(_) => &[],
+            UpstreamRef::Fallbacks(names) => names.get(1..).unwrap_or_default(),
+        }
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        match self {
+            UpstreamRef::Name(name) => vec![name],
+            UpstreamRef::Fallbacks(names) => names.iter().map(String::as_str).collect(),
         }
     }
 }
 
+/// An empty `alpn` condition always matches; otherwise the client must
+/// offer at least one of the listed protocols.
+fn alpn_matches(wanted: &[String], offered: &[String]) -> bool {
+    wanted.is_empty() || wanted.iter().any(|w| offered.contains(w))
+}
+
+// ---------------------------------------------------------------------------
+// SniRule — ordered regex routing entry
+// ---------------------------------------------------------------------------
+
+/// Regex-based SNI route, evaluated in order after the `sni:` map.
+///
+/// The pattern must match the whole SNI. Capture groups can be referenced
+/// in `via.target` as `$1`, `${1}` or `${name}`:
+///
+/// ```yaml
+/// sni_rules:
+///   - match: '(?<svc>[a-z0-9-]+)\.ext\.corp\.org'
+///     upstream: corp_proxy                 # or a fallback list: [corp_proxy, direct]
+///     via:
+///       target: "${svc}.internal:8443"
+/// ```
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SniRule {
+    #[serde(
+        rename = "match",
+        deserialize_with = "deserialize_anchored_regex",
+        serialize_with = "serialize_anchored_regex"
+    )]
+    pub pattern: Regex,
+    pub upstream: UpstreamRef,
+    #[serde(default)]
+    pub via: Option<ViaUpstream>,
+    /// Only match if the client offers one of these ALPN protocols.
+    #[serde(default)]
+    pub alpn: Vec<String>,
+}
+
+impl SniRule {
+    /// The `match` pattern as configured, without the added anchors.
+    pub fn source(&self) -> &str {
+        unanchored(&self.pattern)
+    }
+
+    pub fn accepts_alpn(&self, offered_alpn: &[String]) -> bool {
+        alpn_matches(&self.alpn, offered_alpn)
+    }
+}
+
+fn deserialize_anchored_regex<'de, D>(deserializer: D) -> Result<Regex, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let pattern = String::deserialize(deserializer)?;
+    Regex::new(&format!("^(?:{})$", pattern)).map_err(serde::de::Error::custom)
+}
+
+fn serialize_anchored_regex<S: Serializer>(
+    pattern: &Regex,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(unanchored(pattern))
+}
+
+/// The pattern of a regex built by `deserialize_anchored_regex`.
+fn unanchored(pattern: &Regex) -> &str {
+    let anchored = pattern.as_str();
+    &anchored["^(?:".len()..anchored.len() - ")$".len()]
+}
+
+// ---------------------------------------------------------------------------
+// ClientHelloConfig — limits for peeking at the TLS ClientHello
+// ---------------------------------------------------------------------------
+
+/// ClientHello read limits for `tls: true` servers, flattened into the
+/// server block.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ClientHelloConfig {
+    /// Maximum number of bytes buffered while reassembling a ClientHello
+    /// that spans several reads or TLS records.
+    #[serde(default = "default_client_hello_max_size")]
+    pub client_hello_max_size: usize,
+    /// How long to wait for the rest of a ClientHello once its first bytes
+    /// have arrived.
+    #[serde(
+        default = "default_client_hello_reassembly_timeout",
+        with = "humantime_serde"
+    )]
+    pub client_hello_reassembly_timeout: Duration,
+    /// Overall deadline for receiving a ClientHello after accept.
+    /// `Duration::ZERO` = no deadline.
+    #[serde(default = "default_client_hello_timeout", with = "humantime_serde")]
+    pub client_hello_timeout: Duration,
+    /// What to do with a connection that hits `client_hello_timeout`.
+    #[serde(default)]
+    pub client_hello_timeout_action: ClientHelloTimeoutAction,
+}
+
+impl Default for ClientHelloConfig {
+    fn default() -> Self {
+        ClientHelloConfig {
+            client_hello_max_size: default_client_hello_max_size(),
+            client_hello_reassembly_timeout: default_client_hello_reassembly_timeout(),
+            client_hello_timeout: default_client_hello_timeout(),
+            client_hello_timeout_action: ClientHelloTimeoutAction::default(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientHelloTimeoutAction {
+    /// Drop the connection.
+    #[default]
+    Close,
+    /// Route the connection to the server's `default` upstream.
+    Default,
+}
+
+pub(super) fn default_client_hello_max_size() -> usize {
+    16 * 1024
+}
+
+pub(super) fn default_client_hello_reassembly_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+pub(super) fn default_client_hello_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
 // ---------------------------------------------------------------------------
 // ServerConfig
 // ---------------------------------------------------------------------------
 
-#[derive(Debug, Default, Deserialize, Clone)]
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
 pub struct ServerConfig {
     pub listen: Vec<String>,
     pub protocol: Option<String>,
     pub tls: Option<bool>,
     pub sni: Option<HashMap<String, SniTarget>>,
+    #[serde(default)]
+    pub sni_rules: Vec<SniRule>,
     pub default: Option<String>,
     #[serde(default)]
     pub via: ViaUpstream,
     #[serde(default = "default_maxclients")]
     pub maxclients: usize,
+    /// Expect a PROXY protocol v1/v2 header from a load balancer in front of
+    /// this listener and use the client address it carries.
+    #[serde(default)]
+    pub accept_proxy_protocol: bool,
+    #[serde(flatten)]
+    pub client_hello: ClientHelloConfig,
 }
 
 pub(super) fn default_maxclients() -> usize {
@@ -149,8 +734,23 @@ mod tests {
         assert_eq!(default_connect_timeout(), Duration::from_secs(30));
     }
 
+    #[test]
+    fn test_default_happy_eyeballs_delay() {
+        assert_eq!(default_happy_eyeballs_delay(), Duration::from_millis(250));
+    }
+
     #[test]
     fn test_default_maxclients() {
         assert_eq!(default_maxclients(), 100);
     }
+
+    #[test]
+    fn test_default_client_hello_limits() {
+        let limits = ClientHelloConfig::default();
+        assert_eq!(limits.client_hello_max_size, 16384);
+        assert_eq!(
+            limits.client_hello_reassembly_timeout,
+            Duration::from_secs(5)
+        );
+    }
 }
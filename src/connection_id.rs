@@ -0,0 +1,32 @@
+use std::future::Future;
+
+use crate::access_log::ConnectionId;
+
+// ---------------------------------------------------------------------------
+// Connection IDs
+//
+// `tcp::proxy` gives every accepted connection a random `ConnectionId`. It is
+// carried in `ConnectionContext::id`, names the connection's access log
+// record and is the current ID of the task serving the connection, which is how both log
+// formatters built in `load_config` add it to every line logged on the
+// connection's behalf. Tasks spawned for a connection set it again with
+// `scope`.
+// ---------------------------------------------------------------------------
+
+tokio::task_local! {
+    static CURRENT: ConnectionId;
+}
+
+/// Run `future` with `id` as the current connection ID.
+pub(crate) async fn scope<F: Future>(id: ConnectionId, future: F) -> F::Output {
+    CURRENT.scope(id, future).await
+}
+
+/// ID of the connection the calling task serves, if any.
+pub(crate) fn current() -> Option<ConnectionId> {
+    CURRENT.try_with(|id| *id).ok()
+}
+
+#[cfg(test)]
+#[path = "connection_id_tests.rs"]
+mod tests;
@@ -0,0 +1,15 @@
+use super::*;
+use crate::access_log::ConnectionId;
+
+#[tokio::test]
+async fn test_current_inside_scope_only() {
+    assert_eq!(current(), None);
+    let id = ConnectionId::new();
+    let seen = scope(id, async {
+        tokio::task::yield_now().await;
+        current()
+    })
+    .await;
+    assert_eq!(seen, Some(id));
+    assert_eq!(current(), None);
+}
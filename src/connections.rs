@@ -0,0 +1,206 @@
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use crate::access_log::ConnectionId;
+use crate::access_log::{AccessRecord, TunnelInfo};
+
+// ---------------------------------------------------------------------------
+// Connection registry
+//
+// `tcp::accept` registers every proxied connection for as long as it is open,
+// so the admin API on the `health` upstream can list the open tunnels, kill
+// one, and drain a server: a draining server closes new connections right
+// after accept while its open tunnels run to completion. Health server
+// connections are not registered and never drained. The registry is
+// process-wide and survives reloads; so does draining, for the servers the
+// reload keeps.
+// ---------------------------------------------------------------------------
+
+/// Where a connection is going, as far as it is known yet.
+#[derive(Debug, Default, Clone)]
+struct Destination {
+    client: Option<SocketAddr>,
+    sni: Option<String>,
+    route: Option<String>,
+    upstream: Option<String>,
+    target: Option<String>,
+}
+
+/// One open connection.
+#[derive(Debug)]
+pub struct Connection {
+    pub id: ConnectionId,
+    pub server: String,
+    started: Instant,
+    destination: Mutex<Destination>,
+    /// Filled in by the upstream side; see `ConnectionContext::tunnel`.
+    pub tunnel: Arc<TunnelInfo>,
+    kill: CancellationToken,
+}
+
+/// What the admin API reports about an open connection.
+#[derive(Debug, Serialize)]
+pub struct ConnectionSnapshot {
+    pub id: ConnectionId,
+    pub server: String,
+    pub client: Option<SocketAddr>,
+    pub sni: Option<String>,
+    pub route: Option<String>,
+    pub upstream: Option<String>,
+    pub upstream_addr: Option<String>,
+    pub target: Option<String>,
+    pub bytes_tx: u64,
+    pub bytes_rx: u64,
+    pub age_ms: u128,
+}
+
+impl Connection {
+    pub fn new(id: ConnectionId, server: &str) -> Self {
+        Connection {
+            id,
+            server: server.to_string(),
+            started: Instant::now(),
+            destination: Mutex::default(),
+            tunnel: Arc::default(),
+            kill: CancellationToken::new(),
+        }
+    }
+
+    fn destination(&self) -> MutexGuard<'_, Destination> {
+        self.destination.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Take over what `record` knows about the client and its route.
+    pub fn describe(&self, record: &AccessRecord) {
+        *self.destination() = Destination {
+            client: record.client,
+            sni: record.sni.clone(),
+            route: record.route.clone(),
+            upstream: record.upstream.clone(),
+            target: record.target.clone(),
+        };
+    }
+
+    /// Ask the task serving the connection to close it.
+    pub fn kill(&self) {
+        self.kill.cancel();
+    }
+
+    /// Resolves once `kill` was called.
+    pub async fn killed(&self) {
+        self.kill.cancelled().await
+    }
+
+    pub fn snapshot(&self) -> ConnectionSnapshot {
+        let destination = self.destination().clone();
+        let tunnel = self.tunnel.get();
+        ConnectionSnapshot {
+            id: self.id,
+            server: self.server.clone(),
+            client: destination.client,
+            sni: destination.sni,
+            route: destination.route,
+            upstream: tunnel.upstream.or(destination.upstream),
+            upstream_addr: tunnel.upstream_addr,
+            target: destination.target,
+            bytes_tx: tunnel.bytes_tx,
+            bytes_rx: tunnel.bytes_rx,
+            age_ms: self.started.elapsed().as_millis(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Registry {
+    connections: Mutex<HashMap<ConnectionId, Arc<Connection>>>,
+    draining: Mutex<HashSet<String>>,
+}
+
+/// Keeps a connection registered; it is removed when this is dropped.
+pub struct Registration<'a> {
+    registry: &'a Registry,
+    id: ConnectionId,
+}
+
+impl Drop for Registration<'_> {
+    fn drop(&mut self) {
+        self.registry.connections().remove(&self.id);
+    }
+}
+
+impl Registry {
+    fn connections(&self) -> MutexGuard<'_, HashMap<ConnectionId, Arc<Connection>>> {
+        self.connections.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn draining(&self) -> MutexGuard<'_, HashSet<String>> {
+        self.draining.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn register(&self, connection: Arc<Connection>) -> Registration<'_> {
+        let id = connection.id;
+        self.connections().insert(id, connection);
+        Registration { registry: self, id }
+    }
+
+    /// All open connections, oldest first.
+    pub fn list(&self) -> Vec<ConnectionSnapshot> {
+        let mut connections: Vec<Arc<Connection>> = self.connections().values().cloned().collect();
+        connections.sort_by_key(|c| c.started);
+        connections.iter().map(|c| c.snapshot()).collect()
+    }
+
+    /// Kill the connection `id`. False if there is no such connection.
+    pub fn kill(&self, id: ConnectionId) -> bool {
+        match self.connections().get(&id) {
+            Some(connection) => {
+                connection.kill();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Number of open connections on `server`.
+    pub fn active(&self, server: &str) -> usize {
+        self.connections()
+            .values()
+            .filter(|c| c.server == server)
+            .count()
+    }
+
+    /// Start or stop draining `server`.
+    pub fn set_draining(&self, server: &str, draining: bool) {
+        if draining {
+            self.draining().insert(server.to_string());
+        } else {
+            self.draining().remove(server);
+        }
+    }
+
+    pub fn is_draining(&self, server: &str) -> bool {
+        self.draining().contains(server)
+    }
+
+    /// Forget draining for `servers`, e.g. ones a reload removed or renamed.
+    pub fn forget_draining<'a>(&self, servers: impl IntoIterator<Item = &'a str>) {
+        let mut draining = self.draining();
+        for server in servers {
+            draining.remove(server);
+        }
+    }
+}
+
+/// The process-wide registry.
+pub(crate) fn registry() -> &'static Registry {
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+    REGISTRY.get_or_init(Registry::default)
+}
+
+#[cfg(test)]
+#[path = "connections_tests.rs"]
+mod tests;
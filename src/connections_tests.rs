@@ -0,0 +1,83 @@
+use super::*;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+fn connection(server: &str) -> Arc<Connection> {
+    Arc::new(Connection::new(ConnectionId::new(), server))
+}
+
+#[test]
+fn test_register_lists_until_dropped() {
+    let registry = Registry::default();
+    let first = connection("https_server");
+    let second = connection("socks_server");
+    let registered = registry.register(first.clone());
+    std::thread::sleep(Duration::from_millis(2));
+    {
+        let _registered = registry.register(second.clone());
+        let ids: Vec<ConnectionId> = registry.list().iter().map(|c| c.id).collect();
+        assert_eq!(ids, vec![first.id, second.id]);
+        assert_eq!(registry.active("socks_server"), 1);
+    }
+    assert_eq!(registry.active("socks_server"), 0);
+    drop(registered);
+    assert!(registry.list().is_empty());
+}
+
+#[test]
+fn test_snapshot_prefers_upstream_that_carried_the_tunnel() {
+    let conn = connection("https_server");
+    let mut record = AccessRecord::new(conn.id, "https_server");
+    record.client = Some("192.0.2.1:50000".parse().unwrap());
+    record.sni = Some("www.example.com".to_string());
+    record.upstream = Some("corp".to_string());
+    conn.describe(&record);
+
+    let snapshot = conn.snapshot();
+    assert_eq!(snapshot.upstream.as_deref(), Some("corp"));
+    assert_eq!(snapshot.sni.as_deref(), Some("www.example.com"));
+
+    conn.tunnel.update(|t| {
+        t.upstream = Some("corp_backup".to_string());
+        t.upstream_addr = Some("10.0.0.1:3128".to_string());
+    });
+    conn.tunnel
+        .byte_counters()
+        .1
+        .fetch_add(42, Ordering::Relaxed);
+    let snapshot = serde_json::to_value(conn.snapshot()).unwrap();
+    assert_eq!(snapshot["upstream"], "corp_backup");
+    assert_eq!(snapshot["upstream_addr"], "10.0.0.1:3128");
+    assert_eq!(snapshot["client"], "192.0.2.1:50000");
+    assert_eq!(snapshot["bytes_rx"], 42);
+    assert_eq!(snapshot["id"], conn.id.to_string());
+}
+
+#[tokio::test]
+async fn test_kill_wakes_registered_connection() {
+    let registry = Registry::default();
+    let conn = connection("https_server");
+    let _registered = registry.register(conn.clone());
+
+    assert!(!registry.kill(ConnectionId::new()));
+    assert!(registry.kill(conn.id));
+    tokio::time::timeout(Duration::from_secs(1), conn.killed())
+        .await
+        .unwrap();
+}
+
+#[test]
+fn test_set_draining() {
+    let registry = Registry::default();
+    registry.set_draining("https_server", true);
+    assert!(registry.is_draining("https_server"));
+    assert!(!registry.is_draining("socks_server"));
+    registry.set_draining("https_server", false);
+    assert!(!registry.is_draining("https_server"));
+
+    registry.set_draining("https_server", true);
+    registry.set_draining("old_name", true);
+    registry.forget_draining(["old_name", "socks_server"]);
+    assert!(registry.is_draining("https_server"));
+    assert!(!registry.is_draining("old_name"));
+}
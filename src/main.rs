@@ -1,5 +1,12 @@
+mod access_log;
+mod check;
 mod config;
+mod connection_id;
+mod connections;
+mod metrics;
+mod proxy_protocol;
 mod servers;
+mod telemetry;
 mod upstreams;
 
 use mimalloc::MiMalloc;
@@ -25,6 +32,8 @@ fn print_help() {
              \n\
              OPTIONS:\n\
              \t-c, --config <path>    Path to config file\n\
+             \t    --check            Validate the config, list all errors and exit\n\
+             \t    --resolve          With --check, also resolve upstream hosts\n\
              \t-h, --help             Show this help\n\
              \n\
              CONFIG SEARCH ORDER (when --config is not given):\n\
@@ -39,22 +48,42 @@ fn print_help() {
 #[derive(Debug)]
 enum Cli {
     Help,
-    Run { config_path: Option<String> },
+    Run {
+        config_path: Option<String>,
+    },
+    /// Validate the config and exit; see `check::check_config`.
+    Check {
+        config_path: Option<String>,
+        resolve: bool,
+    },
 }
 
 fn parse_args(args: &[String]) -> Result<Cli, String> {
     if args.iter().any(|a| a == "--help" || a == "-h") {
         return Ok(Cli::Help);
     }
-    match args.first().map(String::as_str) {
-        Some("--config" | "-c") => match args.get(1) {
-            Some(path) => Ok(Cli::Run {
-                config_path: Some(path.clone()),
-            }),
-            None => Err("--config requires a path argument".to_string()),
-        },
-        Some(other) => Err(format!("Unknown argument: {other}")),
-        None => Ok(Cli::Run { config_path: None }),
+    let mut config_path = None;
+    let mut check = false;
+    let mut resolve = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" | "-c" => match args.next() {
+                Some(path) => config_path = Some(path.clone()),
+                None => return Err("--config requires a path argument".to_string()),
+            },
+            "--check" | "validate" => check = true,
+            "--resolve" => resolve = true,
+            other => return Err(format!("Unknown argument: {other}")),
+        }
+    }
+    match (check, resolve) {
+        (true, _) => Ok(Cli::Check {
+            config_path,
+            resolve,
+        }),
+        (false, true) => Err("--resolve can only be used with --check".to_string()),
+        (false, false) => Ok(Cli::Run { config_path }),
     }
 }
 
@@ -92,17 +121,23 @@ fn run(args: &[String]) -> Result<(), u8> {
         }
     };
 
-    let config_path = match cli {
+    let (config_path, check) = match cli {
         Cli::Help => {
             print_help();
             return Ok(());
         }
-        Cli::Run {
-            config_path: Some(p),
-        } => p,
-        Cli::Run { config_path: None } => match find_config() {
+        Cli::Run { config_path } => (config_path, None),
+        Cli::Check {
+            config_path,
+            resolve,
+        } => (config_path, Some(resolve)),
+    };
+
+    let config_path = match config_path {
+        Some(p) => p,
+        None => match find_config() {
             Ok(p) => p,
-            Err(_) if args.is_empty() => {
+            Err(_) if check.is_none() && args.is_empty() => {
                 print_help();
                 return Ok(());
             }
@@ -116,6 +151,19 @@ fn run(args: &[String]) -> Result<(), u8> {
         },
     };
 
+    if let Some(resolve) = check {
+        let errors = check::check_config(&config_path, resolve);
+        if errors.is_empty() {
+            println!("{}: configuration OK", config_path);
+            return Ok(());
+        }
+        eprintln!("{}: {} error(s):", config_path, errors.len());
+        for e in errors {
+            eprintln!("  {}", e);
+        }
+        return Err(1);
+    }
+
     let config = match Config::new(&config_path) {
         Ok(config) => config,
         Err(e) => {
@@ -126,7 +174,7 @@ fn run(args: &[String]) -> Result<(), u8> {
 
     debug!("{:?}", config);
 
-    let mut server = Server::from(config.base);
+    let mut server = Server::from(config.base).with_config_path(config_path);
     info!("{:?}", server);
 
     if let Err(e) = server.run() {
@@ -0,0 +1,282 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+// ---------------------------------------------------------------------------
+// Traffic metrics registry
+//
+// Counters and histograms fed from the connection path — accept, upstream
+// connect, CONNECT handshake and relay — and rendered in the Prometheus text
+// format after the gauges on the `health` upstream's `/metrics`.
+//
+// Series are labelled by server and, once a connection is routed, by route
+// (the matched `sni` key or `sni_rules` pattern, `default` otherwise) and
+// upstream name. All of these come from the config, so the number of series
+// is bounded. The registry is process-wide and survives reloads.
+// ---------------------------------------------------------------------------
+
+/// Route label for connections that matched no SNI entry or rule.
+pub(crate) const DEFAULT_ROUTE: &str = "default";
+
+/// Upper bounds of the upstream connect latency buckets, in seconds.
+const CONNECT_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Upper bounds of the tunnel duration buckets, in seconds.
+const TUNNEL_BUCKETS: &[f64] = &[0.1, 1.0, 10.0, 60.0, 300.0, 900.0, 3600.0, 14400.0];
+
+/// Where a tunnel's traffic is accounted.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TunnelLabels {
+    pub server: String,
+    pub route: String,
+    pub upstream: String,
+}
+
+impl TunnelLabels {
+    fn write(&self, out: &mut String) {
+        write!(
+            out,
+            r#"server="{}",route="{}",upstream="{}""#,
+            escape(&self.server),
+            escape(&self.route),
+            escape(&self.upstream)
+        )
+        .unwrap();
+    }
+}
+
+/// Label values may contain anything a route pattern can.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n")
+}
+
+#[derive(Debug, Clone)]
+struct Histogram {
+    bounds: &'static [f64],
+    /// Non-cumulative count per bucket; the last one is `+Inf`.
+    counts: Vec<u64>,
+    sum: f64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Histogram {
+            bounds,
+            counts: vec![0; bounds.len() + 1],
+            sum: 0.0,
+        }
+    }
+
+    fn observe(&mut self, value: Duration) {
+        let secs = value.as_secs_f64();
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|b| secs <= *b)
+            .unwrap_or(self.bounds.len());
+        self.counts[bucket] += 1;
+        self.sum += secs;
+    }
+
+    fn write(&self, out: &mut String, metric: &str, labels: &TunnelLabels) {
+        let mut cumulative = 0;
+        let les = self
+            .bounds
+            .iter()
+            .map(|b| b.to_string())
+            .chain(["+Inf".into()]);
+        for (le, count) in les.zip(&self.counts) {
+            cumulative += count;
+            write!(out, "{}_bucket{{", metric).unwrap();
+            labels.write(out);
+            writeln!(out, r#",le="{}"}} {}"#, le, cumulative).unwrap();
+        }
+        for (suffix, value) in [
+            ("sum", self.sum.to_string()),
+            ("count", cumulative.to_string()),
+        ] {
+            write!(out, "{}_{}{{", metric, suffix).unwrap();
+            labels.write(out);
+            writeln!(out, "}} {}", value).unwrap();
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Series {
+    connections: BTreeMap<String, u64>,
+    rejections: BTreeMap<String, u64>,
+    /// Shared with open relays so the byte counts move while tunnels run.
+    bytes_tx: BTreeMap<TunnelLabels, Arc<AtomicU64>>,
+    bytes_rx: BTreeMap<TunnelLabels, Arc<AtomicU64>>,
+    connect_statuses: BTreeMap<(TunnelLabels, u16), u64>,
+    connect_durations: BTreeMap<TunnelLabels, Histogram>,
+    tunnel_durations: BTreeMap<TunnelLabels, Histogram>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Registry {
+    series: Mutex<Series>,
+}
+
+/// The process-wide registry.
+pub(crate) fn registry() -> &'static Registry {
+    static REGISTRY: std::sync::OnceLock<Registry> = std::sync::OnceLock::new();
+    REGISTRY.get_or_init(Registry::default)
+}
+
+impl Registry {
+    fn series(&self) -> MutexGuard<'_, Series> {
+        self.series.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// A connection was accepted on `server` and holds a `maxclients` permit.
+    pub fn connection_accepted(&self, server: &str) {
+        *self
+            .series()
+            .connections
+            .entry(server.to_string())
+            .or_default() += 1;
+    }
+
+    /// A connection was closed because `server` was at `maxclients`.
+    pub fn connection_rejected(&self, server: &str) {
+        *self
+            .series()
+            .rejections
+            .entry(server.to_string())
+            .or_default() += 1;
+    }
+
+    /// Counters for bytes sent to (`tx`) and received from (`rx`) the upstream.
+    pub fn byte_counters(&self, labels: &TunnelLabels) -> (Arc<AtomicU64>, Arc<AtomicU64>) {
+        let mut series = self.series();
+        let tx = series.bytes_tx.entry(labels.clone()).or_default().clone();
+        let rx = series.bytes_rx.entry(labels.clone()).or_default().clone();
+        (tx, rx)
+    }
+
+    /// An upstream HTTP proxy answered a CONNECT with `status`.
+    pub fn connect_status(&self, labels: &TunnelLabels, status: u16) {
+        *self
+            .series()
+            .connect_statuses
+            .entry((labels.clone(), status))
+            .or_default() += 1;
+    }
+
+    /// A TCP connection to the upstream was established after `elapsed`.
+    pub fn connect_duration(&self, labels: &TunnelLabels, elapsed: Duration) {
+        self.series()
+            .connect_durations
+            .entry(labels.clone())
+            .or_insert_with(|| Histogram::new(CONNECT_BUCKETS))
+            .observe(elapsed);
+    }
+
+    /// A tunnel closed after relaying for `elapsed`.
+    pub fn tunnel_duration(&self, labels: &TunnelLabels, elapsed: Duration) {
+        self.series()
+            .tunnel_durations
+            .entry(labels.clone())
+            .or_insert_with(|| Histogram::new(TUNNEL_BUCKETS))
+            .observe(elapsed);
+    }
+
+    /// Append every series in the Prometheus text format.
+    pub fn write(&self, out: &mut String) {
+        let series = self.series();
+
+        for (metric, help, values) in [
+            (
+                "tpt_connections_total",
+                "Connections accepted",
+                &series.connections,
+            ),
+            (
+                "tpt_maxclients_rejections_total",
+                "Connections closed because the server was at maxclients",
+                &series.rejections,
+            ),
+        ] {
+            header(out, metric, help, "counter");
+            for (server, value) in values {
+                writeln!(
+                    out,
+                    r#"{}{{server="{}"}} {}"#,
+                    metric,
+                    escape(server),
+                    value
+                )
+                .unwrap();
+            }
+        }
+
+        for (metric, help, values) in [
+            (
+                "tpt_bytes_sent_total",
+                "Bytes relayed from clients to upstreams",
+                &series.bytes_tx,
+            ),
+            (
+                "tpt_bytes_received_total",
+                "Bytes relayed from upstreams to clients",
+                &series.bytes_rx,
+            ),
+        ] {
+            header(out, metric, help, "counter");
+            for (labels, value) in values {
+                write!(out, "{}{{", metric).unwrap();
+                labels.write(out);
+                writeln!(out, "}} {}", value.load(Ordering::Relaxed)).unwrap();
+            }
+        }
+
+        let metric = "tpt_upstream_connect_responses_total";
+        header(
+            out,
+            metric,
+            "CONNECT responses from upstream proxies by status code",
+            "counter",
+        );
+        for ((labels, status), value) in &series.connect_statuses {
+            write!(out, "{}{{", metric).unwrap();
+            labels.write(out);
+            writeln!(out, r#",status="{}"}} {}"#, status, value).unwrap();
+        }
+
+        for (metric, help, values) in [
+            (
+                "tpt_upstream_connect_duration_seconds",
+                "Time to establish the TCP connection to the upstream",
+                &series.connect_durations,
+            ),
+            (
+                "tpt_tunnel_duration_seconds",
+                "How long tunnels relayed data",
+                &series.tunnel_durations,
+            ),
+        ] {
+            header(out, metric, help, "histogram");
+            for (labels, histogram) in values {
+                histogram.write(out, metric, labels);
+            }
+        }
+    }
+}
+
+fn header(out: &mut String, metric: &str, help: &str, kind: &str) {
+    writeln!(out, "# HELP {} {}", metric, help).unwrap();
+    writeln!(out, "# TYPE {} {}", metric, kind).unwrap();
+}
+
+#[cfg(test)]
+#[path = "metrics_tests.rs"]
+mod tests;
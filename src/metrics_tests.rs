@@ -0,0 +1,88 @@
+use super::*;
+
+fn labels(route: &str) -> TunnelLabels {
+    TunnelLabels {
+        server: "web".to_string(),
+        route: route.to_string(),
+        upstream: "corp".to_string(),
+    }
+}
+
+fn render(registry: &Registry) -> String {
+    let mut out = String::new();
+    registry.write(&mut out);
+    out
+}
+
+#[test]
+fn test_counters_by_server() {
+    let registry = Registry::default();
+    registry.connection_accepted("web");
+    registry.connection_accepted("web");
+    registry.connection_rejected("web");
+
+    let out = render(&registry);
+    assert!(out.contains("# TYPE tpt_connections_total counter"));
+    assert!(out.contains(r#"tpt_connections_total{server="web"} 2"#));
+    assert!(out.contains(r#"tpt_maxclients_rejections_total{server="web"} 1"#));
+}
+
+#[test]
+fn test_byte_counters_are_shared_per_labels() {
+    let registry = Registry::default();
+    let (tx, rx) = registry.byte_counters(&labels("default"));
+    tx.fetch_add(10, Ordering::Relaxed);
+    rx.fetch_add(3, Ordering::Relaxed);
+    let (tx, _) = registry.byte_counters(&labels("default"));
+    tx.fetch_add(5, Ordering::Relaxed);
+
+    let out = render(&registry);
+    assert!(
+        out.contains(r#"tpt_bytes_sent_total{server="web",route="default",upstream="corp"} 15"#)
+    );
+    assert!(
+        out.contains(r#"tpt_bytes_received_total{server="web",route="default",upstream="corp"} 3"#)
+    );
+}
+
+#[test]
+fn test_connect_status_counts() {
+    let registry = Registry::default();
+    registry.connect_status(&labels("*.corp.org"), 200);
+    registry.connect_status(&labels("*.corp.org"), 200);
+    registry.connect_status(&labels("*.corp.org"), 502);
+
+    let out = render(&registry);
+    let series =
+        r#"tpt_upstream_connect_responses_total{server="web",route="*.corp.org",upstream="corp""#;
+    assert!(out.contains(&format!(r#"{},status="200"}} 2"#, series)));
+    assert!(out.contains(&format!(r#"{},status="502"}} 1"#, series)));
+}
+
+#[test]
+fn test_histogram_buckets_are_cumulative() {
+    let registry = Registry::default();
+    let l = labels("default");
+    registry.connect_duration(&l, Duration::from_millis(3));
+    registry.connect_duration(&l, Duration::from_millis(40));
+    registry.connect_duration(&l, Duration::from_secs(30));
+
+    let out = render(&registry);
+    let series = r#"tpt_upstream_connect_duration_seconds_bucket{server="web",route="default",upstream="corp""#;
+    assert!(out.contains(&format!(r#"{},le="0.005"}} 1"#, series)));
+    assert!(out.contains(&format!(r#"{},le="0.05"}} 2"#, series)));
+    assert!(out.contains(&format!(r#"{},le="10"}} 2"#, series)));
+    assert!(out.contains(&format!(r#"{},le="+Inf"}} 3"#, series)));
+    assert!(out.contains(
+        r#"tpt_upstream_connect_duration_seconds_count{server="web",route="default",upstream="corp"} 3"#
+    ));
+    assert!(out.contains("# TYPE tpt_tunnel_duration_seconds histogram"));
+}
+
+#[test]
+fn test_label_values_are_escaped() {
+    let registry = Registry::default();
+    registry.tunnel_duration(&labels(r#"^(\w+)\.corp\.org$"#), Duration::from_secs(1));
+    let out = render(&registry);
+    assert!(out.contains(r#"route="^(\\w+)\\.corp\\.org$""#), "{}", out);
+}
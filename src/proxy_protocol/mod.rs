@@ -0,0 +1,294 @@
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{self, AsyncReadExt};
+use tokio::net::TcpStream;
+
+// ---------------------------------------------------------------------------
+// HAProxy PROXY protocol v1/v2 headers.
+// See https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyProtocolVersion {
+    /// Human-readable text header.
+    V1,
+    /// Binary header with TLVs for SNI (authority) and ALPN.
+    V2,
+}
+
+const V2_SIGNATURE: [u8; 12] = [
+    0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A,
+];
+const V2_VERSION_LOCAL: u8 = 0x20;
+const V2_VERSION_PROXY: u8 = 0x21;
+const V2_TCP4: u8 = 0x11;
+const V2_TCP6: u8 = 0x21;
+const PP2_TYPE_ALPN: u8 = 0x01;
+const PP2_TYPE_AUTHORITY: u8 = 0x02;
+
+/// Both addresses must be of the same family; mixed pairs are expressed as
+/// IPv4-mapped IPv6 addresses.
+fn same_family(src: SocketAddr, dst: SocketAddr) -> (IpAddr, IpAddr) {
+    match (src.ip(), dst.ip()) {
+        (IpAddr::V4(s), IpAddr::V4(d)) => (IpAddr::V4(s), IpAddr::V4(d)),
+        (s, d) => (IpAddr::V6(to_v6(s)), IpAddr::V6(to_v6(d))),
+    }
+}
+
+fn to_v6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(v4) => v4.to_ipv6_mapped(),
+        IpAddr::V6(v6) => v6,
+    }
+}
+
+/// `PROXY TCP4 <src> <dst> <sport> <dport>\r\n`
+pub(crate) fn encode_v1(src: SocketAddr, dst: SocketAddr) -> Vec<u8> {
+    let (src_ip, dst_ip) = same_family(src, dst);
+    let family = if src_ip.is_ipv4() { "TCP4" } else { "TCP6" };
+    format!(
+        "PROXY {} {} {} {} {}\r\n",
+        family,
+        src_ip,
+        dst_ip,
+        src.port(),
+        dst.port()
+    )
+    .into_bytes()
+}
+
+/// Binary v2 header. `authority` (the SNI) and `alpn` are added as TLVs
+/// when present.
+pub(crate) fn encode_v2(
+    src: SocketAddr,
+    dst: SocketAddr,
+    authority: Option<&str>,
+    alpn: Option<&str>,
+) -> Vec<u8> {
+    let (src_ip, dst_ip) = same_family(src, dst);
+
+    let mut body = Vec::with_capacity(64);
+    let family = match (src_ip, dst_ip) {
+        (IpAddr::V4(s), IpAddr::V4(d)) => {
+            body.extend_from_slice(&s.octets());
+            body.extend_from_slice(&d.octets());
+            V2_TCP4
+        }
+        _ => {
+            body.extend_from_slice(&to_v6(src_ip).octets());
+            body.extend_from_slice(&to_v6(dst_ip).octets());
+            V2_TCP6
+        }
+    };
+    body.extend_from_slice(&src.port().to_be_bytes());
+    body.extend_from_slice(&dst.port().to_be_bytes());
+
+    for (kind, value) in [(PP2_TYPE_ALPN, alpn), (PP2_TYPE_AUTHORITY, authority)] {
+        if let Some(value) = value.filter(|v| !v.is_empty() && v.len() <= u16::MAX as usize) {
+            body.push(kind);
+            body.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            body.extend_from_slice(value.as_bytes());
+        }
+    }
+
+    let mut header = Vec::with_capacity(16 + body.len());
+    header.extend_from_slice(&V2_SIGNATURE);
+    header.push(V2_VERSION_PROXY);
+    header.push(family);
+    header.extend_from_slice(&(body.len() as u16).to_be_bytes());
+    header.extend_from_slice(&body);
+    header
+}
+
+// ---------------------------------------------------------------------------
+// Decoding (inbound, from a load balancer in front of tpt)
+// ---------------------------------------------------------------------------
+
+/// Longest possible v1 header including CRLF.
+const V1_MAX_LEN: usize = 107;
+/// Delay between peeks while waiting for the rest of a header.
+const PEEK_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A decoded PROXY protocol header. Addresses are `None` for `UNKNOWN` (v1)
+/// and `LOCAL` or non-TCP (v2) headers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ProxyHeader {
+    pub source: Option<SocketAddr>,
+    pub destination: Option<SocketAddr>,
+    /// Header length on the wire, i.e. how many bytes to strip.
+    pub len: usize,
+}
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Decode a PROXY protocol header at the start of `buf`.
+/// Returns `Ok(None)` if more data is needed.
+pub(crate) fn decode(buf: &[u8]) -> io::Result<Option<ProxyHeader>> {
+    if buf.len() < V2_SIGNATURE.len() {
+        let prefix_ok =
+            V2_SIGNATURE.starts_with(buf) || b"PROXY ".starts_with(&buf[..buf.len().min(6)]);
+        return if prefix_ok {
+            Ok(None)
+        } else {
+            Err(invalid("missing PROXY protocol header"))
+        };
+    }
+    if buf.starts_with(&V2_SIGNATURE) {
+        decode_v2(buf)
+    } else if buf.starts_with(b"PROXY ") {
+        decode_v1(buf)
+    } else {
+        Err(invalid("missing PROXY protocol header"))
+    }
+}
+
+fn decode_v1(buf: &[u8]) -> io::Result<Option<ProxyHeader>> {
+    let window = &buf[..buf.len().min(V1_MAX_LEN)];
+    let Some(end) = window.windows(2).position(|w| w == b"\r\n") else {
+        return if buf.len() >= V1_MAX_LEN {
+            Err(invalid("PROXY v1 header too long"))
+        } else {
+            Ok(None)
+        };
+    };
+    let line =
+        std::str::from_utf8(&buf[..end]).map_err(|_| invalid("PROXY v1 header is not ASCII"))?;
+    let parts: Vec<&str> = line.split(' ').collect();
+    let len = end + 2;
+
+    match parts.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(Some(ProxyHeader {
+            source: None,
+            destination: None,
+            len,
+        })),
+        ["PROXY", family @ ("TCP4" | "TCP6"), src, dst, sport, dport] => {
+            let parse_ip = |s: &str| -> io::Result<IpAddr> {
+                let ip: IpAddr = s
+                    .parse()
+                    .map_err(|_| invalid(format!("invalid address {}", s)))?;
+                if ip.is_ipv4() != (*family == "TCP4") {
+                    return Err(invalid(format!("address {} does not match {}", s, family)));
+                }
+                Ok(ip)
+            };
+            let parse_port = |s: &str| -> io::Result<u16> {
+                s.parse()
+                    .map_err(|_| invalid(format!("invalid port {}", s)))
+            };
+            Ok(Some(ProxyHeader {
+                source: Some(SocketAddr::new(parse_ip(src)?, parse_port(sport)?)),
+                destination: Some(SocketAddr::new(parse_ip(dst)?, parse_port(dport)?)),
+                len,
+            }))
+        }
+        _ => Err(invalid(format!("malformed PROXY v1 header {:?}", line))),
+    }
+}
+
+/// Length of the v2 header at the start of `buf` (at most 16 + 65535), once
+/// its fixed part is buffered.
+fn v2_len(buf: &[u8]) -> Option<usize> {
+    if !buf.starts_with(&V2_SIGNATURE) || buf.len() < 16 {
+        return None;
+    }
+    Some(16 + u16::from_be_bytes([buf[14], buf[15]]) as usize)
+}
+
+fn decode_v2(buf: &[u8]) -> io::Result<Option<ProxyHeader>> {
+    let Some(len) = v2_len(buf) else {
+        return Ok(None);
+    };
+    if buf.len() < len {
+        return Ok(None);
+    }
+    let body = &buf[16..len];
+
+    let (source, destination) = match (buf[12], buf[13]) {
+        (V2_VERSION_LOCAL, _) => (None, None),
+        (V2_VERSION_PROXY, V2_TCP4) if body.len() >= 12 => {
+            let ip = |o: usize| Ipv4Addr::new(body[o], body[o + 1], body[o + 2], body[o + 3]);
+            let port = |o: usize| u16::from_be_bytes([body[o], body[o + 1]]);
+            (
+                Some(SocketAddr::new(ip(0).into(), port(8))),
+                Some(SocketAddr::new(ip(4).into(), port(10))),
+            )
+        }
+        (V2_VERSION_PROXY, V2_TCP6) if body.len() >= 36 => {
+            let ip = |o: usize| {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(&body[o..o + 16]);
+                Ipv6Addr::from(octets)
+            };
+            let port = |o: usize| u16::from_be_bytes([body[o], body[o + 1]]);
+            (
+                Some(SocketAddr::new(ip(0).into(), port(32))),
+                Some(SocketAddr::new(ip(16).into(), port(34))),
+            )
+        }
+        (V2_VERSION_PROXY, V2_TCP4 | V2_TCP6) => {
+            return Err(invalid("PROXY v2 address block too short"));
+        }
+        // UDP, UNIX sockets or unspecified: keep the connection, ignore addresses.
+        (V2_VERSION_PROXY, _) => (None, None),
+        (other, _) => {
+            return Err(invalid(format!(
+                "unsupported PROXY v2 version/command 0x{:02x}",
+                other
+            )));
+        }
+    };
+
+    Ok(Some(ProxyHeader {
+        source,
+        destination,
+        len,
+    }))
+}
+
+/// Peek until a complete PROXY protocol header is buffered, then consume
+/// exactly that header so the stream is left at the client's first byte.
+pub(crate) async fn read_header(
+    inbound: &mut TcpStream,
+    timeout: Duration,
+) -> io::Result<ProxyHeader> {
+    let read = async {
+        // Enough for any v1 header and the fixed part of a v2 header, which
+        // announces how much more to expect.
+        let mut buf = vec![0u8; V1_MAX_LEN];
+        loop {
+            let n = inbound.peek(&mut buf).await?;
+            if n == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+            }
+            if let Some(header) = decode(&buf[..n])? {
+                let mut consumed = vec![0u8; header.len];
+                inbound.read_exact(&mut consumed).await?;
+                debug!("PROXY protocol header: {:?}", header);
+                return Ok(header);
+            }
+            if let Some(len) = v2_len(&buf[..n])
+                && len > buf.len()
+            {
+                buf.resize(len, 0);
+                continue;
+            }
+            tokio::time::sleep(PEEK_RETRY_INTERVAL).await;
+        }
+    };
+    tokio::time::timeout(timeout, read).await.map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::TimedOut,
+            "timed out reading PROXY protocol header",
+        )
+    })?
+}
+
+#[cfg(test)]
+#[path = "tests.rs"]
+mod tests;
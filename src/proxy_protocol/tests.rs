@@ -0,0 +1,236 @@
+use super::*;
+
+fn addr(s: &str) -> SocketAddr {
+    s.parse().unwrap()
+}
+
+// --- v1 ---
+
+#[test]
+fn test_encode_v1_ipv4() {
+    let header = encode_v1(addr("192.0.2.10:51234"), addr("198.51.100.1:443"));
+    assert_eq!(
+        header,
+        b"PROXY TCP4 192.0.2.10 198.51.100.1 51234 443\r\n".to_vec()
+    );
+}
+
+#[test]
+fn test_encode_v1_ipv6() {
+    let header = encode_v1(addr("[2001:db8::1]:51234"), addr("[2001:db8::2]:443"));
+    assert_eq!(
+        header,
+        b"PROXY TCP6 2001:db8::1 2001:db8::2 51234 443\r\n".to_vec()
+    );
+}
+
+#[test]
+fn test_encode_v1_mixed_family_maps_to_ipv6() {
+    let header = encode_v1(addr("192.0.2.10:51234"), addr("[2001:db8::2]:443"));
+    assert_eq!(
+        header,
+        b"PROXY TCP6 ::ffff:192.0.2.10 2001:db8::2 51234 443\r\n".to_vec()
+    );
+}
+
+// --- v2 ---
+
+#[test]
+fn test_encode_v2_ipv4_without_tlvs() {
+    let header = encode_v2(
+        addr("192.0.2.10:51234"),
+        addr("198.51.100.1:443"),
+        None,
+        None,
+    );
+    assert_eq!(&header[..12], &V2_SIGNATURE);
+    assert_eq!(header[12], 0x21);
+    assert_eq!(header[13], 0x11);
+    assert_eq!(u16::from_be_bytes([header[14], header[15]]), 12);
+    assert_eq!(&header[16..20], &[192, 0, 2, 10]);
+    assert_eq!(&header[20..24], &[198, 51, 100, 1]);
+    assert_eq!(u16::from_be_bytes([header[24], header[25]]), 51234);
+    assert_eq!(u16::from_be_bytes([header[26], header[27]]), 443);
+    assert_eq!(header.len(), 28);
+}
+
+#[test]
+fn test_encode_v2_ipv6_with_tlvs() {
+    let header = encode_v2(
+        addr("[2001:db8::1]:51234"),
+        addr("[2001:db8::2]:443"),
+        Some("www.example.com"),
+        Some("h2"),
+    );
+    assert_eq!(header[13], 0x21);
+    let len = u16::from_be_bytes([header[14], header[15]]) as usize;
+    assert_eq!(header.len(), 16 + len);
+
+    // 36 bytes of addresses, then ALPN and AUTHORITY TLVs.
+    let tlvs = &header[16 + 36..];
+    assert_eq!(tlvs[0], PP2_TYPE_ALPN);
+    assert_eq!(u16::from_be_bytes([tlvs[1], tlvs[2]]), 2);
+    assert_eq!(&tlvs[3..5], b"h2");
+    assert_eq!(tlvs[5], PP2_TYPE_AUTHORITY);
+    assert_eq!(u16::from_be_bytes([tlvs[6], tlvs[7]]), 15);
+    assert_eq!(&tlvs[8..], b"www.example.com");
+}
+
+// --- decode ---
+
+#[test]
+fn test_decode_v1_tcp4() {
+    let buf = b"PROXY TCP4 192.0.2.10 198.51.100.1 51234 443\r\n\x16\x03";
+    let header = decode(buf).unwrap().unwrap();
+    assert_eq!(header.source, Some(addr("192.0.2.10:51234")));
+    assert_eq!(header.destination, Some(addr("198.51.100.1:443")));
+    assert_eq!(header.len, buf.len() - 2);
+}
+
+#[test]
+fn test_decode_v1_roundtrip_ipv6() {
+    let encoded = encode_v1(addr("[2001:db8::1]:51234"), addr("[2001:db8::2]:443"));
+    let header = decode(&encoded).unwrap().unwrap();
+    assert_eq!(header.source, Some(addr("[2001:db8::1]:51234")));
+    assert_eq!(header.len, encoded.len());
+}
+
+#[test]
+fn test_decode_v1_unknown() {
+    let header = decode(b"PROXY UNKNOWN\r\n").unwrap().unwrap();
+    assert_eq!(header.source, None);
+    assert_eq!(header.destination, None);
+    assert_eq!(header.len, 15);
+}
+
+#[test]
+fn test_decode_v1_partial() {
+    assert_eq!(decode(b"PRO").unwrap(), None);
+    assert_eq!(decode(b"PROXY TCP4 192.0.2.10").unwrap(), None);
+}
+
+#[test]
+fn test_decode_v1_malformed() {
+    assert!(decode(b"PROXY TCP4 192.0.2.10 nope 1 2\r\n").is_err());
+    assert!(decode(b"PROXY TCP4 2001:db8::1 192.0.2.1 1 2\r\n").is_err());
+    assert!(decode(&[b'P'; V1_MAX_LEN + 1]).is_err());
+}
+
+#[test]
+fn test_decode_missing_header() {
+    assert!(decode(b"\x16\x03\x01\x02\x00").is_err());
+    assert!(decode(b"GET / HTTP/1.1\r\n").is_err());
+}
+
+#[test]
+fn test_decode_v2_roundtrip_with_tlvs() {
+    let encoded = encode_v2(
+        addr("192.0.2.10:51234"),
+        addr("198.51.100.1:443"),
+        Some("www.example.com"),
+        Some("h2"),
+    );
+    let header = decode(&encoded).unwrap().unwrap();
+    assert_eq!(header.source, Some(addr("192.0.2.10:51234")));
+    assert_eq!(header.destination, Some(addr("198.51.100.1:443")));
+    assert_eq!(header.len, encoded.len());
+}
+
+#[test]
+fn test_decode_v2_ipv6() {
+    let encoded = encode_v2(addr("[2001:db8::1]:1"), addr("[2001:db8::2]:2"), None, None);
+    let header = decode(&encoded).unwrap().unwrap();
+    assert_eq!(header.source, Some(addr("[2001:db8::1]:1")));
+    assert_eq!(header.destination, Some(addr("[2001:db8::2]:2")));
+}
+
+#[test]
+fn test_decode_v2_local() {
+    let mut buf = V2_SIGNATURE.to_vec();
+    buf.extend_from_slice(&[V2_VERSION_LOCAL, 0x00, 0x00, 0x00]);
+    let header = decode(&buf).unwrap().unwrap();
+    assert_eq!(header.source, None);
+    assert_eq!(header.len, 16);
+}
+
+#[test]
+fn test_decode_v2_partial() {
+    let encoded = encode_v2(addr("192.0.2.10:1"), addr("198.51.100.1:2"), None, None);
+    assert_eq!(decode(&encoded[..8]).unwrap(), None);
+    assert_eq!(decode(&encoded[..20]).unwrap(), None);
+}
+
+/// A v2 header carrying one `PP2_TYPE_NOOP` TLV of `size` bytes.
+fn encode_v2_with_large_tlv(size: usize) -> Vec<u8> {
+    let mut header = encode_v2(addr("192.0.2.10:1"), addr("198.51.100.1:2"), None, None);
+    header.push(0x04);
+    header.extend_from_slice(&(size as u16).to_be_bytes());
+    header.resize(header.len() + size, 0);
+    let body_len = (header.len() - 16) as u16;
+    header[14..16].copy_from_slice(&body_len.to_be_bytes());
+    header
+}
+
+#[test]
+fn test_decode_v2_large_tlv() {
+    let encoded = encode_v2_with_large_tlv(u16::MAX as usize - 15);
+    assert_eq!(encoded.len(), 16 + u16::MAX as usize);
+    let header = decode(&encoded).unwrap().unwrap();
+    assert_eq!(header.source, Some(addr("192.0.2.10:1")));
+    assert_eq!(header.len, encoded.len());
+}
+
+#[test]
+fn test_decode_v2_bad_version() {
+    let mut encoded = encode_v2(addr("192.0.2.10:1"), addr("198.51.100.1:2"), None, None);
+    encoded[12] = 0x31;
+    assert!(decode(&encoded).is_err());
+}
+
+// --- read_header ---
+
+#[tokio::test]
+async fn test_read_header_strips_header_in_pieces() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (mut server, _) = listener.accept().await.unwrap();
+
+    let header = encode_v2_with_large_tlv(4000);
+    tokio::spawn(async move {
+        client.write_all(&header[..10]).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        client.write_all(&header[10..]).await.unwrap();
+        client.write_all(b"payload").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    });
+
+    let decoded = read_header(&mut server, Duration::from_secs(1))
+        .await
+        .unwrap();
+    assert_eq!(decoded.source, Some(addr("192.0.2.10:1")));
+
+    let mut rest = [0u8; 7];
+    server.read_exact(&mut rest).await.unwrap();
+    assert_eq!(&rest, b"payload");
+}
+
+#[tokio::test]
+async fn test_read_header_timeout() {
+    use tokio::net::{TcpListener, TcpStream};
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let _client = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (mut server, _) = listener.accept().await.unwrap();
+
+    let err = read_header(&mut server, Duration::from_millis(50))
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+}
@@ -1,16 +1,33 @@
-use log::{debug, error};
+use log::{debug, error, warn};
 use std::collections::HashMap;
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, Mutex};
 use tokio::sync::Semaphore;
 
+use crate::access_log;
 use crate::config::ParsedConfig;
-use crate::upstreams::{Metrics, MetricsEntry, Upstream};
+use crate::upstreams::{
+    AdminApi, Metrics, MetricsData, MetricsEntry, SrvUpstream, Upstream, UpstreamTarget,
+    monitored_upstreams,
+};
 
 use super::{Proxy, Server, UpstreamMap};
 
 impl From<ParsedConfig> for Server {
     fn from(config: ParsedConfig) -> Self {
+        Server::build(config, &[])
+    }
+}
+
+impl Server {
+    /// Build a server from `config`. Listeners that already exist in
+    /// `previous` keep their maxclients semaphore and counters, so
+    /// connections opened before a reload still count.
+    pub(super) fn build(config: ParsedConfig, previous: &[Arc<Proxy>]) -> Self {
+        access_log::configure(&config.access_log);
+        let admin = AdminApi::new(&config.admin, config.to_json());
+
         // Pass 1: build per-proxy semaphores — needed before metrics can be created.
         let mut raw_proxies: Vec<Proxy> = Vec::new();
 
@@ -38,34 +55,81 @@ impl From<ParsedConfig> for Server {
 
                 debug!("proxy.maxclients {:?}", maxclients_limit);
 
+                let (maxclients, maxclients_deficit, client_hello_timeouts) =
+                    match previous.iter().find(|p| p.listen == listen_addr) {
+                        Some(old) => {
+                            resize_semaphore(old, maxclients_limit);
+                            (
+                                old.maxclients.clone(),
+                                old.maxclients_deficit.clone(),
+                                old.client_hello_timeouts.clone(),
+                            )
+                        }
+                        None => (
+                            Arc::new(Semaphore::new(maxclients_limit)),
+                            Arc::default(),
+                            Arc::new(AtomicU64::new(0)),
+                        ),
+                    };
+
                 raw_proxies.push(Proxy {
                     name: name.clone(),
                     listen: listen_addr,
                     protocol: protocol.clone(),
                     tls,
                     sni: sni.clone(),
+                    sni_rules: proxy_cfg.sni_rules.clone(),
                     default_action: default.clone(),
                     // Placeholder — replaced with the real shared Arc in Pass 3.
                     upstream: Arc::new(HashMap::new()),
                     via: proxy_cfg.via.clone(),
-                    maxclients: Arc::new(Semaphore::new(maxclients_limit)),
+                    maxclients,
                     maxclients_limit,
+                    maxclients_deficit,
+                    accept_proxy_protocol: proxy_cfg.accept_proxy_protocol,
+                    client_hello: proxy_cfg.client_hello.clone(),
+                    client_hello_timeouts,
                 });
             }
         }
 
         // Pass 2: build a shared Metrics snapshot from all proxies.
-        let metrics: Metrics = Arc::new(
-            raw_proxies
+        // Monitored upstreams share their state Arcs with the clones
+        // collected here, so the probes and /metrics see the same values.
+        let monitored = monitored_upstreams(&config.upstream);
+        let mut dns_targets: Vec<UpstreamTarget> = config
+            .upstream
+            .iter()
+            .flat_map(|(name, upstream)| {
+                upstream.proxies().into_iter().map(|p| UpstreamTarget {
+                    upstream: name.clone(),
+                    proxy: p.clone(),
+                })
+            })
+            .collect();
+        let srv_upstreams: Vec<SrvUpstream> = config
+            .upstream
+            .values()
+            .filter_map(|upstream| match upstream {
+                Upstream::Srv(srv) => Some(srv.clone()),
+                _ => None,
+            })
+            .collect();
+        dns_targets.sort_by(|a, b| (&a.upstream, &a.proxy.addr).cmp(&(&b.upstream, &b.proxy.addr)));
+        let metrics: Metrics = Arc::new(MetricsData {
+            servers: raw_proxies
                 .iter()
                 .map(|p| MetricsEntry {
                     name: p.name.clone(),
                     listen: p.listen.to_string(),
                     maxclients_limit: p.maxclients_limit,
                     semaphore: p.maxclients.clone(),
+                    client_hello_timeouts: p.client_hello_timeouts.clone(),
                 })
                 .collect(),
-        );
+            upstreams: monitored.clone(),
+            admin,
+        });
 
         // Pass 3: build one shared upstream map with real metrics injected,
         // then hand the same Arc to every proxy — no HashMap clone per proxy.
@@ -90,6 +154,67 @@ impl From<ParsedConfig> for Server {
 
         Server {
             proxies: raw_proxies.into_iter().map(Arc::new).collect(),
+            health_targets: monitored
+                .into_iter()
+                .filter(|t| t.proxy.health().is_some())
+                .collect(),
+            dns_targets,
+            srv_upstreams,
+            config_path: None,
+            config_watch: config.config_watch,
+            tracing: config.tracing,
+            dns: config.dns,
+            log: (config.log, config.log_format),
+        }
+    }
+}
+
+/// Adjust `old`'s semaphore to `limit` permits. Permits held by open
+/// connections cannot be taken back; a lower limit applies fully once enough
+/// of them have closed. Permits still owed from an earlier, lower limit are
+/// settled before any are added.
+fn resize_semaphore(old: &Proxy, limit: usize) {
+    let semaphore = &old.maxclients;
+    let mut deficit = old.maxclients_deficit.lock().unwrap();
+    let pending = *deficit;
+    // Permits the semaphore still hands out: the old limit plus any not yet
+    // taken back from an earlier, lower limit.
+    let current = old.maxclients_limit + pending;
+    *deficit = 0;
+    if limit > current {
+        semaphore.add_permits(limit - current);
+    } else if limit < current {
+        let shrink = current - limit;
+        let missing = shrink - semaphore.forget_permits(shrink);
+        if missing > 0 {
+            warn!(
+                "maxclients of '{}' lowered to {}, waiting for {} open connections to close",
+                old.name, limit, missing
+            );
+            *deficit = missing;
+            // A task is still taking permits for the earlier deficit.
+            if pending == 0 {
+                tokio::spawn(take_permits(
+                    semaphore.clone(),
+                    old.maxclients_deficit.clone(),
+                ));
+            }
+        }
+    }
+}
+
+/// Forget permits as open connections release them until `deficit` is paid.
+/// A resize may lower or clear the deficit while this waits.
+async fn take_permits(semaphore: Arc<Semaphore>, deficit: Arc<Mutex<usize>>) {
+    while let Ok(permit) = semaphore.acquire().await {
+        let mut deficit = deficit.lock().unwrap();
+        if *deficit == 0 {
+            return;
+        }
+        permit.forget();
+        *deficit -= 1;
+        if *deficit == 0 {
+            return;
         }
     }
 }
@@ -1,27 +1,57 @@
 use log::{error, info};
 use std::collections::HashMap;
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, Mutex};
 use tokio::signal::unix::{SignalKind, signal};
-use tokio::sync::Semaphore;
+use tokio::sync::{Notify, Semaphore};
 use tokio::task;
 use tokio_util::sync::CancellationToken;
 use tokio_util::task::TaskTracker;
 
 mod builder;
-mod protocol;
+pub(crate) mod protocol;
+mod reload;
+mod routing;
 pub(crate) mod upstream_address;
+mod watch;
 
+use crate::config::ClientHelloConfig;
+use crate::config::ConfigWatch;
+use crate::config::DnsConfig;
+use crate::config::SniRule;
 use crate::config::SniTarget;
+use crate::config::TracingConfig;
 use crate::config::ViaUpstream;
-use crate::upstreams::Upstream;
-use protocol::tcp;
+use crate::telemetry;
+use crate::upstreams::health_check;
+use crate::upstreams::{SrvUpstream, Upstream, UpstreamTarget};
+use protocol::ConnectReply;
+use reload::Listeners;
 
 pub(super) type UpstreamMap = Arc<HashMap<String, Upstream>>;
 
 #[derive(Debug)]
 pub(crate) struct Server {
     pub proxies: Vec<Arc<Proxy>>,
+    /// Upstreams probed in the background while the server runs.
+    pub health_targets: Vec<UpstreamTarget>,
+    /// Upstreams whose DNS answers are refreshed in the background.
+    pub dns_targets: Vec<UpstreamTarget>,
+    /// `srv://` upstreams whose records are refreshed in the background.
+    pub srv_upstreams: Vec<SrvUpstream>,
+    /// Config file re-read on reload.
+    pub config_path: Option<String>,
+    /// Reload when the config file changes.
+    pub config_watch: ConfigWatch,
+    /// Where connection traces are exported.
+    pub tracing: TracingConfig,
+    /// Applied to the process-wide resolver when serving starts and after
+    /// each reload.
+    pub dns: DnsConfig,
+    /// `log` and `log-format` as loaded. The logger is set up once, so a
+    /// reload that changes them only warns.
+    pub log: (Option<String>, String),
 }
 
 #[derive(Debug, Clone)]
@@ -31,12 +61,21 @@ pub(crate) struct Proxy {
     pub protocol: String,
     pub tls: bool,
     pub sni: Option<HashMap<String, SniTarget>>,
+    pub sni_rules: Vec<SniRule>,
     pub default_action: String,
     pub upstream: UpstreamMap,
     pub via: ViaUpstream,
     pub maxclients: Arc<Semaphore>,
     /// Maximum number of concurrent connections (config value).
     pub maxclients_limit: usize,
+    /// Permits a lowered `maxclients` still has to take back from open
+    /// connections.
+    pub maxclients_deficit: Arc<Mutex<usize>>,
+    /// Strip and honour a PROXY protocol header before anything else.
+    pub accept_proxy_protocol: bool,
+    pub client_hello: ClientHelloConfig,
+    /// Connections that hit `client_hello_timeout`.
+    pub client_hello_timeouts: Arc<AtomicU64>,
 }
 
 impl Proxy {
@@ -46,21 +85,33 @@ impl Proxy {
             Some(Upstream::Health(_))
         )
     }
+
+    /// Forward-proxy listeners: the client names its destination first.
+    pub fn connect_reply(&self) -> Option<ConnectReply> {
+        match self.protocol.as_str() {
+            "http-connect" => Some(ConnectReply::Http),
+            "socks5" => Some(ConnectReply::Socks5),
+            _ => None,
+        }
+    }
 }
 
 impl Server {
+    /// Re-read `path` on SIGHUP; see `Server::reload`.
+    pub fn with_config_path(mut self, path: String) -> Self {
+        self.config_path = Some(path);
+        self
+    }
+
     #[tokio::main]
     pub async fn run(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        let proxies = self.proxies.clone();
         let token = CancellationToken::new();
-        let tracker = TaskTracker::new();
 
         // Signal handlers cancel the token instead of calling process::exit.
         // This lets active connections drain before the process exits.
         for sig in [
             SignalKind::interrupt(),
             SignalKind::terminate(),
-            SignalKind::hangup(),
             SignalKind::quit(),
         ] {
             let token = token.clone();
@@ -72,29 +123,52 @@ impl Server {
             });
         }
 
-        for config in proxies {
-            info!(
-                "Starting {} server {} on {}",
-                config.protocol, config.name, config.listen
-            );
-            let token = token.clone();
-            let tracker_clone = tracker.clone();
-            tracker.spawn(async move {
-                match config.protocol.as_ref() {
-                    "tcp" | "tcp4" | "tcp6" => {
-                        if let Err(e) = tcp::proxy(config.clone(), token, tracker_clone).await {
-                            error!("Failed to start {}: {}", config.name, e);
-                        }
-                    }
-                    _ => {
-                        error!("Invalid protocol: {}", config.protocol)
+        let reload = Arc::new(Notify::new());
+        task::spawn({
+            let reload = reload.clone();
+            async move {
+                let mut hangup =
+                    signal(SignalKind::hangup()).expect("Failed to initialize a signal handler");
+                while hangup.recv().await.is_some() {
+                    info!("SIGHUP received: reloading configuration.");
+                    reload.notify_one();
+                }
+            }
+        });
+
+        self.serve(token, reload).await
+    }
+
+    /// Run the listeners and background tasks until `token` is cancelled,
+    /// reloading the configuration whenever `reload` is notified. Requests
+    /// that arrive while a reload is pending are merged into it.
+    pub(crate) async fn serve(
+        &mut self,
+        token: CancellationToken,
+        reload: Arc<Notify>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let tracker = TaskTracker::new();
+        let mut listeners = Listeners::default();
+        upstream_address::configure(&self.dns);
+        let mut background = self.start_background(&token, &tracker, &reload);
+        listeners.apply(&self.proxies, &token, &tracker);
+
+        loop {
+            tokio::select! {
+                _ = token.cancelled() => break,
+                _ = reload.notified() => {
+                    if let Err(e) = self.reload().await {
+                        error!("Reload failed, keeping the running configuration: {}", e);
+                        continue;
                     }
+                    upstream_address::configure(&self.dns);
+                    background.cancel();
+                    background = self.start_background(&token, &tracker, &reload);
+                    listeners.apply(&self.proxies, &token, &tracker);
+                    info!("Configuration reloaded.");
                 }
-            });
+            }
         }
-
-        // Block until a signal fires and cancels the token.
-        token.cancelled().await;
         info!("Shutdown signal received, waiting for active connections to close...");
 
         // Stop the tracker from accepting new spawns, then wait for all
@@ -105,6 +179,58 @@ impl Server {
         info!("Shutdown complete.");
         Ok(())
     }
+
+    /// Start health checks, DNS refreshes and SRV discovery for the current
+    /// upstreams, and the config file watcher if enabled. Cancel the returned
+    /// token to stop them.
+    fn start_background(
+        &self,
+        token: &CancellationToken,
+        tracker: &TaskTracker,
+        reload: &Arc<Notify>,
+    ) -> CancellationToken {
+        let token = token.child_token();
+
+        if let Some(path) = &self.config_path
+            && self.config_watch.enabled
+        {
+            info!(
+                "Watching config file {} every {:?}",
+                path, self.config_watch.interval
+            );
+            tracker.spawn(watch::run(
+                path.clone(),
+                self.config_watch.interval,
+                reload.clone(),
+                token.clone(),
+            ));
+        }
+
+        if self.tracing.otlp_endpoint.is_some() {
+            tracker.spawn(telemetry::export(self.tracing.clone(), token.clone()));
+        }
+
+        for target in self.health_targets.clone() {
+            info!(
+                "Starting health check for upstream {} ({})",
+                target.upstream, target.proxy.addr
+            );
+            tracker.spawn(health_check::run(target, token.clone()));
+        }
+
+        for target in self.dns_targets.clone() {
+            let token = token.clone();
+            tracker.spawn(async move { target.proxy.refresh_dns(token).await });
+        }
+
+        for srv in self.srv_upstreams.clone() {
+            info!("Starting SRV discovery for {}", srv.name);
+            let token = token.clone();
+            tracker.spawn(async move { srv.keep_fresh(token).await });
+        }
+
+        token
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,163 @@
+use log::debug;
+use std::net::Ipv6Addr;
+use std::time::Duration;
+use tokio::io::{self, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use super::ConnectRequest;
+
+/// Largest request header accepted from a client.
+const MAX_REQUEST_SIZE: usize = 8192;
+
+/// Delay between peeks while waiting for the rest of the request header.
+const PEEK_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Why a request was not accepted, with the status code to answer with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestError {
+    pub status: u16,
+    pub reason: String,
+}
+
+impl RequestError {
+    fn new(status: u16, reason: impl Into<String>) -> Self {
+        RequestError {
+            status,
+            reason: reason.into(),
+        }
+    }
+}
+
+/// Parse the request header (up to and including the blank line).
+/// Returns `Ok(None)` if more data is needed.
+pub fn parse_request(buf: &[u8]) -> Result<Option<(ConnectRequest, usize)>, RequestError> {
+    let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") else {
+        return if buf.len() >= MAX_REQUEST_SIZE {
+            Err(RequestError::new(431, "request header too large"))
+        } else {
+            Ok(None)
+        };
+    };
+    let header_len = end + 4;
+    let head = std::str::from_utf8(&buf[..end])
+        .map_err(|_| RequestError::new(400, "request header is not valid UTF-8"))?;
+    let mut lines = head.lines();
+    let request_line = lines.next().unwrap_or_default();
+
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(authority), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(RequestError::new(400, "malformed request line"));
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err(RequestError::new(400, "unsupported HTTP version"));
+    }
+    if method != "CONNECT" {
+        return Err(RequestError::new(405, "only CONNECT is supported"));
+    }
+
+    let (host, port) = split_authority(authority)
+        .ok_or_else(|| RequestError::new(400, format!("invalid CONNECT target {}", authority)))?;
+
+    let traceparent = lines.find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.eq_ignore_ascii_case("traceparent")
+            .then(|| value.trim().to_string())
+    });
+
+    Ok(Some((
+        ConnectRequest {
+            authority: authority.to_string(),
+            host: host.to_ascii_lowercase(),
+            port,
+            traceparent,
+        },
+        header_len,
+    )))
+}
+
+/// Split `host:port` / `[v6]:port` into its parts. The port is mandatory.
+fn split_authority(authority: &str) -> Option<(&str, u16)> {
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(rest) => {
+            let (host, port) = rest.split_once("]:")?;
+            host.parse::<Ipv6Addr>().ok()?;
+            (host, port)
+        }
+        None => {
+            let (host, port) = authority.rsplit_once(':')?;
+            is_valid_hostname(host).then_some((host, port))?
+        }
+    };
+    let port = port.parse::<u16>().ok().filter(|p| *p != 0)?;
+    Some((host, port))
+}
+
+/// Whether `host` is a DNS name or IPv4 address: letters, digits, `-`, `.`
+/// and `_` only. Anything else could smuggle separators or header lines into
+/// a chained CONNECT.
+pub(super) fn is_valid_hostname(host: &str) -> bool {
+    !host.is_empty()
+        && host
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_'))
+}
+
+/// Read a CONNECT request from the client and consume exactly its header, so
+/// that anything the client pipelined after it stays in the socket for the
+/// relay. Invalid requests are answered here; the reason is returned for
+/// logging.
+pub async fn read_request(inbound: &mut TcpStream) -> io::Result<Result<ConnectRequest, String>> {
+    let mut buf = vec![0u8; MAX_REQUEST_SIZE];
+    loop {
+        let n = inbound.peek(&mut buf).await?;
+        if n == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+        }
+        match parse_request(&buf[..n]) {
+            Ok(Some((request, header_len))) => {
+                let mut consumed = vec![0u8; header_len];
+                inbound.read_exact(&mut consumed).await?;
+                debug!("CONNECT request: {:?}", request);
+                return Ok(Ok(request));
+            }
+            Ok(None) => tokio::time::sleep(PEEK_RETRY_INTERVAL).await,
+            Err(e) => {
+                respond(inbound, e.status).await?;
+                return Ok(Err(format!("{} {}", e.status, e.reason)));
+            }
+        }
+    }
+}
+
+/// Send a bodiless response to the client.
+pub async fn respond<W>(inbound: &mut W, status: u16) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let reason = match status {
+        200 => "Connection established",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        431 => "Request Header Fields Too Large",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        _ => "Error",
+    };
+    let mut response = format!("HTTP/1.1 {} {}\r\n", status, reason);
+    if status == 405 {
+        response.push_str("Allow: CONNECT\r\n");
+    }
+    if status != 200 {
+        response.push_str("Content-Length: 0\r\nConnection: close\r\n");
+    }
+    response.push_str("\r\n");
+    inbound.write_all(response.as_bytes()).await
+}
+
+#[cfg(test)]
+#[path = "http_connect_tests.rs"]
+mod tests;
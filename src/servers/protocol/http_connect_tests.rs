@@ -0,0 +1,118 @@
+use super::*;
+use tokio::net::TcpListener;
+
+fn parse(buf: &[u8]) -> Result<Option<ConnectRequest>, RequestError> {
+    parse_request(buf).map(|r| r.map(|(request, _)| request))
+}
+
+#[test]
+fn test_parse_connect_request() {
+    let buf = b"CONNECT WWW.Example.com:443 HTTP/1.1\r\nHost: www.example.com:443\r\n\r\n\x16\x03";
+    let (request, len) = parse_request(buf).unwrap().unwrap();
+    assert_eq!(request.authority, "WWW.Example.com:443");
+    assert_eq!(request.host, "www.example.com");
+    assert_eq!(request.port, 443);
+    assert_eq!(len, buf.len() - 2);
+}
+
+#[test]
+fn test_parse_connect_traceparent() {
+    let request = parse(
+        b"CONNECT www.example.com:443 HTTP/1.1\r\nHost: www.example.com:443\r\n\
+          TraceParent:  00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01\r\n\r\n",
+    )
+    .unwrap()
+    .unwrap();
+    assert_eq!(
+        request.traceparent.as_deref(),
+        Some("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01")
+    );
+    let request = parse(b"CONNECT www.example.com:443 HTTP/1.1\r\n\r\n")
+        .unwrap()
+        .unwrap();
+    assert_eq!(request.traceparent, None);
+}
+
+#[test]
+fn test_parse_connect_ipv6() {
+    let request = parse(b"CONNECT [2001:db8::1]:8443 HTTP/1.1\r\n\r\n")
+        .unwrap()
+        .unwrap();
+    assert_eq!(request.host, "2001:db8::1");
+    assert_eq!(request.port, 8443);
+    assert_eq!(request.authority, "[2001:db8::1]:8443");
+}
+
+#[test]
+fn test_parse_incomplete() {
+    assert_eq!(parse(b"CONNECT www.example.com:443 HTTP/1.1\r\n"), Ok(None));
+}
+
+#[test]
+fn test_parse_rejects_other_methods() {
+    let err = parse(b"GET / HTTP/1.1\r\n\r\n").unwrap_err();
+    assert_eq!(err.status, 405);
+}
+
+#[test]
+fn test_parse_rejects_bad_targets() {
+    for line in [
+        "CONNECT www.example.com HTTP/1.1",
+        "CONNECT www.example.com:0 HTTP/1.1",
+        "CONNECT :443 HTTP/1.1",
+        "CONNECT user@host:443 HTTP/1.1",
+        "CONNECT a:b:443 HTTP/1.1",
+        "CONNECT [not-v6]:443 HTTP/1.1",
+        "CONNECT www.example.com:443",
+        "CONNECT www.example.com:443 HTTP/2",
+    ] {
+        let buf = format!("{}\r\n\r\n", line);
+        assert_eq!(parse(buf.as_bytes()).unwrap_err().status, 400, "{}", line);
+    }
+}
+
+#[test]
+fn test_parse_header_too_large() {
+    let buf = vec![b'a'; MAX_REQUEST_SIZE];
+    assert_eq!(parse(&buf).unwrap_err().status, 431);
+}
+
+#[tokio::test]
+async fn test_read_request_leaves_pipelined_data() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (mut server, _) = listener.accept().await.unwrap();
+
+    client
+        .write_all(b"CONNECT www.example.com:443 HTTP/1.1\r\n")
+        .await
+        .unwrap();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        client.write_all(b"\r\nhello").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    });
+
+    let request = read_request(&mut server).await.unwrap().unwrap();
+    assert_eq!(request.host, "www.example.com");
+
+    let mut rest = [0u8; 5];
+    server.read_exact(&mut rest).await.unwrap();
+    assert_eq!(&rest, b"hello");
+}
+
+#[tokio::test]
+async fn test_respond_formats_status_line() {
+    let mut out = Vec::new();
+    respond(&mut out, 200).await.unwrap();
+    assert_eq!(out, b"HTTP/1.1 200 Connection established\r\n\r\n");
+
+    let mut out = Vec::new();
+    respond(&mut out, 405).await.unwrap();
+    let text = String::from_utf8(out).unwrap();
+    assert!(text.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+    assert!(text.contains("Allow: CONNECT\r\n"));
+    assert!(text.ends_with("Connection: close\r\n\r\n"));
+}
@@ -1,2 +1,62 @@
+use tokio::io::{self, AsyncWrite};
+
+pub mod http_connect;
+pub mod socks5;
 pub mod tcp;
 pub mod tls;
+
+/// Destination requested by an `http-connect` or `socks5` client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectRequest {
+    /// `host:port` as requested; IPv6 hosts are bracketed.
+    pub authority: String,
+    /// Host without port or brackets, lowercased — used like an SNI for routing.
+    pub host: String,
+    pub port: u16,
+    /// W3C trace context sent with an HTTP CONNECT.
+    pub traceparent: Option<String>,
+}
+
+/// Listener protocols in which the client names its destination and waits
+/// for a reply before the tunnel starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectReply {
+    Http,
+    Socks5,
+}
+
+/// What the waiting client is told.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectOutcome {
+    Established,
+    /// The destination routed to `ban`.
+    Forbidden,
+    /// The upstream could not be reached.
+    Failed,
+}
+
+impl ConnectReply {
+    pub async fn send<W>(self, inbound: &mut W, outcome: ConnectOutcome) -> io::Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        match self {
+            ConnectReply::Http => {
+                let status = match outcome {
+                    ConnectOutcome::Established => 200,
+                    ConnectOutcome::Forbidden => 403,
+                    ConnectOutcome::Failed => 502,
+                };
+                http_connect::respond(inbound, status).await
+            }
+            ConnectReply::Socks5 => {
+                let code = match outcome {
+                    ConnectOutcome::Established => socks5::REP_SUCCEEDED,
+                    ConnectOutcome::Forbidden => socks5::REP_NOT_ALLOWED,
+                    ConnectOutcome::Failed => socks5::REP_HOST_UNREACHABLE,
+                };
+                socks5::reply(inbound, code).await
+            }
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use log::debug;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use tokio::io::{self, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use super::ConnectRequest;
+use super::http_connect::is_valid_hostname;
+
+const VERSION: u8 = 0x05;
+const METHOD_NO_AUTH: u8 = 0x00;
+const METHOD_NONE_ACCEPTABLE: u8 = 0xff;
+const CMD_CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+pub const REP_SUCCEEDED: u8 = 0x00;
+pub const REP_NOT_ALLOWED: u8 = 0x02;
+pub const REP_HOST_UNREACHABLE: u8 = 0x04;
+const REP_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+const REP_ADDRESS_TYPE_NOT_SUPPORTED: u8 = 0x08;
+
+/// Run the server side of the SOCKS5 handshake up to the CONNECT request.
+/// Only the no-auth method and the CONNECT command are supported; anything
+/// else is answered here and the reason returned for logging.
+pub async fn read_request(inbound: &mut TcpStream) -> io::Result<Result<ConnectRequest, String>> {
+    // Method negotiation ------------------------------------------------------
+    let mut greeting = [0u8; 2];
+    inbound.read_exact(&mut greeting).await?;
+    if greeting[0] != VERSION {
+        return Ok(Err(format!("unsupported SOCKS version {}", greeting[0])));
+    }
+    let mut methods = vec![0u8; greeting[1] as usize];
+    inbound.read_exact(&mut methods).await?;
+    if !methods.contains(&METHOD_NO_AUTH) {
+        inbound
+            .write_all(&[VERSION, METHOD_NONE_ACCEPTABLE])
+            .await?;
+        return Ok(Err(format!("no supported auth method in {:?}", methods)));
+    }
+    inbound.write_all(&[VERSION, METHOD_NO_AUTH]).await?;
+
+    // Request: VER CMD RSV ATYP DST.ADDR DST.PORT ------------------------------
+    let mut head = [0u8; 4];
+    inbound.read_exact(&mut head).await?;
+    if head[1] != CMD_CONNECT {
+        reply(inbound, REP_COMMAND_NOT_SUPPORTED).await?;
+        return Ok(Err(format!("unsupported SOCKS command {}", head[1])));
+    }
+    let (host, authority_host) = match head[3] {
+        ATYP_IPV4 => {
+            let mut octets = [0u8; 4];
+            inbound.read_exact(&mut octets).await?;
+            let ip = Ipv4Addr::from(octets).to_string();
+            (ip.clone(), ip)
+        }
+        ATYP_IPV6 => {
+            let mut octets = [0u8; 16];
+            inbound.read_exact(&mut octets).await?;
+            let ip = Ipv6Addr::from(octets).to_string();
+            (ip.clone(), format!("[{}]", ip))
+        }
+        ATYP_DOMAIN => {
+            let len = inbound.read_u8().await? as usize;
+            let mut name = vec![0u8; len];
+            inbound.read_exact(&mut name).await?;
+            match String::from_utf8(name) {
+                Ok(name) if is_valid_hostname(&name) => {
+                    let name = name.to_ascii_lowercase();
+                    (name.clone(), name)
+                }
+                _ => {
+                    reply(inbound, REP_ADDRESS_TYPE_NOT_SUPPORTED).await?;
+                    return Ok(Err("invalid domain name".to_string()));
+                }
+            }
+        }
+        other => {
+            reply(inbound, REP_ADDRESS_TYPE_NOT_SUPPORTED).await?;
+            return Ok(Err(format!("unsupported address type {}", other)));
+        }
+    };
+    let port = inbound.read_u16().await?;
+
+    let request = ConnectRequest {
+        authority: format!("{}:{}", authority_host, port),
+        host,
+        port,
+        traceparent: None,
+    };
+    debug!("SOCKS5 CONNECT request: {:?}", request);
+    Ok(Ok(request))
+}
+
+/// Send a reply with an unspecified bound address.
+pub async fn reply<W>(inbound: &mut W, code: u8) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    inbound
+        .write_all(&[VERSION, code, 0x00, ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+        .await
+}
+
+#[cfg(test)]
+#[path = "socks5_tests.rs"]
+mod tests;
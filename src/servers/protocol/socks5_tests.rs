@@ -0,0 +1,90 @@
+use super::*;
+use tokio::net::TcpListener;
+
+/// Feed `client_bytes` to `read_request` and return its result together with
+/// everything the server wrote back.
+async fn handshake(client_bytes: &'static [u8]) -> (Result<ConnectRequest, String>, Vec<u8>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = tokio::spawn(async move {
+        let mut c = TcpStream::connect(addr).await.unwrap();
+        c.write_all(client_bytes).await.unwrap();
+        c.shutdown().await.unwrap();
+        let mut replies = Vec::new();
+        let _ = c.read_to_end(&mut replies).await;
+        replies
+    });
+    let (mut server, _) = listener.accept().await.unwrap();
+    let result = read_request(&mut server).await.unwrap();
+    drop(server);
+    (result, client.await.unwrap())
+}
+
+#[tokio::test]
+async fn test_read_request_domain() {
+    let (result, replies) =
+        handshake(b"\x05\x01\x00\x05\x01\x00\x03\x0fWWW.Example.com\x01\xbb").await;
+    let request = result.unwrap();
+    assert_eq!(request.host, "www.example.com");
+    assert_eq!(request.port, 443);
+    assert_eq!(request.authority, "www.example.com:443");
+    assert_eq!(replies, [VERSION, METHOD_NO_AUTH]);
+}
+
+#[tokio::test]
+async fn test_read_request_ipv4() {
+    let (result, _) = handshake(b"\x05\x02\x02\x00\x05\x01\x00\x01\xc0\x00\x02\x01\x20\xfb").await;
+    let request = result.unwrap();
+    assert_eq!(request.host, "192.0.2.1");
+    assert_eq!(request.authority, "192.0.2.1:8443");
+}
+
+#[tokio::test]
+async fn test_read_request_ipv6() {
+    let (result, _) = handshake(
+        b"\x05\x01\x00\x05\x01\x00\x04\x20\x01\x0d\xb8\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x01\x01\xbb",
+    )
+    .await;
+    let request = result.unwrap();
+    assert_eq!(request.host, "2001:db8::1");
+    assert_eq!(request.authority, "[2001:db8::1]:443");
+}
+
+#[tokio::test]
+async fn test_read_request_requires_no_auth_method() {
+    let (result, replies) = handshake(b"\x05\x01\x02").await;
+    assert!(result.unwrap_err().contains("no supported auth method"));
+    assert_eq!(replies, [VERSION, METHOD_NONE_ACCEPTABLE]);
+}
+
+#[tokio::test]
+async fn test_read_request_rejects_bind() {
+    // Stop after the request head so nothing is left unread when the server
+    // closes (which would turn the close into a reset).
+    let (result, replies) = handshake(b"\x05\x01\x00\x05\x02\x00\x01").await;
+    assert!(result.unwrap_err().contains("unsupported SOCKS command"));
+    assert_eq!(replies[2..4], [VERSION, REP_COMMAND_NOT_SUPPORTED]);
+}
+
+#[tokio::test]
+async fn test_read_request_rejects_invalid_domain() {
+    // No port after the name, so nothing is left unread (see above).
+    let (result, replies) =
+        handshake(b"\x05\x01\x00\x05\x01\x00\x03\x1awww.example.com\r\nX-Evil: 1").await;
+    assert_eq!(result.unwrap_err(), "invalid domain name");
+    assert_eq!(replies[2..4], [VERSION, REP_ADDRESS_TYPE_NOT_SUPPORTED]);
+}
+
+#[tokio::test]
+async fn test_read_request_rejects_socks4() {
+    let (result, replies) = handshake(b"\x04\x01\x00\x50\x7f\x00\x00\x01\x00").await;
+    assert!(result.unwrap_err().contains("unsupported SOCKS version"));
+    assert!(replies.is_empty());
+}
+
+#[tokio::test]
+async fn test_reply_format() {
+    let mut out = Vec::new();
+    reply(&mut out, REP_NOT_ALLOWED).await.unwrap();
+    assert_eq!(out, [5, REP_NOT_ALLOWED, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0]);
+}
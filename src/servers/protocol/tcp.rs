@@ -1,30 +1,51 @@
+use crate::access_log::ConnectionId;
+use crate::access_log::{self, AccessRecord, CloseReason};
+use crate::config::ClientHelloTimeoutAction;
+use crate::connection_id;
+use crate::connections::{self, Connection};
+use crate::metrics::{self, TunnelLabels};
+use crate::proxy_protocol;
 use crate::servers::Proxy;
-use crate::servers::protocol::tls::get_sni;
+use crate::servers::protocol::tls::{
+    ClientHelloInfo, HelloStatus, parse_client_hello, peek_client_hello,
+};
+use crate::servers::protocol::{ConnectReply, ConnectRequest, http_connect, socks5};
+use crate::servers::routing::{Route, route};
+use crate::telemetry;
+use crate::upstreams::{ConnectionContext, process_with_fallback};
 use log::{debug, error, info, warn};
 use std::error::Error;
+use std::fmt;
 use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
 use tokio::{
     io::{self},
     net::{TcpListener, TcpStream},
+    sync::watch,
 };
 use tokio_util::sync::CancellationToken;
 use tokio_util::task::TaskTracker;
+use tracing::field::{Empty, display};
+use tracing::{Instrument, Span, info_span};
+
+/// How long a client may take to send its PROXY protocol header.
+const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long an `http-connect` or `socks5` client may take to name its
+/// destination.
+const CONNECT_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
 
+/// Accept connections on `current`'s listen address until `token` is
+/// cancelled. Every connection uses the `Proxy` current at accept time, so a
+/// reload swaps routes for new connections while open tunnels keep theirs.
 pub(crate) async fn proxy(
-    config: Arc<Proxy>,
+    current: watch::Receiver<Arc<Proxy>>,
     token: CancellationToken,
     tracker: TaskTracker,
 ) -> Result<(), Box<dyn Error>> {
-    let listener = TcpListener::bind(config.listen).await?;
-
-    debug!(
-        "Name :{:?}: Semaphore :{:?}:",
-        config.name, config.maxclients
-    );
-
-    // Health servers bypass maxclients entirely — health checks must always
-    // succeed regardless of connection load on the same instance.
-    let is_health_server = config.is_health_server();
+    let listen = current.borrow().listen;
+    let listener = TcpListener::bind(listen).await?;
 
     loop {
         // Wait for either an incoming connection or a shutdown signal.
@@ -38,20 +59,37 @@ pub(crate) async fn proxy(
                 Ok(pair) => pair,
             },
             _ = token.cancelled() => {
-                info!("Listener '{}' shutting down, no longer accepting connections.", config.name);
+                info!("Listener '{}' shutting down, no longer accepting connections.", current.borrow().name);
                 return Ok(());
             }
         };
+        let id = ConnectionId::new();
 
+        let config = current.borrow().clone();
+        debug!(
+            "Name :{:?}: Semaphore :{:?}:",
+            config.name, config.maxclients
+        );
         let thread_proxy = config.clone();
 
-        if is_health_server {
+        // Health servers bypass maxclients entirely — health checks must always
+        // succeed regardless of connection load on the same instance.
+        if config.is_health_server() {
             // No permit needed — health checks are never counted against maxclients.
-            tracker.spawn(async move {
-                if let Err(e) = accept(stream, thread_proxy).await {
+            tracker.spawn(connection_id::scope(id, async move {
+                if let Err(e) = accept(stream, thread_proxy, id).await {
                     error!("Health handler error: {}", e);
                 }
-            });
+            }));
+            continue;
+        }
+
+        // A draining server lets its open tunnels finish but takes no new ones.
+        if connections::registry().is_draining(&config.name) {
+            debug!(
+                "Server '{}' is draining, closing connection {} from {}",
+                config.name, id, peer
+            );
             continue;
         }
 
@@ -63,26 +101,136 @@ pub(crate) async fn proxy(
             Ok(p) => p,
             Err(_) => {
                 warn!(
-                    "maxclients reached on '{}', rejecting connection from {}",
-                    config.name, peer,
+                    "maxclients reached on '{}', rejecting connection {} from {}",
+                    config.name, id, peer,
                 );
+                metrics::registry().connection_rejected(&config.name);
                 // stream is dropped here → connection is closed
                 continue;
             }
         };
 
-        tracker.spawn(async move {
-            if let Err(e) = accept(stream, thread_proxy).await {
+        metrics::registry().connection_accepted(&config.name);
+        tracker.spawn(connection_id::scope(id, async move {
+            if let Err(e) = accept(stream, thread_proxy, id).await {
                 error!("Relay thread returned an error: {}", e);
             }
             drop(permit);
-        });
+        }));
+    }
+}
+
+/// An error `handle` has already logged along with the connection's details.
+#[derive(Debug)]
+struct LoggedError(Box<dyn Error>);
+
+impl fmt::Display for LoggedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Error for LoggedError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.0.source()
+    }
+}
+
+async fn accept(
+    inbound: TcpStream,
+    proxy: Arc<Proxy>,
+    id: ConnectionId,
+) -> Result<(), Box<dyn Error>> {
+    let mut record = AccessRecord::new(id, &proxy.name);
+    let conn = Arc::new(Connection::new(id, &proxy.name));
+    if proxy.is_health_server() {
+        return handle(inbound, &proxy, &mut record, &conn).await;
+    }
+    let _registered = connections::registry().register(conn.clone());
+
+    let started = Instant::now();
+    let span = info_span!(
+        "accept",
+        conn_id = %id,
+        server = %proxy.name,
+        client = Empty,
+        listen = Empty,
+        reason = Empty,
+        error = Empty,
+    );
+    // Killing the connection drops `handle`, which closes both sides.
+    let handled = tokio::select! {
+        result = handle(inbound, &proxy, &mut record, &conn).instrument(span.clone()) => Some(result),
+        _ = conn.killed() => None,
+    };
+    let result = handled.unwrap_or_else(|| {
+        info!("Connection killed through the admin API");
+        record.reason = CloseReason::Killed;
+        record.set_tunnel(conn.tunnel.get());
+        Ok(())
+    });
+    if let Err(e) = &result {
+        record.fail(e);
+    }
+    record.finish(started.elapsed());
+    trace_outcome(&span, &record);
+    access_log::write(&record);
+    match result {
+        Err(e) if e.is::<LoggedError>() => Ok(()),
+        result => result,
     }
 }
 
-async fn accept(inbound: TcpStream, proxy: Arc<Proxy>) -> Result<(), Box<dyn Error>> {
+/// Copy what the access log records about the connection onto its span.
+fn trace_outcome(span: &Span, record: &AccessRecord) {
+    if let Some(client) = record.client {
+        span.record("client", display(client));
+    }
+    if let Some(listen) = record.listen {
+        span.record("listen", display(listen));
+    }
+    span.record("reason", record.reason.as_str());
+    if let Some(error) = &record.error {
+        span.record("error", error.as_str());
+    }
+}
+
+/// Serve one connection, noting in `record` what happened to it and keeping
+/// `conn` up to date for the admin API.
+async fn handle(
+    mut inbound: TcpStream,
+    proxy: &Proxy,
+    record: &mut AccessRecord,
+    conn: &Connection,
+) -> Result<(), Box<dyn Error>> {
     let is_health = proxy.is_health_server();
 
+    // Behind a load balancer the real client and destination addresses come
+    // from the PROXY protocol header, which is stripped before the ClientHello
+    // peek. Connections without a valid header are closed.
+    let mut client_addr = inbound.peer_addr()?;
+    let mut local_addr = inbound.local_addr()?;
+    if proxy.accept_proxy_protocol {
+        match proxy_protocol::read_header(&mut inbound, PROXY_HEADER_TIMEOUT).await {
+            Ok(header) => {
+                client_addr = header.source.unwrap_or(client_addr);
+                local_addr = header.destination.unwrap_or(local_addr);
+            }
+            Err(e) => {
+                warn!(
+                    "Invalid PROXY protocol header from {} on '{}': {}",
+                    client_addr, proxy.name, e
+                );
+                record.client = Some(client_addr);
+                record.reason = CloseReason::InvalidProxyHeader;
+                return Ok(());
+            }
+        }
+    }
+    record.client = Some(client_addr);
+    record.listen = Some(local_addr);
+    conn.describe(record);
+
     if is_health {
         debug!("Health check request");
     } else {
@@ -91,65 +239,147 @@ async fn accept(inbound: TcpStream, proxy: Arc<Proxy>) -> Result<(), Box<dyn Err
             .saturating_sub(proxy.maxclients.available_permits());
         info!(
             "New connection from {:?}, active: {}/{}",
-            inbound.peer_addr()?,
-            active,
-            proxy.maxclients_limit
+            client_addr, active, proxy.maxclients_limit
         );
     }
 
-    // For TLS connections: peek at the ClientHello to extract SNI.
+    // For TLS connections: peek at the ClientHello to extract SNI and ALPN.
     // peek() does not consume bytes — the ClientHello is replayed automatically
     // once the bidirectional copy starts, so the TLS handshake runs end-to-end.
-    let snis: Vec<String> = if proxy.tls {
-        let mut hello_buf = [0u8; 4096];
-        inbound.peek(&mut hello_buf).await?;
-        get_sni(&hello_buf)
-    } else {
-        Vec::new()
-    };
-
-    // Route to the upstream name based on SNI map (or fall back to default).
-    // Also extract any per-SNI via override from extended SniTarget entries.
-    let (upstream_name, sni_via_override) = if !snis.is_empty() {
-        match &proxy.sni {
-            Some(sni_map) => {
-                let mut matched = proxy.default_action.clone();
-                let mut via_override = None;
-                for sni in &snis {
-                    if let Some(target) = sni_map.get(sni) {
-                        matched = target.upstream_name().to_string();
-                        via_override = target.via_override();
-                        break;
+    // A ClientHello split across TCP segments or TLS records is reassembled
+    // by peeking repeatedly until it is complete.
+    // The whole read is bounded by client_hello_timeout so that a client
+    // which never sends a ClientHello cannot hold a maxclients permit forever.
+    // On http-connect and socks5 servers the client's request is read (and
+    // consumed) instead; its host takes the place of the SNI in routing.
+    let mut connect_request: Option<ConnectRequest> = None;
+    let connect_reply = proxy.connect_reply();
+    let hello = if let Some(reply) = connect_reply {
+        let read = async {
+            match reply {
+                ConnectReply::Http => http_connect::read_request(&mut inbound).await,
+                ConnectReply::Socks5 => socks5::read_request(&mut inbound).await,
+            }
+        };
+        let request = match tokio::time::timeout(CONNECT_REQUEST_TIMEOUT, read).await {
+            Ok(Ok(Ok(request))) => request,
+            Ok(Ok(Err(reason))) => {
+                warn!(
+                    "Rejected {} request from {} on '{}': {}",
+                    proxy.protocol, client_addr, proxy.name, reason
+                );
+                record.reason = CloseReason::ConnectRequestRejected;
+                record.error = Some(reason);
+                return Ok(());
+            }
+            Ok(Err(e)) => return Err(e.into()),
+            Err(_) => {
+                warn!(
+                    "No {} request from {} within {:?} on '{}'",
+                    proxy.protocol, client_addr, CONNECT_REQUEST_TIMEOUT, proxy.name
+                );
+                record.reason = CloseReason::ConnectRequestTimeout;
+                if reply == ConnectReply::Http {
+                    http_connect::respond(&mut inbound, 408).await?;
+                }
+                return Ok(());
+            }
+        };
+        if let Some(traceparent) = &request.traceparent {
+            telemetry::set_remote_parent(&Span::current(), traceparent);
+        }
+        let hello = ClientHelloInfo {
+            snis: vec![request.host.clone()],
+            ..Default::default()
+        };
+        connect_request = Some(request);
+        hello
+    } else if proxy.tls {
+        let limits = &proxy.client_hello;
+        let peek = peek_client_hello(
+            &inbound,
+            limits.client_hello_max_size,
+            limits.client_hello_reassembly_timeout,
+        );
+        let peeked = if limits.client_hello_timeout.is_zero() {
+            Some(peek.await?)
+        } else {
+            match tokio::time::timeout(limits.client_hello_timeout, peek).await {
+                Ok(result) => Some(result?),
+                Err(_) => None,
+            }
+        };
+        match peeked {
+            Some((hello_buf, HelloStatus::Complete)) => {
+                parse_client_hello(&hello_buf).unwrap_or_default()
+            }
+            // Not a timeout: route on what is known, i.e. to `default`.
+            Some((hello_buf, status)) => {
+                warn!(
+                    "No usable ClientHello from {:?} on '{}': {:?} after {} bytes, routing to default",
+                    client_addr,
+                    proxy.name,
+                    status,
+                    hello_buf.len()
+                );
+                ClientHelloInfo::default()
+            }
+            None => {
+                proxy.client_hello_timeouts.fetch_add(1, Ordering::Relaxed);
+                warn!(
+                    "ClientHello timeout: no ClientHello from {:?} within {:?} on '{}', action: {:?}",
+                    client_addr,
+                    limits.client_hello_timeout,
+                    proxy.name,
+                    limits.client_hello_timeout_action
+                );
+                match limits.client_hello_timeout_action {
+                    ClientHelloTimeoutAction::Close => {
+                        record.reason = CloseReason::ClientHelloTimeout;
+                        return Ok(());
                     }
+                    ClientHelloTimeoutAction::Default => ClientHelloInfo::default(),
                 }
-                (matched, via_override)
             }
-            None => (proxy.default_action.clone(), None),
         }
     } else {
-        (proxy.default_action.clone(), None)
+        ClientHelloInfo::default()
     };
 
-    // Per-SNI via takes precedence over the server-level via.
-    let effective_via = sni_via_override.unwrap_or(&proxy.via);
-
-    // Determine the CONNECT target for the upstream HTTP proxy:
-    //   use_sni_as_target=true  → "{first_sni}:{target_port}" (dynamic, per-connection)
-    //   use_sni_as_target=false → via.target if non-empty (static config)
-    //   otherwise               → None (direct TCP forward, no CONNECT)
-    let connect_target: Option<String> = if effective_via.use_sni_as_target {
-        snis.first()
-            .map(|sni| format!("{}:{}", sni, effective_via.target_port))
-    } else if !effective_via.target.is_empty() {
-        Some(effective_via.target.clone())
-    } else {
-        None
+    // Route to the upstream name based on SNI map/rules (or fall back to
+    // default) and derive the CONNECT target from the effective via.
+    let Route {
+        name: route_name,
+        upstream_name,
+        fallbacks,
+        via: effective_via,
+        connect_target,
+    } = {
+        let span = info_span!("route", sni = Empty, route = Empty, upstream = Empty);
+        let route = span.in_scope(|| route(proxy, &hello.snis, &hello.alpn));
+        if let Some(sni) = hello.snis.first() {
+            span.record("sni", sni.as_str());
+        }
+        span.record("route", route.name.as_str());
+        span.record("upstream", route.upstream_name.as_str());
+        route
+    };
+
+    // A chained CONNECT for the requested host keeps the requested port.
+    let connect_target = match &connect_request {
+        Some(request) if effective_via.use_sni_as_target => Some(request.authority.clone()),
+        _ => connect_target,
     };
 
     debug!(
         "Upstream: {} connect_target: {:?}",
         upstream_name, connect_target
     );
+    record.sni = hello.snis.first().cloned();
+    record.route = Some(route_name.clone());
+    record.upstream = Some(upstream_name.clone());
+    record.target = connect_target.clone();
+    conn.describe(record);
 
     let upstream = match proxy.upstream.get(&upstream_name) {
         Some(upstream) => upstream,
@@ -171,27 +401,53 @@ async fn accept(inbound: TcpStream, proxy: Arc<Proxy>) -> Result<(), Box<dyn Err
         }
     };
 
-    let result = upstream
-        .process(inbound, effective_via, connect_target)
-        .await;
-
-    if !is_health {
-        let active = proxy
-            .maxclients_limit
-            .saturating_sub(proxy.maxclients.available_permits());
-        match &result {
-            Ok(_) => info!(
-                "Connection closed for {:?}, active: {}/{}",
-                upstream_name, active, proxy.maxclients_limit
-            ),
-            Err(e) => error!(
-                "Connection error for {:?}, active: {}/{}: {:?}",
-                upstream_name, active, proxy.maxclients_limit, e
+    // Fallbacks are only tried if the routed upstream cannot be reached.
+    let mut chain = vec![(upstream_name.as_str(), upstream)];
+    for name in &fallbacks {
+        match proxy.upstream.get(name) {
+            Some(fallback) => chain.push((name.as_str(), fallback)),
+            None => warn!(
+                "No fallback upstream named {:?} on server {:?}, skipping it",
+                name, proxy.name
             ),
         }
     }
 
-    Ok(())
+    let ctx = ConnectionContext {
+        sni: hello.snis.first().cloned(),
+        alpn: hello.alpn.first().cloned(),
+        reply_to_connect: connect_reply,
+        id: record.conn_id,
+        labels: TunnelLabels {
+            server: proxy.name.clone(),
+            route: route_name,
+            upstream: String::new(),
+        },
+        tunnel: conn.tunnel.clone(),
+        ..ConnectionContext::new(client_addr, local_addr)
+    };
+
+    let result = process_with_fallback(&chain, inbound, effective_via, connect_target, &ctx).await;
+    record.set_tunnel(ctx.tunnel.get());
+    if is_health {
+        return result;
+    }
+
+    let active = proxy
+        .maxclients_limit
+        .saturating_sub(proxy.maxclients.available_permits());
+    match &result {
+        Ok(_) => info!(
+            "Connection closed for {:?}, active: {}/{}",
+            upstream_name, active, proxy.maxclients_limit
+        ),
+        Err(e) => error!(
+            "Connection error for {:?}, active: {}/{}: {:?}",
+            upstream_name, active, proxy.maxclients_limit, e
+        ),
+    }
+    // Logged above; `accept` records it in the access log.
+    result.map_err(|e| LoggedError(e).into())
 }
 
 #[cfg(test)]
@@ -1,13 +1,19 @@
 use super::*;
-use crate::config::{SniTarget, ViaUpstream};
+use crate::config::{ClientHelloTimeoutAction, SniTarget, ViaUpstream};
+use crate::connections::{self, Connection};
+use crate::servers::tests::base_proxy;
+use crate::telemetry::{Collector, SpanLayer, SpanRecord, Value};
 use crate::upstreams::ProxyToUpstream;
-use crate::upstreams::{MetricsEntry, Upstream};
+use crate::upstreams::{MetricsData, MetricsEntry, Upstream};
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
 use std::time::Duration;
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::sync::Semaphore;
 use tokio_util::task::TaskTracker;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::Registry;
 
 // Real TLS ClientHello for www.lirui.tech — same bytes used in tls.rs tests.
 const TLS_CLIENT_HELLO: &[u8] = &[
@@ -40,7 +46,10 @@ const TLS_CLIENT_HELLO: &[u8] = &[
     0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
     0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
     0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00,
 ];
 
 fn make_proxy(
@@ -50,16 +59,9 @@ fn make_proxy(
     sni: Option<HashMap<String, SniTarget>>,
 ) -> Arc<Proxy> {
     Arc::new(Proxy {
-        name: "test".to_string(),
-        listen: "127.0.0.1:0".parse().unwrap(),
-        protocol: "tcp".to_string(),
         tls,
         sni,
-        default_action: default_action.to_string(),
-        upstream: Arc::new(upstream),
-        via: ViaUpstream::default(),
-        maxclients: Arc::new(Semaphore::new(10)),
-        maxclients_limit: 10,
+        ..base_proxy(default_action, upstream)
     })
 }
 
@@ -72,7 +74,7 @@ async fn test_accept_missing_default_upstream() {
     let (server, _) = listener.accept().await.unwrap();
 
     let proxy = make_proxy(false, "nonexistent", HashMap::new(), None);
-    let result = accept(server, proxy).await;
+    let result = accept(server, proxy, ConnectionId::new()).await;
     assert!(result.is_err());
     assert!(result.unwrap_err().to_string().contains("not found"));
 }
@@ -92,7 +94,7 @@ async fn test_accept_tls_no_sni_map_falls_back_to_default() {
     let mut upstream = HashMap::new();
     upstream.insert("ban".to_string(), Upstream::Ban);
     let proxy = make_proxy(true, "ban", upstream, None);
-    let result = accept(server, proxy).await;
+    let result = accept(server, proxy, ConnectionId::new()).await;
     assert!(result.is_ok());
 }
 
@@ -110,11 +112,11 @@ async fn test_accept_health_server() {
     });
     let (server, _) = listener.accept().await.unwrap();
 
-    let metrics = Arc::new(vec![]);
+    let metrics = Arc::default();
     let mut upstream = HashMap::new();
     upstream.insert("health".to_string(), Upstream::Health(metrics));
     let proxy = make_proxy(false, "health", upstream, None);
-    let result = accept(server, proxy).await;
+    let result = accept(server, proxy, ConnectionId::new()).await;
     assert!(result.is_ok());
 }
 
@@ -132,19 +134,11 @@ async fn test_proxy_loop_shutdown() {
     let mut upstream = HashMap::new();
     upstream.insert("ban".to_string(), Upstream::Ban);
     let p = Arc::new(Proxy {
-        name: "test".to_string(),
         listen: listen_addr,
-        protocol: "tcp".to_string(),
-        tls: false,
-        sni: None,
-        default_action: "ban".to_string(),
-        upstream: Arc::new(upstream),
-        via: ViaUpstream::default(),
-        maxclients: Arc::new(Semaphore::new(10)),
-        maxclients_limit: 10,
+        ..base_proxy("ban", upstream)
     });
 
-    let result = proxy(p, token, tracker).await;
+    let result = proxy(tokio::sync::watch::channel(p).1, token, tracker).await;
     assert!(result.is_ok());
 }
 
@@ -168,19 +162,12 @@ async fn test_accept_use_sni_as_target() {
     let mut upstream = HashMap::new();
     upstream.insert("ban".to_string(), Upstream::Ban);
     let proxy = Arc::new(Proxy {
-        name: "test".to_string(),
-        listen: "127.0.0.1:0".parse().unwrap(),
-        protocol: "tcp".to_string(),
         tls: true,
-        sni: None,
-        default_action: "ban".to_string(),
-        upstream: Arc::new(upstream),
         via,
-        maxclients: Arc::new(Semaphore::new(10)),
-        maxclients_limit: 10,
+        ..base_proxy("ban", upstream)
     });
 
-    let result = accept(server, proxy).await;
+    let result = accept(server, proxy, ConnectionId::new()).await;
     assert!(result.is_ok());
 }
 
@@ -200,19 +187,11 @@ async fn test_accept_static_connect_target() {
     let mut upstream = HashMap::new();
     upstream.insert("ban".to_string(), Upstream::Ban);
     let proxy = Arc::new(Proxy {
-        name: "test".to_string(),
-        listen: "127.0.0.1:0".parse().unwrap(),
-        protocol: "tcp".to_string(),
-        tls: false,
-        sni: None,
-        default_action: "ban".to_string(),
-        upstream: Arc::new(upstream),
         via,
-        maxclients: Arc::new(Semaphore::new(10)),
-        maxclients_limit: 10,
+        ..base_proxy("ban", upstream)
     });
 
-    let result = accept(server, proxy).await;
+    let result = accept(server, proxy, ConnectionId::new()).await;
     assert!(result.is_ok());
 }
 
@@ -231,16 +210,13 @@ async fn test_accept_tls_sni_extended_target() {
     let mut sni_map: HashMap<String, SniTarget> = HashMap::new();
     sni_map.insert(
         "www.lirui.tech".to_string(),
-        SniTarget::Extended {
-            upstream: "ban".to_string(),
-            via: Some(ViaUpstream::default()),
-        },
+        serde_yaml_ng::from_str("{upstream: ban, via: {}}").unwrap(),
     );
 
     let mut upstream = HashMap::new();
     upstream.insert("ban".to_string(), Upstream::Ban);
     let proxy = make_proxy(true, "ban", upstream, Some(sni_map));
-    let result = accept(server, proxy).await;
+    let result = accept(server, proxy, ConnectionId::new()).await;
     assert!(result.is_ok());
 }
 
@@ -265,7 +241,7 @@ async fn test_accept_tls_sni_not_in_map() {
     let mut upstream = HashMap::new();
     upstream.insert("ban".to_string(), Upstream::Ban);
     let proxy = make_proxy(true, "ban", upstream, Some(sni_map));
-    let result = accept(server, proxy).await;
+    let result = accept(server, proxy, ConnectionId::new()).await;
     assert!(result.is_ok());
 }
 
@@ -290,8 +266,648 @@ async fn test_accept_tls_sni_simple_match() {
     let mut upstream = HashMap::new();
     upstream.insert("ban".to_string(), Upstream::Ban);
     let proxy = make_proxy(true, "ban", upstream, Some(sni_map));
-    let result = accept(server, proxy).await;
+    let result = accept(server, proxy, ConnectionId::new()).await;
+    assert!(result.is_ok());
+}
+
+// Covers: SNI matched through a wildcard key in the map
+#[tokio::test]
+async fn test_accept_tls_sni_wildcard_match() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        if let Ok(mut c) = TcpStream::connect(addr).await {
+            let _ = c.write_all(TLS_CLIENT_HELLO).await;
+        }
+    });
+    let (server, _) = listener.accept().await.unwrap();
+
+    let mut sni_map: HashMap<String, SniTarget> = HashMap::new();
+    sni_map.insert(
+        "*.lirui.tech".to_string(),
+        SniTarget::Simple("ban".to_string()),
+    );
+
+    let mut upstream = HashMap::new();
+    upstream.insert("ban".to_string(), Upstream::Ban);
+    let proxy = make_proxy(true, "echo", upstream, Some(sni_map));
+    let result = accept(server, proxy, ConnectionId::new()).await;
+    assert!(result.is_ok());
+}
+
+// Covers: client never sends a ClientHello → timeout, close action
+#[tokio::test]
+async fn test_accept_client_hello_timeout_close() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut client = TcpStream::connect(addr).await.unwrap();
+    let (server, _) = listener.accept().await.unwrap();
+
+    let mut upstream = HashMap::new();
+    upstream.insert("echo".to_string(), Upstream::Echo);
+    let mut p = (*make_proxy(true, "echo", upstream, None)).clone();
+    p.client_hello.client_hello_timeout = Duration::from_millis(50);
+    let p = Arc::new(p);
+
+    let result = accept(server, p.clone(), ConnectionId::new()).await;
+    assert!(result.is_ok());
+    assert_eq!(p.client_hello_timeouts.load(Ordering::Relaxed), 1);
+
+    // The connection was dropped rather than handed to the echo upstream.
+    let mut buf = [0u8; 1];
+    assert_eq!(client.read(&mut buf).await.unwrap(), 0);
+}
+
+// Covers: client never sends a ClientHello → timeout, route to default
+#[tokio::test]
+async fn test_accept_client_hello_timeout_default() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let _client = TcpStream::connect(addr).await.unwrap();
+    let (server, _) = listener.accept().await.unwrap();
+
+    let mut upstream = HashMap::new();
+    upstream.insert("ban".to_string(), Upstream::Ban);
+    let mut p = (*make_proxy(true, "ban", upstream, None)).clone();
+    p.client_hello.client_hello_timeout = Duration::from_millis(50);
+    p.client_hello.client_hello_timeout_action = ClientHelloTimeoutAction::Default;
+    let p = Arc::new(p);
+
+    let result = accept(server, p.clone(), ConnectionId::new()).await;
     assert!(result.is_ok());
+    assert_eq!(p.client_hello_timeouts.load(Ordering::Relaxed), 1);
+}
+
+// Covers: one byte of a ClientHello, then nothing → reassembly gives up well
+// before client_hello_timeout and the connection goes to default, which is
+// not counted as a timeout
+#[tokio::test]
+async fn test_accept_client_hello_stalled_after_one_byte_routes_to_default() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut client = TcpStream::connect(addr).await.unwrap();
+    client.write_all(&TLS_CLIENT_HELLO[..1]).await.unwrap();
+    let (server, _) = listener.accept().await.unwrap();
+
+    let mut upstream = HashMap::new();
+    upstream.insert("echo".to_string(), Upstream::Echo);
+    let mut p = (*make_proxy(true, "echo", upstream, None)).clone();
+    p.client_hello.client_hello_reassembly_timeout = Duration::from_millis(50);
+    let p = Arc::new(p);
+
+    let task = tokio::spawn({
+        let p = p.clone();
+        async move {
+            accept(server, p, ConnectionId::new())
+                .await
+                .map_err(|e| e.to_string())
+        }
+    });
+    let mut buf = [0u8; 1];
+    client.read_exact(&mut buf).await.unwrap();
+    assert_eq!(buf, TLS_CLIENT_HELLO[..1]);
+    drop(client);
+    task.await.unwrap().unwrap();
+    assert_eq!(p.client_hello_timeouts.load(Ordering::Relaxed), 0);
+}
+
+// Covers: accept_proxy_protocol strips the header before the ClientHello peek
+#[tokio::test]
+async fn test_accept_proxy_protocol_then_client_hello() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = tokio::spawn(async move {
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let mut payload = b"PROXY TCP4 192.0.2.10 198.51.100.1 51234 443\r\n".to_vec();
+        payload.extend_from_slice(TLS_CLIENT_HELLO);
+        client.write_all(&payload).await.unwrap();
+
+        // The echo upstream sees the ClientHello without the PROXY header.
+        let mut echoed = vec![0u8; TLS_CLIENT_HELLO.len()];
+        client.read_exact(&mut echoed).await.unwrap();
+        echoed
+    });
+    let (server, _) = listener.accept().await.unwrap();
+
+    let mut upstream = HashMap::new();
+    upstream.insert("echo".to_string(), Upstream::Echo);
+    upstream.insert("ban".to_string(), Upstream::Ban);
+    let mut sni = HashMap::new();
+    sni.insert(
+        "www.lirui.tech".to_string(),
+        SniTarget::Simple("echo".to_string()),
+    );
+    let mut p = (*make_proxy(true, "ban", upstream, Some(sni))).clone();
+    p.accept_proxy_protocol = true;
+
+    assert!(
+        accept(server, Arc::new(p), ConnectionId::new())
+            .await
+            .is_ok()
+    );
+    assert_eq!(client.await.unwrap(), TLS_CLIENT_HELLO);
+}
+
+// Covers: accept_proxy_protocol with a client that sends no header
+#[tokio::test]
+async fn test_accept_proxy_protocol_missing_header_closes() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut client = TcpStream::connect(addr).await.unwrap();
+    let (server, _) = listener.accept().await.unwrap();
+    client.write_all(TLS_CLIENT_HELLO).await.unwrap();
+
+    let mut upstream = HashMap::new();
+    upstream.insert("echo".to_string(), Upstream::Echo);
+    let mut p = (*make_proxy(true, "echo", upstream, None)).clone();
+    p.accept_proxy_protocol = true;
+
+    assert!(
+        accept(server, Arc::new(p), ConnectionId::new())
+            .await
+            .is_ok()
+    );
+    // Closing with unread data may surface as a reset instead of EOF.
+    let mut buf = [0u8; 1];
+    assert!(matches!(client.read(&mut buf).await, Ok(0) | Err(_)));
+}
+
+// --- http-connect ---
+
+fn make_http_connect_proxy(
+    default_action: &str,
+    upstream: HashMap<String, Upstream>,
+    sni: &[(&str, &str)],
+    via: ViaUpstream,
+) -> Arc<Proxy> {
+    let sni = sni
+        .iter()
+        .map(|(k, v)| (k.to_string(), SniTarget::Simple(v.to_string())))
+        .collect();
+    let mut p = (*make_proxy(false, default_action, upstream, Some(sni))).clone();
+    p.protocol = "http-connect".to_string();
+    p.via = via;
+    Arc::new(p)
+}
+
+/// Connect, send `request` and return everything the server sends back until
+/// it closes the connection. Also used for socks5 servers.
+async fn http_connect_roundtrip(proxy: Arc<Proxy>, request: &'static [u8]) -> Vec<u8> {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = tokio::spawn(async move {
+        let mut c = TcpStream::connect(addr).await.unwrap();
+        c.write_all(request).await.unwrap();
+        c.shutdown().await.unwrap();
+        let mut response = Vec::new();
+        let _ = c.read_to_end(&mut response).await;
+        response
+    });
+    let (server, _) = listener.accept().await.unwrap();
+    assert!(accept(server, proxy, ConnectionId::new()).await.is_ok());
+    client.await.unwrap()
+}
+
+// Covers: CONNECT host routed to echo → 200, then pipelined data is tunnelled
+#[tokio::test]
+async fn test_accept_http_connect_routes_on_host() {
+    let mut upstream = HashMap::new();
+    upstream.insert("echo".to_string(), Upstream::Echo);
+    upstream.insert("ban".to_string(), Upstream::Ban);
+    let proxy = make_http_connect_proxy(
+        "ban",
+        upstream,
+        &[("*.example.com", "echo")],
+        ViaUpstream::default(),
+    );
+
+    let response = http_connect_roundtrip(
+        proxy,
+        b"CONNECT www.example.com:443 HTTP/1.1\r\nHost: www.example.com:443\r\n\r\nping",
+    )
+    .await;
+    assert_eq!(response, b"HTTP/1.1 200 Connection established\r\n\r\nping");
+}
+
+// Covers: CONNECT host not allowed → default ban → 403
+#[tokio::test]
+async fn test_accept_http_connect_ban_forbidden() {
+    let mut upstream = HashMap::new();
+    upstream.insert("echo".to_string(), Upstream::Echo);
+    upstream.insert("ban".to_string(), Upstream::Ban);
+    let proxy = make_http_connect_proxy(
+        "ban",
+        upstream,
+        &[("*.example.com", "echo")],
+        ViaUpstream::default(),
+    );
+
+    let response = http_connect_roundtrip(proxy, b"CONNECT evil.org:443 HTTP/1.1\r\n\r\n").await;
+    assert!(response.starts_with(b"HTTP/1.1 403 Forbidden\r\n"));
+}
+
+// Covers: non-CONNECT request → 405
+#[tokio::test]
+async fn test_accept_http_connect_rejects_get() {
+    let mut upstream = HashMap::new();
+    upstream.insert("echo".to_string(), Upstream::Echo);
+    let proxy = make_http_connect_proxy("echo", upstream, &[], ViaUpstream::default());
+
+    let response = http_connect_roundtrip(proxy, b"GET / HTTP/1.1\r\n\r\n").await;
+    assert!(response.starts_with(b"HTTP/1.1 405 Method Not Allowed\r\n"));
+}
+
+// Covers: via chaining — use_sni_as_target forwards the requested host:port
+// to the upstream proxy; a failing upstream yields 502 to the client
+#[tokio::test]
+async fn test_accept_http_connect_chains_via_upstream_proxy() {
+    let parent = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let parent_addr = parent.local_addr().unwrap();
+    let seen = tokio::spawn(async move {
+        let (mut s, _) = parent.accept().await.unwrap();
+        let mut buf = vec![0u8; 1024];
+        let n = s.read(&mut buf).await.unwrap();
+        s.write_all(b"HTTP/1.1 503 Service Unavailable\r\n\r\n")
+            .await
+            .unwrap();
+        String::from_utf8_lossy(&buf[..n]).to_string()
+    });
+
+    let mut upstream = HashMap::new();
+    upstream.insert(
+        "parent".to_string(),
+        Upstream::Proxy(ProxyToUpstream::new(
+            parent_addr.to_string(),
+            "tcp".to_string(),
+        )),
+    );
+    upstream.insert("ban".to_string(), Upstream::Ban);
+    let via = ViaUpstream {
+        use_sni_as_target: true,
+        target_port: 443,
+        ..Default::default()
+    };
+    let proxy = make_http_connect_proxy("ban", upstream, &[(".example.com", "parent")], via);
+
+    let response =
+        http_connect_roundtrip(proxy, b"CONNECT api.example.com:8443 HTTP/1.1\r\n\r\n").await;
+    assert!(response.starts_with(b"HTTP/1.1 502 Bad Gateway\r\n"));
+    assert!(
+        seen.await
+            .unwrap()
+            .starts_with("CONNECT api.example.com:8443 HTTP/1.1\r\n")
+    );
+}
+
+// Covers: a chained tunnel is recorded in the metrics registry under its
+// server, route and upstream
+#[tokio::test]
+async fn test_accept_http_connect_records_metrics() {
+    let parent = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let parent_addr = parent.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut s, _) = parent.accept().await.unwrap();
+        let mut buf = vec![0u8; 1024];
+        let _ = s.read(&mut buf).await.unwrap();
+        s.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+            .await
+            .unwrap();
+        let (mut r, mut w) = tokio::io::split(s);
+        tokio::io::copy(&mut r, &mut w).await.unwrap();
+    });
+
+    let mut upstream = HashMap::new();
+    upstream.insert(
+        "parent".to_string(),
+        Upstream::Proxy(ProxyToUpstream::new(
+            parent_addr.to_string(),
+            "tcp".to_string(),
+        )),
+    );
+    upstream.insert("ban".to_string(), Upstream::Ban);
+    let via = ViaUpstream {
+        use_sni_as_target: true,
+        ..Default::default()
+    };
+    let mut proxy =
+        (*make_http_connect_proxy("ban", upstream, &[("*.metrics.example", "parent")], via))
+            .clone();
+    proxy.name = "metrics_chain".to_string();
+
+    let response = http_connect_roundtrip(
+        Arc::new(proxy),
+        b"CONNECT a.metrics.example:443 HTTP/1.1\r\n\r\nping",
+    )
+    .await;
+    assert!(response.ends_with(b"ping"));
+
+    let mut out = String::new();
+    crate::metrics::registry().write(&mut out);
+    let labels = r#"server="metrics_chain",route="*.metrics.example",upstream="parent""#;
+    for expected in [
+        format!("tpt_bytes_sent_total{{{}}} 4", labels),
+        format!("tpt_bytes_received_total{{{}}} 4", labels),
+        format!(
+            r#"tpt_upstream_connect_responses_total{{{},status="200"}} 1"#,
+            labels
+        ),
+        format!(
+            "tpt_upstream_connect_duration_seconds_count{{{}}} 1",
+            labels
+        ),
+        format!("tpt_tunnel_duration_seconds_count{{{}}} 1", labels),
+    ] {
+        assert!(out.contains(&expected), "missing {} in:\n{}", expected, out);
+    }
+}
+
+// Covers: the access record of a chained CONNECT tunnel carries the route,
+// upstream, CONNECT target and status and the bytes relayed each way
+#[tokio::test]
+async fn test_handle_fills_access_record() {
+    let parent = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let parent_addr = parent.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut s, _) = parent.accept().await.unwrap();
+        let mut buf = vec![0u8; 1024];
+        let _ = s.read(&mut buf).await.unwrap();
+        s.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+            .await
+            .unwrap();
+        let mut ping = [0u8; 4];
+        s.read_exact(&mut ping).await.unwrap();
+        s.write_all(b"pong!").await.unwrap();
+    });
+
+    let mut upstream = HashMap::new();
+    upstream.insert(
+        "parent".to_string(),
+        Upstream::Proxy(ProxyToUpstream::new(
+            parent_addr.to_string(),
+            "tcp".to_string(),
+        )),
+    );
+    upstream.insert("ban".to_string(), Upstream::Ban);
+    let via = ViaUpstream {
+        use_sni_as_target: true,
+        ..Default::default()
+    };
+    let proxy = make_http_connect_proxy("ban", upstream, &[("*.example.com", "parent")], via);
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = tokio::spawn(async move {
+        let mut c = TcpStream::connect(addr).await.unwrap();
+        c.write_all(b"CONNECT www.example.com:443 HTTP/1.1\r\n\r\nping")
+            .await
+            .unwrap();
+        c.shutdown().await.unwrap();
+        let mut response = Vec::new();
+        let _ = c.read_to_end(&mut response).await;
+        c.local_addr().unwrap()
+    });
+    let (server, _) = listener.accept().await.unwrap();
+    let mut record = AccessRecord::new(ConnectionId::new(), &proxy.name);
+    let conn = Connection::new(record.conn_id, &proxy.name);
+    handle(server, &proxy, &mut record, &conn).await.unwrap();
+
+    assert_eq!(record.client, Some(client.await.unwrap()));
+    assert_eq!(record.listen, Some(addr));
+    assert_eq!(record.sni.as_deref(), Some("www.example.com"));
+    assert_eq!(record.route.as_deref(), Some("*.example.com"));
+    assert_eq!(record.upstream.as_deref(), Some("parent"));
+    assert_eq!(record.upstream_addr, Some(parent_addr.to_string()));
+    assert_eq!(record.target.as_deref(), Some("www.example.com:443"));
+    assert_eq!(record.connect_status, Some(200));
+    assert_eq!((record.bytes_tx, record.bytes_rx), (4, 5));
+    assert!(record.connect_ms.is_some());
+    assert_eq!(record.reason, CloseReason::Closed);
+
+    // The admin API sees the same.
+    let snapshot = conn.snapshot();
+    assert_eq!(snapshot.route.as_deref(), Some("*.example.com"));
+    assert_eq!(snapshot.upstream_addr, Some(parent_addr.to_string()));
+    assert_eq!((snapshot.bytes_tx, snapshot.bytes_rx), (4, 5));
+}
+
+// Covers: a chained CONNECT tunnel is traced as accept → route, resolve,
+// connect, http_connect and relay spans in one trace
+#[tokio::test]
+async fn test_accept_traces_pipeline() {
+    let parent = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let parent_addr = parent.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut s, _) = parent.accept().await.unwrap();
+        let mut buf = vec![0u8; 1024];
+        let _ = s.read(&mut buf).await.unwrap();
+        s.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+            .await
+            .unwrap();
+        let (mut r, mut w) = tokio::io::split(s);
+        tokio::io::copy(&mut r, &mut w).await.unwrap();
+    });
+
+    let mut upstream = HashMap::new();
+    upstream.insert(
+        "parent".to_string(),
+        Upstream::Proxy(ProxyToUpstream::new(
+            parent_addr.to_string(),
+            "tcp".to_string(),
+        )),
+    );
+    upstream.insert("ban".to_string(), Upstream::Ban);
+    let via = ViaUpstream {
+        use_sni_as_target: true,
+        ..Default::default()
+    };
+    let proxy = make_http_connect_proxy("ban", upstream, &[("*.example.com", "parent")], via);
+
+    let collector = Arc::new(Collector::default());
+    let _exporting = collector.exporting();
+    let _subscriber = tracing::subscriber::set_default(
+        Registry::default().with(SpanLayer::new(collector.clone())),
+    );
+    let response =
+        http_connect_roundtrip(proxy, b"CONNECT www.example.com:443 HTTP/1.1\r\n\r\nping").await;
+    assert!(response.ends_with(b"ping"));
+
+    let spans = collector.take();
+    let names: Vec<&str> = spans.iter().map(|s| s.name).collect();
+    assert_eq!(
+        names,
+        [
+            "route",
+            "resolve",
+            "connect",
+            "http_connect",
+            "relay",
+            "accept"
+        ]
+    );
+    let root = spans.last().unwrap();
+    assert_eq!(root.parent_span_id, None);
+    for span in &spans[..spans.len() - 1] {
+        assert_eq!(span.trace_id, root.trace_id);
+        assert_eq!(span.parent_span_id, Some(root.span_id), "{}", span.name);
+    }
+    let attribute = |name: &str, key: &str| {
+        spans
+            .iter()
+            .find(|s| s.name == name)
+            .and_then(|s| s.attributes.iter().find(|(k, _)| *k == key))
+            .map(|(_, v)| v.clone())
+    };
+    assert_eq!(
+        attribute("route", "route"),
+        Some(Value::Str("*.example.com".to_string()))
+    );
+    assert_eq!(attribute("http_connect", "status"), Some(Value::Int(200)));
+    assert_eq!(attribute("relay", "bytes_tx"), Some(Value::Int(4)));
+    assert_eq!(
+        attribute("accept", "reason"),
+        Some(Value::Str("closed".to_string()))
+    );
+}
+
+// Covers: a client's traceparent parents the accept span, and the chained
+// CONNECT carries the trace on, so both proxies of a two-hop chain report
+// one trace
+#[tokio::test]
+async fn test_accept_propagates_trace_context() {
+    let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target_addr = target.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut s, _) = target.accept().await.unwrap();
+        let (mut r, mut w) = s.split();
+        let _ = tokio::io::copy(&mut r, &mut w).await;
+    });
+
+    let via = ViaUpstream {
+        use_sni_as_target: true,
+        ..Default::default()
+    };
+    let second = make_http_connect_proxy(
+        "direct",
+        HashMap::from([("direct".to_string(), Upstream::Direct)]),
+        &[],
+        via.clone(),
+    );
+    let second_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let second_addr = second_listener.local_addr().unwrap();
+    let second_hop = tokio::spawn(async move {
+        let (server, _) = second_listener.accept().await.unwrap();
+        accept(server, second, ConnectionId::new()).await.unwrap();
+    });
+    let first = make_http_connect_proxy(
+        "second",
+        HashMap::from([(
+            "second".to_string(),
+            Upstream::Proxy(ProxyToUpstream::new(
+                second_addr.to_string(),
+                "tcp".to_string(),
+            )),
+        )]),
+        &[],
+        via,
+    );
+
+    let collector = Arc::new(Collector::default());
+    let _exporting = collector.exporting();
+    let _subscriber = tracing::subscriber::set_default(
+        Registry::default().with(SpanLayer::new(collector.clone())),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = tokio::spawn(async move {
+        let mut c = TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "CONNECT {} HTTP/1.1\r\n\
+             traceparent: 00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01\r\n\r\nping",
+            target_addr
+        );
+        c.write_all(request.as_bytes()).await.unwrap();
+        c.shutdown().await.unwrap();
+        let mut response = Vec::new();
+        let _ = c.read_to_end(&mut response).await;
+        response
+    });
+    let (server, _) = listener.accept().await.unwrap();
+    accept(server, first, ConnectionId::new()).await.unwrap();
+    second_hop.await.unwrap();
+    assert!(client.await.unwrap().ends_with(b"ping"));
+
+    let spans = collector.take();
+    assert!(
+        spans
+            .iter()
+            .all(|s| s.trace_id == 0x0af7651916cd43dd8448eb211c80319c),
+        "{:?}",
+        spans
+    );
+    let accepts: Vec<&SpanRecord> = spans.iter().filter(|s| s.name == "accept").collect();
+    let chained = spans.iter().find(|s| s.name == "http_connect").unwrap();
+    assert_eq!(accepts.len(), 2);
+    assert!(accepts.iter().all(|s| s.server));
+    // The second hop closes first.
+    assert_eq!(accepts[0].parent_span_id, Some(chained.span_id));
+    assert_eq!(accepts[1].parent_span_id, Some(0xb7ad6b7169203331));
+}
+
+// Covers: a request rejected before routing is recorded with its reason
+#[tokio::test]
+async fn test_handle_records_rejected_request() {
+    let proxy = make_http_connect_proxy("ban", HashMap::new(), &[], ViaUpstream::default());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = tokio::spawn(async move {
+        let mut c = TcpStream::connect(addr).await.unwrap();
+        c.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        let mut response = Vec::new();
+        let _ = c.read_to_end(&mut response).await;
+    });
+    let (server, _) = listener.accept().await.unwrap();
+    let mut record = AccessRecord::new(ConnectionId::new(), &proxy.name);
+    let conn = Connection::new(record.conn_id, &proxy.name);
+    handle(server, &proxy, &mut record, &conn).await.unwrap();
+    client.await.unwrap();
+
+    assert_eq!(record.reason, CloseReason::ConnectRequestRejected);
+    assert!(record.error.is_some());
+    assert!(record.route.is_none());
+}
+
+// --- socks5 ---
+
+// Covers: socks5 destination routed through the sni map → success reply,
+// then tunnelled; other destinations get "not allowed by ruleset"
+#[tokio::test]
+async fn test_accept_socks5_routes_on_destination() {
+    let mut upstream = HashMap::new();
+    upstream.insert("echo".to_string(), Upstream::Echo);
+    upstream.insert("ban".to_string(), Upstream::Ban);
+    let mut p = (*make_http_connect_proxy(
+        "ban",
+        upstream,
+        &[("*.example.com", "echo")],
+        ViaUpstream::default(),
+    ))
+    .clone();
+    p.protocol = "socks5".to_string();
+    let proxy = Arc::new(p);
+
+    let allowed = http_connect_roundtrip(
+        proxy.clone(),
+        b"\x05\x01\x00\x05\x01\x00\x03\x0fwww.example.com\x01\xbbping",
+    )
+    .await;
+    assert_eq!(
+        allowed,
+        b"\x05\x00\x05\x00\x00\x01\x00\x00\x00\x00\x00\x00ping"
+    );
+
+    let denied =
+        http_connect_roundtrip(proxy, b"\x05\x01\x00\x05\x01\x00\x03\x08evil.org\x01\xbb").await;
+    assert_eq!(&denied[..4], b"\x05\x00\x05\x02");
 }
 
 // Covers: accept() result Err arm — error log (lines 187–190)
@@ -313,7 +929,7 @@ async fn test_accept_upstream_process_error_logged() {
     let proxy = make_proxy(false, "proxy", upstream, None);
 
     // accept() always returns Ok — the Err from process() is only logged
-    let result = accept(server, proxy).await;
+    let result = accept(server, proxy, ConnectionId::new()).await;
     assert!(result.is_ok());
 }
 
@@ -330,21 +946,13 @@ async fn test_proxy_non_health_dispatch() {
     let mut upstream = HashMap::new();
     upstream.insert("ban".to_string(), Upstream::Ban);
     let p = Arc::new(Proxy {
-        name: "test".to_string(),
         listen: addr,
-        protocol: "tcp".to_string(),
-        tls: false,
-        sni: None,
-        default_action: "ban".to_string(),
-        upstream: Arc::new(upstream),
-        via: ViaUpstream::default(),
-        maxclients: Arc::new(Semaphore::new(10)),
-        maxclients_limit: 10,
+        ..base_proxy("ban", upstream)
     });
 
     let token_clone = token.clone();
     tokio::select! {
-        result = proxy(p, token_clone, tracker) => { result.unwrap(); }
+        result = proxy(tokio::sync::watch::channel(p).1, token_clone, tracker) => { result.unwrap(); }
         _ = async {
             tokio::time::sleep(Duration::from_millis(20)).await;
             let _ = TcpStream::connect(addr).await;
@@ -364,30 +972,27 @@ async fn test_proxy_health_dispatch() {
 
     let token = CancellationToken::new();
     let tracker = TaskTracker::new();
-    let metrics = Arc::new(vec![MetricsEntry {
-        name: "test".to_string(),
-        listen: addr.to_string(),
-        maxclients_limit: 10,
-        semaphore: Arc::new(Semaphore::new(10)),
-    }]);
+    let metrics = Arc::new(MetricsData {
+        servers: vec![MetricsEntry {
+            name: "test".to_string(),
+            listen: addr.to_string(),
+            maxclients_limit: 10,
+            semaphore: Arc::new(Semaphore::new(10)),
+            client_hello_timeouts: Arc::new(AtomicU64::new(0)),
+        }],
+        upstreams: Vec::new(),
+        admin: Default::default(),
+    });
     let mut upstream = HashMap::new();
     upstream.insert("health".to_string(), Upstream::Health(metrics));
     let p = Arc::new(Proxy {
-        name: "test".to_string(),
         listen: addr,
-        protocol: "tcp".to_string(),
-        tls: false,
-        sni: None,
-        default_action: "health".to_string(),
-        upstream: Arc::new(upstream),
-        via: ViaUpstream::default(),
-        maxclients: Arc::new(Semaphore::new(10)),
-        maxclients_limit: 10,
+        ..base_proxy("health", upstream)
     });
 
     let token_clone = token.clone();
     tokio::select! {
-        result = proxy(p, token_clone, tracker) => { result.unwrap(); }
+        result = proxy(tokio::sync::watch::channel(p).1, token_clone, tracker) => { result.unwrap(); }
         _ = async {
             tokio::time::sleep(Duration::from_millis(20)).await;
             let _ = TcpStream::connect(addr).await;
@@ -410,21 +1015,15 @@ async fn test_proxy_maxclients_exceeded() {
     let mut upstream = HashMap::new();
     upstream.insert("ban".to_string(), Upstream::Ban);
     let p = Arc::new(Proxy {
-        name: "test".to_string(),
         listen: addr,
-        protocol: "tcp".to_string(),
-        tls: false,
-        sni: None,
-        default_action: "ban".to_string(),
-        upstream: Arc::new(upstream),
-        via: ViaUpstream::default(),
         maxclients: Arc::new(Semaphore::new(0)), // no permits → all connections rejected
         maxclients_limit: 0,
+        ..base_proxy("ban", upstream)
     });
 
     let token_clone = token.clone();
     tokio::select! {
-        result = proxy(p, token_clone, tracker) => { result.unwrap(); }
+        result = proxy(tokio::sync::watch::channel(p).1, token_clone, tracker) => { result.unwrap(); }
         _ = async {
             tokio::time::sleep(Duration::from_millis(20)).await;
             let _ = TcpStream::connect(addr).await;
@@ -434,3 +1033,64 @@ async fn test_proxy_maxclients_exceeded() {
         } => {}
     }
 }
+
+// Covers: accept() closes a connection killed through the registry and
+// unregisters it
+#[tokio::test]
+async fn test_accept_killed_connection_closes() {
+    let mut upstream = HashMap::new();
+    upstream.insert("echo".to_string(), Upstream::Echo);
+    let proxy = make_proxy(false, "echo", upstream, None);
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut client = TcpStream::connect(addr).await.unwrap();
+    let (server, _) = listener.accept().await.unwrap();
+    let id = ConnectionId::new();
+    let accepted =
+        tokio::spawn(async move { accept(server, proxy, id).await.map_err(|e| e.to_string()) });
+
+    client.write_all(b"x").await.unwrap();
+    let mut buf = [0u8; 1];
+    client.read_exact(&mut buf).await.unwrap();
+    assert!(connections::registry().kill(id));
+
+    assert_eq!(client.read(&mut buf).await.unwrap(), 0);
+    accepted.await.unwrap().unwrap();
+    assert!(!connections::registry().kill(id));
+}
+
+// Covers: proxy() closes new connections on a draining server
+#[tokio::test]
+async fn test_proxy_draining_server_closes_new_connections() {
+    let tmp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = tmp.local_addr().unwrap();
+    drop(tmp);
+
+    let token = CancellationToken::new();
+    let tracker = TaskTracker::new();
+    let mut upstream = HashMap::new();
+    upstream.insert("echo".to_string(), Upstream::Echo);
+    let mut p = (*make_proxy(false, "echo", upstream, None)).clone();
+    p.name = "draining".to_string();
+    p.listen = addr;
+
+    let token_clone = token.clone();
+    tokio::select! {
+        result = proxy(tokio::sync::watch::channel(Arc::new(p)).1, token_clone, tracker) => { result.unwrap(); }
+        _ = async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            connections::registry().set_draining("draining", true);
+            let mut conn = TcpStream::connect(addr).await.unwrap();
+            let mut buf = [0u8; 1];
+            assert_eq!(conn.read(&mut buf).await.unwrap(), 0);
+
+            connections::registry().set_draining("draining", false);
+            let mut conn = TcpStream::connect(addr).await.unwrap();
+            conn.write_all(b"x").await.unwrap();
+            conn.read_exact(&mut buf).await.unwrap();
+            token.cancel();
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        } => {}
+    }
+}
@@ -1,56 +1,206 @@
 use log::{debug, warn};
+use std::time::Duration;
 use tls_parser::{
-    TlsMessage, TlsMessageHandshake, parse_tls_extensions, parse_tls_raw_record,
-    parse_tls_record_with_header,
+    TlsCipherSuiteID, TlsExtension, TlsMessage, TlsMessageHandshake, TlsVersion,
+    parse_tls_extensions, parse_tls_message_handshake,
 };
+use tokio::io;
+use tokio::net::TcpStream;
+use tokio::time::Instant;
 
-pub fn get_sni(buf: &[u8]) -> Vec<String> {
-    let mut snis: Vec<String> = Vec::new();
-    match parse_tls_raw_record(buf) {
-        Ok((_, ref r)) => match parse_tls_record_with_header(r.data, &r.hdr) {
-            Ok((_, ref msg_list)) => {
-                for msg in msg_list {
-                    if let TlsMessage::Handshake(TlsMessageHandshake::ClientHello(ref content)) =
-                        *msg
-                    {
-                        debug!("TLS ClientHello version: {}", content.version);
-                        let ext = parse_tls_extensions(content.ext.unwrap_or(b""));
-                        match ext {
-                            Ok((_, ref extensions)) => {
-                                for ext in extensions {
-                                    if let tls_parser::TlsExtension::SNI(ref v) = *ext {
-                                        for &(t, sni) in v {
-                                            match String::from_utf8(sni.to_vec()) {
-                                                Ok(s) => {
-                                                    debug!("TLS SNI: {} {}", t, s);
-                                                    snis.push(s);
-                                                }
-                                                Err(e) => {
-                                                    warn!("Failed to parse SNI: {} {}", t, e);
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                warn!("TLS extensions error: {}", e);
-                            }
-                        }
+const RECORD_HEADER_LEN: usize = 5;
+const HANDSHAKE_HEADER_LEN: usize = 4;
+const CONTENT_TYPE_HANDSHAKE: u8 = 0x16;
+
+/// Delay between peeks while waiting for the rest of a fragmented ClientHello.
+/// `peek()` returns immediately once any data is buffered, so it cannot be
+/// used to wait for *more* data.
+const PEEK_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Routing-relevant fields of a peeked TLS ClientHello.
+#[derive(Debug, Default, Clone)]
+pub struct ClientHelloInfo {
+    pub snis: Vec<String>,
+    /// ALPN protocol names offered by the client, in client preference order.
+    pub alpn: Vec<String>,
+    /// Versions from the `supported_versions` extension, or the legacy
+    /// ClientHello version if the extension is absent.
+    pub versions: Vec<TlsVersion>,
+    pub cipher_suites: Vec<TlsCipherSuiteID>,
+}
+
+/// How much of the first handshake message a buffer holds.
+#[derive(Debug, PartialEq, Eq)]
+pub enum HelloStatus {
+    Complete,
+    Incomplete,
+    /// Not a TLS handshake (or interrupted by a non-handshake record).
+    NotHandshake,
+}
+
+// ---------------------------------------------------------------------------
+// Record-layer reassembly
+//
+// A ClientHello may be split across several TLS records (each at most 16 KiB)
+// and those records across several TCP segments. Concatenate the handshake
+// record payloads until the handshake message announced in the first four
+// bytes is complete.
+// ---------------------------------------------------------------------------
+fn reassemble(buf: &[u8]) -> (Vec<u8>, HelloStatus) {
+    let mut payload = Vec::new();
+    let mut rest = buf;
+    loop {
+        if payload.len() >= HANDSHAKE_HEADER_LEN {
+            let msg_len = u32::from_be_bytes([0, payload[1], payload[2], payload[3]]) as usize;
+            if payload.len() >= HANDSHAKE_HEADER_LEN + msg_len {
+                return (payload, HelloStatus::Complete);
+            }
+        }
+        if rest.is_empty() {
+            return (payload, HelloStatus::Incomplete);
+        }
+        if rest[0] != CONTENT_TYPE_HANDSHAKE {
+            return (payload, HelloStatus::NotHandshake);
+        }
+        if rest.len() < RECORD_HEADER_LEN {
+            return (payload, HelloStatus::Incomplete);
+        }
+        let record_len = u16::from_be_bytes([rest[3], rest[4]]) as usize;
+        let Some(record) = rest.get(RECORD_HEADER_LEN..RECORD_HEADER_LEN + record_len) else {
+            return (payload, HelloStatus::Incomplete);
+        };
+        payload.extend_from_slice(record);
+        rest = &rest[RECORD_HEADER_LEN + record_len..];
+    }
+}
+
+fn hello_status(buf: &[u8]) -> HelloStatus {
+    reassemble(buf).1
+}
+
+/// Peek at the inbound stream until it holds a complete ClientHello, the
+/// data turns out not to be a TLS handshake, `max_size` bytes are buffered,
+/// or `reassembly_timeout` passes after the first bytes arrived. Returns the
+/// buffered bytes with how much of the ClientHello they hold.
+///
+/// Nothing is consumed: the returned bytes are still in the socket buffer.
+pub async fn peek_client_hello(
+    inbound: &TcpStream,
+    max_size: usize,
+    reassembly_timeout: Duration,
+) -> io::Result<(Vec<u8>, HelloStatus)> {
+    let mut buf = vec![0u8; max_size];
+    // Blocks until the client has sent anything at all.
+    let mut n = inbound.peek(&mut buf).await?;
+    let deadline = Instant::now() + reassembly_timeout;
+
+    let status = loop {
+        let status = hello_status(&buf[..n]);
+        match status {
+            HelloStatus::Complete | HelloStatus::NotHandshake => break status,
+            HelloStatus::Incomplete if n >= max_size => {
+                warn!(
+                    "ClientHello exceeds client_hello_max_size ({} bytes)",
+                    max_size
+                );
+                break status;
+            }
+            HelloStatus::Incomplete if n == 0 || Instant::now() >= deadline => {
+                warn!(
+                    "Incomplete ClientHello after {:?} ({} bytes)",
+                    reassembly_timeout, n
+                );
+                break status;
+            }
+            HelloStatus::Incomplete => {}
+        }
+        tokio::time::sleep(PEEK_RETRY_INTERVAL).await;
+        let previous = n;
+        n = inbound.peek(&mut buf).await?;
+        if n > previous {
+            debug!("ClientHello reassembly: {} bytes buffered", n);
+        }
+    };
+
+    buf.truncate(n);
+    Ok((buf, status))
+}
+
+/// Extract the routing-relevant fields. `None` if `buf` does not hold a
+/// complete, well-formed ClientHello.
+pub fn parse_client_hello(buf: &[u8]) -> Option<ClientHelloInfo> {
+    let mut info = ClientHelloInfo::default();
+    let (payload, status) = reassemble(buf);
+    if status != HelloStatus::Complete {
+        warn!(
+            "Failed to parse TLS: {:?} ClientHello ({} bytes)",
+            status,
+            buf.len()
+        );
+        return None;
+    }
+
+    match parse_tls_message_handshake(&payload) {
+        Ok((_, TlsMessage::Handshake(TlsMessageHandshake::ClientHello(ref content)))) => {
+            debug!("TLS ClientHello version: {}", content.version);
+            info.cipher_suites.clone_from(&content.ciphers);
+            let ext = parse_tls_extensions(content.ext.unwrap_or(b""));
+            match ext {
+                Ok((_, ref extensions)) => {
+                    for ext in extensions {
+                        collect_extension(ext, &mut info);
                     }
                 }
+                Err(e) => {
+                    warn!("TLS extensions error: {}", e);
+                }
             }
-            Err(err) => {
-                warn!("Failed to parse TLS: {}", err);
+            if info.versions.is_empty() {
+                info.versions.push(content.version);
             }
-        },
+        }
+        Ok(_) => {
+            warn!("First TLS handshake message is not a ClientHello");
+            return None;
+        }
         Err(err) => {
             warn!("Failed to parse TLS: {}", err);
+            return None;
         }
     }
 
-    debug!("Found SNIs: {:?}", &snis);
-    snis
+    debug!("Found SNIs: {:?} ALPN: {:?}", &info.snis, &info.alpn);
+    Some(info)
+}
+
+fn collect_extension(ext: &TlsExtension, info: &mut ClientHelloInfo) {
+    match ext {
+        TlsExtension::SNI(v) => {
+            for &(t, sni) in v {
+                match String::from_utf8(sni.to_vec()) {
+                    Ok(s) => {
+                        debug!("TLS SNI: {} {}", t, s);
+                        info.snis.push(s);
+                    }
+                    Err(e) => {
+                        warn!("Failed to parse SNI: {} {}", t, e);
+                    }
+                }
+            }
+        }
+        TlsExtension::ALPN(v) => {
+            for proto in v {
+                match std::str::from_utf8(proto) {
+                    Ok(s) => info.alpn.push(s.to_string()),
+                    Err(e) => warn!("Failed to parse ALPN protocol: {}", e),
+                }
+            }
+        }
+        TlsExtension::SupportedVersions(v) => {
+            info.versions.extend(v.iter().copied());
+        }
+        _ => {}
+    }
 }
 
 #[cfg(test)]
@@ -59,75 +209,191 @@ mod tests {
 
     #[test]
     fn test_empty_buffer_returns_no_sni() {
-        let snis = get_sni(&[]);
-        assert!(snis.is_empty());
+        assert!(parse_client_hello(&[]).is_none());
     }
 
     #[test]
     fn test_garbage_bytes_returns_no_sni() {
         let buf = [0xde, 0xad, 0xbe, 0xef, 0x01, 0x02, 0x03];
-        let snis = get_sni(&buf);
-        assert!(snis.is_empty());
+        assert!(parse_client_hello(&buf).is_none());
     }
 
     #[test]
     fn test_partial_tls_record_returns_no_sni() {
         // Valid TLS record type (0x16 = handshake) but truncated
         let buf = [0x16, 0x03, 0x01, 0x00, 0x10];
-        let snis = get_sni(&buf);
-        assert!(snis.is_empty());
+        assert!(parse_client_hello(&buf).is_none());
     }
 
     #[test]
     fn test_non_handshake_record_returns_no_sni() {
         // Application data record (0x17), not a ClientHello
         let buf = [0x17, 0x03, 0x03, 0x00, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f];
-        let snis = get_sni(&buf);
-        assert!(snis.is_empty());
+        assert!(parse_client_hello(&buf).is_none());
+    }
+
+    /// Re-frame the single-record CLIENT_HELLO as two TLS records, split
+    /// `at` bytes into the handshake payload.
+    fn split_into_records(at: usize) -> Vec<u8> {
+        let payload = &CLIENT_HELLO[RECORD_HEADER_LEN..];
+        let mut out = Vec::new();
+        for part in [&payload[..at], &payload[at..]] {
+            out.extend_from_slice(&[CONTENT_TYPE_HANDSHAKE, 0x03, 0x01]);
+            out.extend_from_slice(&(part.len() as u16).to_be_bytes());
+            out.extend_from_slice(part);
+        }
+        out
+    }
+
+    #[test]
+    fn test_hello_status() {
+        assert_eq!(hello_status(&CLIENT_HELLO), HelloStatus::Complete);
+        assert_eq!(hello_status(&CLIENT_HELLO[..300]), HelloStatus::Incomplete);
+        assert_eq!(hello_status(&CLIENT_HELLO[..3]), HelloStatus::Incomplete);
+        assert_eq!(
+            hello_status(b"GET / HTTP/1.1\r\n"),
+            HelloStatus::NotHandshake
+        );
+    }
+
+    #[test]
+    fn test_sni_extract_multi_record() {
+        let buf = split_into_records(100);
+        assert_eq!(hello_status(&buf[..110]), HelloStatus::Incomplete);
+        assert_eq!(hello_status(&buf), HelloStatus::Complete);
+        let info = parse_client_hello(&buf).unwrap();
+        assert_eq!(info.snis, vec!["www.lirui.tech"]);
+        assert_eq!(info.alpn, vec!["h2", "http/1.1"]);
+    }
+
+    #[test]
+    fn test_multi_record_interrupted_by_other_record() {
+        let mut buf = split_into_records(100)[..105].to_vec();
+        buf.extend_from_slice(&[0x17, 0x03, 0x03, 0x00, 0x01, 0x00]);
+        assert_eq!(hello_status(&buf), HelloStatus::NotHandshake);
+        assert!(parse_client_hello(&buf).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_peek_client_hello_across_segments() {
+        use tokio::io::AsyncWriteExt;
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let buf = split_into_records(100);
+        let client = tokio::spawn(async move {
+            let mut c = TcpStream::connect(addr).await.unwrap();
+            c.write_all(&buf[..50]).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            c.write_all(&buf[50..]).await.unwrap();
+            c
+        });
+        let (server, _) = listener.accept().await.unwrap();
+
+        let (peeked, status) = peek_client_hello(&server, 16384, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(status, HelloStatus::Complete);
+        assert_eq!(peeked.len(), CLIENT_HELLO.len() + RECORD_HEADER_LEN);
+        let info = parse_client_hello(&peeked).unwrap();
+        assert_eq!(info.snis, vec!["www.lirui.tech"]);
+        drop(client.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_peek_client_hello_reassembly_timeout() {
+        use tokio::io::AsyncWriteExt;
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = tokio::spawn(async move {
+            let mut c = TcpStream::connect(addr).await.unwrap();
+            c.write_all(&CLIENT_HELLO[..100]).await.unwrap();
+            c
+        });
+        let (server, _) = listener.accept().await.unwrap();
+
+        let (peeked, status) = peek_client_hello(&server, 16384, Duration::from_millis(50))
+            .await
+            .unwrap();
+        assert_eq!((peeked.len(), status), (100, HelloStatus::Incomplete));
+        drop(client.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_peek_client_hello_max_size() {
+        use tokio::io::AsyncWriteExt;
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = tokio::spawn(async move {
+            let mut c = TcpStream::connect(addr).await.unwrap();
+            c.write_all(&CLIENT_HELLO).await.unwrap();
+            c
+        });
+        let (server, _) = listener.accept().await.unwrap();
+
+        let (peeked, status) = peek_client_hello(&server, 256, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!((peeked.len(), status), (256, HelloStatus::Incomplete));
+        drop(client.await.unwrap());
     }
 
     #[test]
     fn test_sni_extract() {
-        const BUF: [u8; 517] = [
-            0x16, 0x03, 0x01, 0x02, 0x00, 0x01, 0x00, 0x01, 0xfc, 0x03, 0x03, 0x35, 0x7a, 0xba,
-            0x3d, 0x89, 0xd2, 0x5e, 0x7a, 0xa2, 0xd4, 0xe5, 0x6d, 0xd5, 0xa3, 0x98, 0x41, 0xb0,
-            0xae, 0x41, 0xfc, 0xe6, 0x64, 0xfd, 0xae, 0x0b, 0x27, 0x6d, 0x90, 0xa8, 0x0a, 0xfa,
-            0x90, 0x20, 0x59, 0x6f, 0x13, 0x18, 0x4a, 0xd1, 0x1c, 0xc4, 0x83, 0x8c, 0xfc, 0x93,
-            0xac, 0x6b, 0x3b, 0xac, 0x67, 0xd0, 0x36, 0xb0, 0xa2, 0x1b, 0x04, 0xf7, 0xde, 0x02,
-            0xfb, 0x96, 0x1e, 0xdc, 0x76, 0xa8, 0x00, 0x20, 0x2a, 0x2a, 0x13, 0x01, 0x13, 0x02,
-            0x13, 0x03, 0xc0, 0x2b, 0xc0, 0x2f, 0xc0, 0x2c, 0xc0, 0x30, 0xcc, 0xa9, 0xcc, 0xa8,
-            0xc0, 0x13, 0xc0, 0x14, 0x00, 0x9c, 0x00, 0x9d, 0x00, 0x2f, 0x00, 0x35, 0x01, 0x00,
-            0x01, 0x93, 0xea, 0xea, 0x00, 0x00, 0x00, 0x00, 0x00, 0x13, 0x00, 0x11, 0x00, 0x00,
-            0x0e, 0x77, 0x77, 0x77, 0x2e, 0x6c, 0x69, 0x72, 0x75, 0x69, 0x2e, 0x74, 0x65, 0x63,
-            0x68, 0x00, 0x17, 0x00, 0x00, 0xff, 0x01, 0x00, 0x01, 0x00, 0x00, 0x0a, 0x00, 0x0a,
-            0x00, 0x08, 0xba, 0xba, 0x00, 0x1d, 0x00, 0x17, 0x00, 0x18, 0x00, 0x0b, 0x00, 0x02,
-            0x01, 0x00, 0x00, 0x23, 0x00, 0x00, 0x00, 0x10, 0x00, 0x0e, 0x00, 0x0c, 0x02, 0x68,
-            0x32, 0x08, 0x68, 0x74, 0x74, 0x70, 0x2f, 0x31, 0x2e, 0x31, 0x00, 0x05, 0x00, 0x05,
-            0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0d, 0x00, 0x12, 0x00, 0x10, 0x04, 0x03, 0x08,
-            0x04, 0x04, 0x01, 0x05, 0x03, 0x08, 0x05, 0x05, 0x01, 0x08, 0x06, 0x06, 0x01, 0x00,
-            0x12, 0x00, 0x00, 0x00, 0x33, 0x00, 0x2b, 0x00, 0x29, 0xba, 0xba, 0x00, 0x01, 0x00,
-            0x00, 0x1d, 0x00, 0x20, 0x3b, 0x45, 0xf9, 0xbc, 0x6e, 0x23, 0x86, 0x41, 0xa5, 0xb2,
-            0xf5, 0x03, 0xec, 0x67, 0x4a, 0xd7, 0x9a, 0x17, 0x9f, 0x0c, 0x38, 0x6d, 0x36, 0xf3,
-            0x4e, 0x5d, 0xa4, 0x7d, 0x15, 0x79, 0xa4, 0x3f, 0x00, 0x2d, 0x00, 0x02, 0x01, 0x01,
-            0x00, 0x2b, 0x00, 0x0b, 0x0a, 0xba, 0xba, 0x03, 0x04, 0x03, 0x03, 0x03, 0x02, 0x03,
-            0x01, 0x00, 0x1b, 0x00, 0x03, 0x02, 0x00, 0x02, 0x44, 0x69, 0x00, 0x05, 0x00, 0x03,
-            0x02, 0x68, 0x32, 0xda, 0xda, 0x00, 0x01, 0x00, 0x00, 0x15, 0x00, 0xc5, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-        ];
-        let sni = get_sni(&BUF);
-        assert!(sni[0] == *"www.lirui.tech");
+        let info = parse_client_hello(&CLIENT_HELLO).unwrap();
+        assert!(info.snis[0] == *"www.lirui.tech");
+    }
+
+    const CLIENT_HELLO: [u8; 517] = [
+        0x16, 0x03, 0x01, 0x02, 0x00, 0x01, 0x00, 0x01, 0xfc, 0x03, 0x03, 0x35, 0x7a, 0xba, 0x3d,
+        0x89, 0xd2, 0x5e, 0x7a, 0xa2, 0xd4, 0xe5, 0x6d, 0xd5, 0xa3, 0x98, 0x41, 0xb0, 0xae, 0x41,
+        0xfc, 0xe6, 0x64, 0xfd, 0xae, 0x0b, 0x27, 0x6d, 0x90, 0xa8, 0x0a, 0xfa, 0x90, 0x20, 0x59,
+        0x6f, 0x13, 0x18, 0x4a, 0xd1, 0x1c, 0xc4, 0x83, 0x8c, 0xfc, 0x93, 0xac, 0x6b, 0x3b, 0xac,
+        0x67, 0xd0, 0x36, 0xb0, 0xa2, 0x1b, 0x04, 0xf7, 0xde, 0x02, 0xfb, 0x96, 0x1e, 0xdc, 0x76,
+        0xa8, 0x00, 0x20, 0x2a, 0x2a, 0x13, 0x01, 0x13, 0x02, 0x13, 0x03, 0xc0, 0x2b, 0xc0, 0x2f,
+        0xc0, 0x2c, 0xc0, 0x30, 0xcc, 0xa9, 0xcc, 0xa8, 0xc0, 0x13, 0xc0, 0x14, 0x00, 0x9c, 0x00,
+        0x9d, 0x00, 0x2f, 0x00, 0x35, 0x01, 0x00, 0x01, 0x93, 0xea, 0xea, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x13, 0x00, 0x11, 0x00, 0x00, 0x0e, 0x77, 0x77, 0x77, 0x2e, 0x6c, 0x69, 0x72, 0x75,
+        0x69, 0x2e, 0x74, 0x65, 0x63, 0x68, 0x00, 0x17, 0x00, 0x00, 0xff, 0x01, 0x00, 0x01, 0x00,
+        0x00, 0x0a, 0x00, 0x0a, 0x00, 0x08, 0xba, 0xba, 0x00, 0x1d, 0x00, 0x17, 0x00, 0x18, 0x00,
+        0x0b, 0x00, 0x02, 0x01, 0x00, 0x00, 0x23, 0x00, 0x00, 0x00, 0x10, 0x00, 0x0e, 0x00, 0x0c,
+        0x02, 0x68, 0x32, 0x08, 0x68, 0x74, 0x74, 0x70, 0x2f, 0x31, 0x2e, 0x31, 0x00, 0x05, 0x00,
+        0x05, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0d, 0x00, 0x12, 0x00, 0x10, 0x04, 0x03, 0x08,
+        0x04, 0x04, 0x01, 0x05, 0x03, 0x08, 0x05, 0x05, 0x01, 0x08, 0x06, 0x06, 0x01, 0x00, 0x12,
+        0x00, 0x00, 0x00, 0x33, 0x00, 0x2b, 0x00, 0x29, 0xba, 0xba, 0x00, 0x01, 0x00, 0x00, 0x1d,
+        0x00, 0x20, 0x3b, 0x45, 0xf9, 0xbc, 0x6e, 0x23, 0x86, 0x41, 0xa5, 0xb2, 0xf5, 0x03, 0xec,
+        0x67, 0x4a, 0xd7, 0x9a, 0x17, 0x9f, 0x0c, 0x38, 0x6d, 0x36, 0xf3, 0x4e, 0x5d, 0xa4, 0x7d,
+        0x15, 0x79, 0xa4, 0x3f, 0x00, 0x2d, 0x00, 0x02, 0x01, 0x01, 0x00, 0x2b, 0x00, 0x0b, 0x0a,
+        0xba, 0xba, 0x03, 0x04, 0x03, 0x03, 0x03, 0x02, 0x03, 0x01, 0x00, 0x1b, 0x00, 0x03, 0x02,
+        0x00, 0x02, 0x44, 0x69, 0x00, 0x05, 0x00, 0x03, 0x02, 0x68, 0x32, 0xda, 0xda, 0x00, 0x01,
+        0x00, 0x00, 0x15, 0x00, 0xc5, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn test_client_hello_info_extract() {
+        let info = parse_client_hello(&CLIENT_HELLO).unwrap();
+        assert_eq!(info.alpn, vec!["h2", "http/1.1"]);
+        assert!(info.versions.contains(&TlsVersion::Tls13));
+        assert!(info.versions.contains(&TlsVersion::Tls12));
+        assert_eq!(info.cipher_suites.len(), 16);
+        assert_eq!(info.cipher_suites[1], TlsCipherSuiteID(0x1301));
     }
 }
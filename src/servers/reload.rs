@@ -0,0 +1,125 @@
+use log::{debug, error, info, warn};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::watch;
+use tokio::task;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+use crate::config::Config;
+use crate::connections;
+
+use super::protocol::tcp;
+use super::{Proxy, Server};
+
+// ---------------------------------------------------------------------------
+// Configuration reload
+//
+// Listeners are keyed by listen address. On reload, addresses that are gone
+// stop accepting, new ones are bound, and the rest get the new `Proxy` — with
+// its upstream map and SNI tables — for their next connection. Open tunnels
+// keep the `Proxy` they were accepted with.
+// ---------------------------------------------------------------------------
+
+struct Listener {
+    proxy: watch::Sender<Arc<Proxy>>,
+    token: CancellationToken,
+}
+
+#[derive(Default)]
+pub(super) struct Listeners(HashMap<SocketAddr, Listener>);
+
+impl Listeners {
+    /// Make the running listeners match `proxies`.
+    pub fn apply(
+        &mut self,
+        proxies: &[Arc<Proxy>],
+        token: &CancellationToken,
+        tracker: &TaskTracker,
+    ) {
+        let wanted: HashSet<SocketAddr> = proxies.iter().map(|p| p.listen).collect();
+        self.0.retain(|listen, listener| {
+            if wanted.contains(listen) {
+                return true;
+            }
+            info!(
+                "Closing server {} on {}",
+                listener.proxy.borrow().name,
+                listen
+            );
+            listener.token.cancel();
+            false
+        });
+
+        for proxy in proxies {
+            // A closed channel means the listener failed to bind; try again.
+            if let Some(listener) = self.0.get(&proxy.listen)
+                && !listener.proxy.is_closed()
+            {
+                debug!("Updating server {} on {}", proxy.name, proxy.listen);
+                listener.proxy.send_replace(proxy.clone());
+                continue;
+            }
+            let listener = Listener {
+                proxy: watch::Sender::new(proxy.clone()),
+                token: token.child_token(),
+            };
+            spawn(listener.proxy.subscribe(), listener.token.clone(), tracker);
+            self.0.insert(proxy.listen, listener);
+        }
+    }
+}
+
+fn spawn(current: watch::Receiver<Arc<Proxy>>, token: CancellationToken, tracker: &TaskTracker) {
+    let config = current.borrow().clone();
+    info!(
+        "Starting {} server {} on {}",
+        config.protocol, config.name, config.listen
+    );
+    let tracker_clone = tracker.clone();
+    tracker.spawn(async move {
+        match config.protocol.as_ref() {
+            "tcp" | "tcp4" | "tcp6" | "http-connect" | "socks5" => {
+                if let Err(e) = tcp::proxy(current, token, tracker_clone).await {
+                    error!("Failed to start {}: {}", config.name, e);
+                }
+            }
+            _ => {
+                error!("Invalid protocol: {}", config.protocol)
+            }
+        }
+    });
+}
+
+impl Server {
+    /// Load the config file again and replace this server's proxies and
+    /// upstreams. On error the running configuration is left untouched.
+    pub(super) async fn reload(&mut self) -> Result<(), Box<dyn Error>> {
+        let path = self.config_path.clone().ok_or("no config file to reload")?;
+        let config = task::spawn_blocking({
+            let path = path.clone();
+            move || Config::load(&path)
+        })
+        .await??;
+        let mut server = Server::build(config.base, &self.proxies).with_config_path(path);
+        if server.log != self.log {
+            warn!("log and log-format changes take effect on restart");
+            server.log = self.log.clone();
+        }
+        let kept: HashSet<&str> = server.proxies.iter().map(|p| p.name.as_str()).collect();
+        connections::registry().forget_draining(
+            self.proxies
+                .iter()
+                .map(|p| p.name.as_str())
+                .filter(|name| !kept.contains(name)),
+        );
+        *self = server;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[path = "reload_tests.rs"]
+mod tests;
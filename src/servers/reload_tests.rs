@@ -0,0 +1,266 @@
+use super::*;
+use crate::servers::tests::base_proxy;
+use crate::upstreams::Upstream;
+use std::io::Write;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Notify;
+
+async fn free_addr() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    listener.local_addr().unwrap()
+}
+
+fn make_proxy(listen: SocketAddr, default_action: &str) -> Arc<Proxy> {
+    let upstream = HashMap::from([
+        ("ban".to_string(), Upstream::Ban),
+        ("echo".to_string(), Upstream::Echo),
+    ]);
+    Arc::new(Proxy {
+        name: default_action.to_string(),
+        listen,
+        ..base_proxy(default_action, upstream)
+    })
+}
+
+fn write_config(file: &mut tempfile::NamedTempFile, listen: SocketAddr, maxclients: usize) {
+    let yaml = format!(
+        "version: 1\nlog: disable\nservers:\n  echo_server:\n    listen: [\"{}\"]\n    \
+         maxclients: {}\n    default: echo\nupstream: {{}}\n",
+        listen, maxclients
+    );
+    file.as_file().set_len(0).unwrap();
+    let mut f = file.reopen().unwrap();
+    f.write_all(yaml.as_bytes()).unwrap();
+}
+
+/// Send one byte and expect it back.
+async fn assert_echo(conn: &mut TcpStream) {
+    conn.write_all(b"x").await.unwrap();
+    let mut buf = [0u8; 1];
+    conn.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"x");
+}
+
+async fn wait_until_listening(addr: SocketAddr) -> TcpStream {
+    for _ in 0..50 {
+        if let Ok(conn) = TcpStream::connect(addr).await {
+            return conn;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("nothing listening on {}", addr);
+}
+
+#[tokio::test]
+async fn test_apply_swaps_routes_for_new_connections_only() {
+    let addr = free_addr().await;
+    let token = CancellationToken::new();
+    let tracker = TaskTracker::new();
+    let mut listeners = Listeners::default();
+
+    listeners.apply(&[make_proxy(addr, "echo")], &token, &tracker);
+    let mut old = wait_until_listening(addr).await;
+    assert_echo(&mut old).await;
+
+    listeners.apply(&[make_proxy(addr, "ban")], &token, &tracker);
+    let mut new = TcpStream::connect(addr).await.unwrap();
+    let mut buf = [0u8; 1];
+    assert_eq!(new.read(&mut buf).await.unwrap(), 0, "expected ban");
+
+    // The tunnel accepted before the reload keeps its route.
+    assert_echo(&mut old).await;
+    token.cancel();
+}
+
+#[tokio::test]
+async fn test_apply_closes_removed_and_binds_added_listeners() {
+    let first = free_addr().await;
+    let second = free_addr().await;
+    let token = CancellationToken::new();
+    let tracker = TaskTracker::new();
+    let mut listeners = Listeners::default();
+
+    listeners.apply(&[make_proxy(first, "echo")], &token, &tracker);
+    let mut open = wait_until_listening(first).await;
+    // Make sure it was accepted, not just queued in the backlog.
+    assert_echo(&mut open).await;
+
+    listeners.apply(&[make_proxy(second, "echo")], &token, &tracker);
+    let mut added = wait_until_listening(second).await;
+    assert_echo(&mut added).await;
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(TcpStream::connect(first).await.is_err());
+    assert_echo(&mut open).await;
+    token.cancel();
+}
+
+#[tokio::test]
+async fn test_serve_reloads_config_file() {
+    let first = free_addr().await;
+    let second = free_addr().await;
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    write_config(&mut file, first, 10);
+    let path = file.path().to_string_lossy().into_owned();
+
+    let config = Config::new(&path).unwrap();
+    let mut server = Server::from(config.base).with_config_path(path);
+    let token = CancellationToken::new();
+    let reload = Arc::new(Notify::new());
+    let serve = tokio::spawn({
+        let token = token.clone();
+        let reload = reload.clone();
+        async move { server.serve(token, reload).await.map_err(|e| e.to_string()) }
+    });
+
+    let mut open = wait_until_listening(first).await;
+    assert_echo(&mut open).await;
+
+    write_config(&mut file, second, 10);
+    reload.notify_one();
+    let mut added = wait_until_listening(second).await;
+    assert_echo(&mut added).await;
+    assert_echo(&mut open).await;
+
+    token.cancel();
+    drop(open);
+    drop(added);
+    serve.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_reload_invalid_config_keeps_running_config() {
+    let addr = free_addr().await;
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    write_config(&mut file, addr, 10);
+    let path = file.path().to_string_lossy().into_owned();
+    let mut server = Server::from(Config::new(&path).unwrap().base).with_config_path(path);
+
+    file.as_file().set_len(0).unwrap();
+    file.reopen()
+        .unwrap()
+        .write_all(b"version: 1\nservers: [")
+        .unwrap();
+
+    assert!(server.reload().await.is_err());
+    assert_eq!(server.proxies.len(), 1);
+    assert_eq!(server.proxies[0].listen, addr);
+}
+
+#[tokio::test]
+async fn test_reload_keeps_maxclients_semaphore() {
+    let addr = free_addr().await;
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    write_config(&mut file, addr, 10);
+    let path = file.path().to_string_lossy().into_owned();
+    let mut server = Server::from(Config::new(&path).unwrap().base).with_config_path(path);
+    let semaphore = server.proxies[0].maxclients.clone();
+    let _open = semaphore.clone().try_acquire_many_owned(3).unwrap();
+
+    write_config(&mut file, addr, 20);
+    server.reload().await.unwrap();
+    assert!(Arc::ptr_eq(&server.proxies[0].maxclients, &semaphore));
+    assert_eq!(semaphore.available_permits(), 17);
+
+    write_config(&mut file, addr, 5);
+    server.reload().await.unwrap();
+    assert_eq!(server.proxies[0].maxclients_limit, 5);
+    assert_eq!(semaphore.available_permits(), 2);
+
+    // 10 → 5 → 10 with all 10 in use: the deficit is settled, not left to
+    // take permits from the raise.
+    write_config(&mut file, addr, 10);
+    server.reload().await.unwrap();
+    let _more = semaphore.clone().try_acquire_many_owned(7).unwrap();
+    write_config(&mut file, addr, 5);
+    server.reload().await.unwrap();
+    assert_eq!(semaphore.available_permits(), 0);
+    write_config(&mut file, addr, 10);
+    server.reload().await.unwrap();
+    drop(_open);
+    drop(_more);
+    for _ in 0..10 {
+        tokio::task::yield_now().await;
+    }
+    assert_eq!(semaphore.available_permits(), 10);
+}
+
+#[tokio::test]
+async fn test_serve_reloads_on_config_file_change() {
+    let first = free_addr().await;
+    let second = free_addr().await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.yaml");
+    let watched = |listen: SocketAddr| {
+        format!(
+            "version: 1\nlog: disable\nconfig_watch:\n  enabled: true\n  interval: 20ms\n\
+             servers:\n  echo_server:\n    listen: [\"{}\"]\n    default: echo\n",
+            listen
+        )
+    };
+    std::fs::write(&path, watched(first)).unwrap();
+    let path = path.to_string_lossy().into_owned();
+
+    let mut server = Server::from(Config::new(&path).unwrap().base).with_config_path(path.clone());
+    let token = CancellationToken::new();
+    let serve = tokio::spawn({
+        let token = token.clone();
+        async move {
+            server
+                .serve(token, Arc::new(Notify::new()))
+                .await
+                .map_err(|e| e.to_string())
+        }
+    });
+    drop(wait_until_listening(first).await);
+
+    // An invalid file is rejected and the running config kept.
+    std::fs::write(&path, "version: 1\nservers: [").unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    drop(wait_until_listening(first).await);
+
+    std::fs::write(&path, watched(second)).unwrap();
+    let mut added = wait_until_listening(second).await;
+    assert_echo(&mut added).await;
+
+    token.cancel();
+    drop(added);
+    serve.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_reload_keeps_log_settings() {
+    let addr = free_addr().await;
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    write_config(&mut file, addr, 10);
+    let path = file.path().to_string_lossy().into_owned();
+    let mut server = Server::from(Config::new(&path).unwrap().base).with_config_path(path);
+
+    let yaml = std::fs::read_to_string(file.path()).unwrap();
+    std::fs::write(
+        file.path(),
+        yaml.replace("log: disable", "log: debug\nlog-format: json"),
+    )
+    .unwrap();
+    server.reload().await.unwrap();
+    assert_eq!(server.log, (Some("disable".to_string()), "txt".to_string()));
+}
+
+#[tokio::test]
+async fn test_reload_forgets_draining_of_removed_servers() {
+    let addr = free_addr().await;
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    write_config(&mut file, addr, 10);
+    // A name of its own: draining is process-wide.
+    let yaml = std::fs::read_to_string(file.path()).unwrap();
+    std::fs::write(file.path(), yaml.replace("echo_server", "drained_server")).unwrap();
+    let path = file.path().to_string_lossy().into_owned();
+    let mut server = Server::from(Config::new(&path).unwrap().base).with_config_path(path);
+    connections::registry().set_draining("drained_server", true);
+
+    std::fs::write(file.path(), yaml.replace("echo_server", "renamed_server")).unwrap();
+    server.reload().await.unwrap();
+    assert!(!connections::registry().is_draining("drained_server"));
+}
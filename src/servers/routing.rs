@@ -0,0 +1,140 @@
+use log::debug;
+use std::collections::HashMap;
+
+use crate::config::{SniTarget, ViaUpstream};
+use crate::metrics::DEFAULT_ROUTE;
+
+use super::Proxy;
+
+// ---------------------------------------------------------------------------
+// SNI map lookup
+//
+// Keys in the `sni:` map come in three forms:
+//   www.corp.org   exact match
+//   *.corp.org     any subdomain of corp.org (one or more labels), not corp.org
+//   .corp.org      corp.org itself and any subdomain of it
+//
+// Precedence is exact → longest matching wildcard/suffix → no match. A
+// wildcard and a suffix key for the same domain would match the same names,
+// so `verify_config` rejects that combination as ambiguous.
+//
+// Returns every matching key with its target, most specific first.
+// ---------------------------------------------------------------------------
+pub(crate) fn lookup_sni<'a>(
+    sni_map: &'a HashMap<String, SniTarget>,
+    sni: &str,
+) -> impl Iterator<Item = (&'a str, &'a SniTarget)> + use<'a> {
+    // Exact keys are lowercase without a trailing dot (see `normalize_sni_keys`).
+    let name = sni.trim_end_matches('.').to_ascii_lowercase();
+
+    // ".corp.org" also covers the apex "corp.org". The parent domains follow
+    // from longest to shortest.
+    let mut candidates = vec![name.clone(), format!(".{}", name)];
+    for (i, _) in name.match_indices('.') {
+        let parent = &name[i..];
+        candidates.push(format!("*{}", parent));
+        candidates.push(parent.to_string());
+    }
+
+    candidates
+        .into_iter()
+        .filter_map(|key| sni_map.get_key_value(&key))
+        .map(|(key, target)| (key.as_str(), target))
+}
+
+// ---------------------------------------------------------------------------
+// Route selection for an accepted connection
+// ---------------------------------------------------------------------------
+
+/// Outcome of routing one connection.
+#[derive(Debug)]
+pub(crate) struct Route<'a> {
+    /// The matched `sni` key or `sni_rules` pattern, or `default`.
+    pub name: String,
+    pub upstream_name: String,
+    /// Upstreams to try, in order, if `upstream_name` cannot be reached.
+    pub fallbacks: Vec<String>,
+    /// Per-route `via` if the matched entry has one, otherwise the server's.
+    pub via: &'a ViaUpstream,
+    /// HTTP CONNECT target, or `None` for a direct TCP forward.
+    pub connect_target: Option<String>,
+}
+
+/// Pick the upstream and CONNECT target for the given SNI list and offered
+/// ALPN protocols.
+///
+/// The `sni:` map is consulted first, then `sni_rules` in order; entries with
+/// an `alpn` condition only match if the client offered one of the listed
+/// protocols. The first SNI with a match wins. Without a match the server's
+/// `default` is used.
+pub(crate) fn route<'a>(proxy: &'a Proxy, snis: &[String], alpn: &[String]) -> Route<'a> {
+    let mut name = DEFAULT_ROUTE.to_string();
+    let mut upstream_name = proxy.default_action.clone();
+    let mut fallbacks = Vec::new();
+    let mut via_override = None;
+    let mut captures = None;
+
+    'snis: for sni in snis {
+        // A key whose alternatives all exclude the client's ALPN leaves the
+        // connection to the less specific keys.
+        if let Some((key, target)) = proxy
+            .sni
+            .as_ref()
+            .and_then(|m| lookup_sni(m, sni).find_map(|(key, t)| Some((key, t.select(alpn)?))))
+        {
+            debug!("SNI {} matched {}", sni, key);
+            name = key.to_string();
+            upstream_name = target.upstream_name().to_string();
+            fallbacks = target.fallbacks().to_vec();
+            via_override = target.via_override();
+            break;
+        }
+        for rule in proxy.sni_rules.iter().filter(|r| r.accepts_alpn(alpn)) {
+            if let Some(caps) = rule.pattern.captures(sni) {
+                debug!("SNI {} matched rule {}", sni, rule.pattern);
+                name = rule.source().to_string();
+                upstream_name = rule.upstream.primary().to_string();
+                fallbacks = rule.upstream.fallbacks().to_vec();
+                via_override = rule.via.as_ref();
+                captures = Some(caps);
+                break 'snis;
+            }
+        }
+    }
+
+    // Per-route via takes precedence over the server-level via.
+    let via = via_override.unwrap_or(&proxy.via);
+
+    // Determine the CONNECT target for the upstream HTTP proxy:
+    //   use_sni_as_target=true  → "{first_sni}:{target_port}" (dynamic, per-connection)
+    //   use_sni_as_target=false → via.target if non-empty (static config, with
+    //                             `$1`/`${name}` expanded from a matched sni_rule)
+    //   otherwise               → None (direct TCP forward, no CONNECT)
+    let connect_target = if via.use_sni_as_target {
+        snis.first()
+            .map(|sni| format!("{}:{}", sni, via.target_port))
+    } else if !via.target.is_empty() {
+        match &captures {
+            Some(caps) => {
+                let mut target = String::new();
+                caps.expand(&via.target, &mut target);
+                Some(target)
+            }
+            None => Some(via.target.clone()),
+        }
+    } else {
+        None
+    };
+
+    Route {
+        name,
+        upstream_name,
+        fallbacks,
+        via,
+        connect_target,
+    }
+}
+
+#[cfg(test)]
+#[path = "routing_tests.rs"]
+mod tests;
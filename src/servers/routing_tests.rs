@@ -0,0 +1,272 @@
+use super::*;
+use crate::servers::tests::base_proxy;
+
+fn sni_map(entries: &[(&str, &str)]) -> HashMap<String, SniTarget> {
+    entries
+        .iter()
+        .map(|(k, v)| (k.to_string(), SniTarget::Simple(v.to_string())))
+        .collect()
+}
+
+fn lookup(map: &HashMap<String, SniTarget>, sni: &str) -> Option<String> {
+    lookup_sni(map, sni)
+        .next()
+        .map(|(_, t)| t.upstream_name().to_string())
+}
+
+#[test]
+fn test_lookup_exact() {
+    let map = sni_map(&[("www.corp.org", "exact")]);
+    assert_eq!(lookup(&map, "www.corp.org").as_deref(), Some("exact"));
+    assert_eq!(lookup(&map, "api.corp.org"), None);
+}
+
+#[test]
+fn test_lookup_wildcard_matches_subdomains_only() {
+    let map = sni_map(&[("*.corp.org", "wild")]);
+    assert_eq!(lookup(&map, "www.corp.org").as_deref(), Some("wild"));
+    assert_eq!(lookup(&map, "a.b.corp.org").as_deref(), Some("wild"));
+    assert_eq!(lookup(&map, "corp.org"), None);
+    assert_eq!(lookup(&map, "notcorp.org"), None);
+}
+
+#[test]
+fn test_lookup_suffix_matches_apex_and_subdomains() {
+    let map = sni_map(&[(".corp.org", "suffix")]);
+    assert_eq!(lookup(&map, "corp.org").as_deref(), Some("suffix"));
+    assert_eq!(lookup(&map, "www.corp.org").as_deref(), Some("suffix"));
+    assert_eq!(lookup(&map, "xcorp.org"), None);
+}
+
+#[test]
+fn test_lookup_exact_beats_wildcard() {
+    let map = sni_map(&[("*.corp.org", "wild"), ("www.corp.org", "exact")]);
+    assert_eq!(lookup(&map, "www.corp.org").as_deref(), Some("exact"));
+    assert_eq!(lookup(&map, "api.corp.org").as_deref(), Some("wild"));
+}
+
+#[test]
+fn test_lookup_longest_pattern_wins() {
+    let map = sni_map(&[
+        ("*.corp.org", "short"),
+        ("*.eu.corp.org", "long"),
+        (".org", "tld"),
+    ]);
+    assert_eq!(lookup(&map, "www.eu.corp.org").as_deref(), Some("long"));
+    assert_eq!(lookup(&map, "www.us.corp.org").as_deref(), Some("short"));
+    assert_eq!(lookup(&map, "example.org").as_deref(), Some("tld"));
+}
+
+#[test]
+fn test_lookup_pattern_is_case_insensitive() {
+    let map = sni_map(&[("*.corp.org", "wild")]);
+    assert_eq!(lookup(&map, "WWW.Corp.Org").as_deref(), Some("wild"));
+}
+
+#[test]
+fn test_lookup_ignores_trailing_dot() {
+    let map = sni_map(&[("*.corp.org", "wild")]);
+    assert_eq!(lookup(&map, "www.corp.org.").as_deref(), Some("wild"));
+}
+
+#[test]
+fn test_lookup_exact_is_normalized() {
+    let map = sni_map(&[("*.corp.org", "wild"), ("www.corp.org", "exact")]);
+    for sni in ["WWW.Corp.org", "www.corp.org.", "Www.CORP.org."] {
+        assert_eq!(lookup(&map, sni).as_deref(), Some("exact"), "{}", sni);
+    }
+    assert_eq!(lookup(&map, "API.corp.org.").as_deref(), Some("wild"));
+}
+
+// --- route ---
+
+fn make_proxy(sni: Option<HashMap<String, SniTarget>>, sni_rules: &str, via: ViaUpstream) -> Proxy {
+    Proxy {
+        tls: true,
+        sni,
+        sni_rules: serde_yaml_ng::from_str(sni_rules).unwrap(),
+        via,
+        ..base_proxy("ban", HashMap::new())
+    }
+}
+
+fn s(v: &[&str]) -> Vec<String> {
+    v.iter().map(|s| s.to_string()).collect()
+}
+
+const RULES: &str = r#"
+- match: '(?<svc>[a-z0-9-]+)\.ext\.corp\.org'
+  upstream: rule_proxy
+  via:
+    target: "${svc}.internal:8443"
+- match: '([a-z]+)\.([a-z]+)\.example\.com'
+  upstream: rule_proxy
+  via:
+    target: "$2-$1.example.net:443"
+- match: 'inherit\.(.+)'
+  upstream: inherit_proxy
+"#;
+
+#[test]
+fn test_route_default_without_match() {
+    let proxy = make_proxy(None, RULES, ViaUpstream::default());
+    let route = route(&proxy, &s(&["nomatch.org"]), &[]);
+    assert_eq!(route.name, "default");
+    assert_eq!(route.upstream_name, "ban");
+    assert_eq!(route.connect_target, None);
+}
+
+#[test]
+fn test_route_rule_named_capture() {
+    let proxy = make_proxy(None, RULES, ViaUpstream::default());
+    let route = route(&proxy, &s(&["billing.ext.corp.org"]), &[]);
+    assert_eq!(route.name, r"(?<svc>[a-z0-9-]+)\.ext\.corp\.org");
+    assert_eq!(route.upstream_name, "rule_proxy");
+    assert_eq!(
+        route.connect_target.as_deref(),
+        Some("billing.internal:8443")
+    );
+}
+
+#[test]
+fn test_route_rule_numbered_captures() {
+    let proxy = make_proxy(None, RULES, ViaUpstream::default());
+    let route = route(&proxy, &s(&["www.shop.example.com"]), &[]);
+    assert_eq!(
+        route.connect_target.as_deref(),
+        Some("shop-www.example.net:443")
+    );
+}
+
+#[test]
+fn test_route_rule_expands_inherited_server_via() {
+    let via = ViaUpstream {
+        target: "${1}:8443".to_string(),
+        ..Default::default()
+    };
+    let proxy = make_proxy(None, RULES, via);
+    let route = route(&proxy, &s(&["inherit.corp.org"]), &[]);
+    assert_eq!(route.upstream_name, "inherit_proxy");
+    assert_eq!(route.connect_target.as_deref(), Some("corp.org:8443"));
+}
+
+#[test]
+fn test_route_sni_map_beats_rules() {
+    let map = sni_map(&[("*.ext.corp.org", "map_proxy")]);
+    let proxy = make_proxy(Some(map), RULES, ViaUpstream::default());
+    let route = route(&proxy, &s(&["billing.ext.corp.org"]), &[]);
+    assert_eq!(route.name, "*.ext.corp.org");
+    assert_eq!(route.upstream_name, "map_proxy");
+    assert_eq!(route.connect_target, None);
+}
+
+#[test]
+fn test_route_use_sni_as_target() {
+    let via = ViaUpstream {
+        use_sni_as_target: true,
+        target_port: 8443,
+        ..Default::default()
+    };
+    let proxy = make_proxy(None, "[]", via);
+    let route = route(&proxy, &s(&["www.corp.org"]), &[]);
+    assert_eq!(route.connect_target.as_deref(), Some("www.corp.org:8443"));
+}
+
+#[test]
+fn test_route_alpn_alternatives() {
+    let map: HashMap<String, SniTarget> = serde_yaml_ng::from_str(
+        r#"
+www.corp.org:
+  - upstream: acme
+    alpn: [acme-tls/1]
+  - web
+"#,
+    )
+    .unwrap();
+    let proxy = make_proxy(Some(map), "[]", ViaUpstream::default());
+    let acme = route(&proxy, &s(&["www.corp.org"]), &s(&["acme-tls/1"]));
+    assert_eq!(acme.upstream_name, "acme");
+    let web = route(&proxy, &s(&["www.corp.org"]), &s(&["h2", "http/1.1"]));
+    assert_eq!(web.upstream_name, "web");
+}
+
+#[test]
+fn test_route_alpn_condition_falls_through_to_rules() {
+    let map: HashMap<String, SniTarget> = serde_yaml_ng::from_str(
+        r#"
+www.corp.org:
+  upstream: h2_only
+  alpn: [h2]
+"#,
+    )
+    .unwrap();
+    let rules = r#"
+- match: 'www\.corp\.org'
+  upstream: rule_acme
+  alpn: [acme-tls/1]
+"#;
+    let proxy = make_proxy(Some(map), rules, ViaUpstream::default());
+    let h2 = route(&proxy, &s(&["www.corp.org"]), &s(&["h2"]));
+    assert_eq!(h2.upstream_name, "h2_only");
+    let acme = route(&proxy, &s(&["www.corp.org"]), &s(&["acme-tls/1"]));
+    assert_eq!(acme.upstream_name, "rule_acme");
+    let none = route(&proxy, &s(&["www.corp.org"]), &[]);
+    assert_eq!(none.upstream_name, "ban");
+}
+
+#[test]
+fn test_route_fallbacks() {
+    let map: HashMap<String, SniTarget> = serde_yaml_ng::from_str(
+        r#"
+api.corp.org:
+  upstream: [corp_proxy_a, corp_proxy_b, direct]
+www.corp.org:
+  upstream: web
+"#,
+    )
+    .unwrap();
+    let rules = r#"
+- match: '.*\.ext\.corp\.org'
+  upstream: [rule_proxy, direct]
+"#;
+    let proxy = make_proxy(Some(map), rules, ViaUpstream::default());
+
+    let api = route(&proxy, &s(&["api.corp.org"]), &[]);
+    assert_eq!(api.upstream_name, "corp_proxy_a");
+    assert_eq!(api.fallbacks, s(&["corp_proxy_b", "direct"]));
+
+    let web = route(&proxy, &s(&["www.corp.org"]), &[]);
+    assert_eq!(web.upstream_name, "web");
+    assert!(web.fallbacks.is_empty());
+
+    let ext = route(&proxy, &s(&["billing.ext.corp.org"]), &[]);
+    assert_eq!(ext.upstream_name, "rule_proxy");
+    assert_eq!(ext.fallbacks, s(&["direct"]));
+
+    let default = route(&proxy, &s(&["nomatch.org"]), &[]);
+    assert!(default.fallbacks.is_empty());
+}
+
+#[test]
+fn test_route_alpn_mismatch_falls_through_to_pattern() {
+    let map: HashMap<String, SniTarget> = serde_yaml_ng::from_str(
+        r#"
+www.corp.org:
+  upstream: h2_only
+  alpn: [h2]
+"*.corp.org": wild
+"#,
+    )
+    .unwrap();
+    let proxy = make_proxy(Some(map), "[]", ViaUpstream::default());
+    let h2 = route(&proxy, &s(&["www.corp.org"]), &s(&["h2"]));
+    assert_eq!(
+        (h2.name.as_str(), h2.upstream_name.as_str()),
+        ("www.corp.org", "h2_only")
+    );
+    let http1 = route(&proxy, &s(&["www.corp.org"]), &s(&["http/1.1"]));
+    assert_eq!(
+        (http1.name.as_str(), http1.upstream_name.as_str()),
+        ("*.corp.org", "wild")
+    );
+}
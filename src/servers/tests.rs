@@ -6,6 +6,28 @@ use tokio::net::TcpListener;
 
 use super::*;
 
+/// A plain `tcp` server named "test" on an ephemeral port that sends every
+/// connection to `default_action`. Tests override the fields they exercise.
+pub(super) fn base_proxy(default_action: &str, upstream: HashMap<String, Upstream>) -> Proxy {
+    Proxy {
+        name: "test".to_string(),
+        listen: "127.0.0.1:0".parse().unwrap(),
+        protocol: "tcp".to_string(),
+        tls: false,
+        sni: None,
+        sni_rules: Vec::new(),
+        default_action: default_action.to_string(),
+        upstream: Arc::new(upstream),
+        via: ViaUpstream::default(),
+        maxclients: Arc::new(Semaphore::new(10)),
+        maxclients_limit: 10,
+        maxclients_deficit: Arc::default(),
+        accept_proxy_protocol: false,
+        client_hello: ClientHelloConfig::default(),
+        client_hello_timeouts: Arc::new(AtomicU64::new(0)),
+    }
+}
+
 #[tokio::main]
 async fn tcp_mock_server() {
     let server_addr: SocketAddr = "127.0.0.1:54599".parse().unwrap();
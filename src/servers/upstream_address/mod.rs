@@ -1,4 +1,4 @@
-use log::{debug, error};
+use log::{debug, error, warn};
 use std::fmt::{Display, Formatter};
 use std::io::Result;
 use std::net::SocketAddr;
@@ -6,6 +6,22 @@ use std::sync::Arc;
 use std::time::Instant;
 use time::{Duration, OffsetDateTime};
 use tokio::sync::RwLock;
+use tokio_util::sync::CancellationToken;
+
+mod resolver;
+
+pub(crate) use resolver::{SrvRecord, configure};
+
+/// Look up SRV records with the process-wide resolver; see
+/// `Resolver::lookup_srv`.
+pub(crate) async fn lookup_srv(name: &str) -> Result<(Vec<SrvRecord>, std::time::Duration)> {
+    resolver::current().lookup_srv(name).await
+}
+
+/// How long a failed lookup is remembered (`dns.negative_ttl`).
+pub(crate) fn negative_ttl() -> std::time::Duration {
+    resolver::current().config.negative_ttl
+}
 
 // ---------------------------------------------------------------------------
 // UpstreamAddress — DNS cache with TTL
@@ -68,21 +84,33 @@ impl UpstreamAddress {
             );
             return Ok(self.resolved_addresses.read().await.clone());
         }
+        self.refresh(mode).await
+    }
 
+    /// Look the address up again regardless of the cached TTL. If the lookup
+    /// fails but earlier addresses are known, they are kept and returned.
+    pub async fn refresh(&self, mode: ResolutionMode) -> Result<Vec<SocketAddr>> {
         debug!(
             "Resolving addresses for {} with mode {:?}",
             &self.address, &mode
         );
 
-        let lookup_result = tokio::net::lookup_host(&self.address).await;
-
-        let resolved_addresses: Vec<SocketAddr> = match lookup_result {
-            Ok(resolved_addresses) => resolved_addresses.into_iter().collect(),
+        let resolver = resolver::current();
+        let (resolved_addresses, ttl) = match resolver.lookup(&self.address).await {
+            Ok(answer) => answer,
             Err(e) => {
                 debug!("Failed looking up {}: {}", &self.address, &e);
                 *self.resolved_time.write().await = Some(Instant::now());
-                *self.ttl.write().await = Some(Duration::seconds(3));
-                return Err(e);
+                *self.ttl.write().await = Some(to_ttl(resolver.config.negative_ttl));
+                let stale = self.resolved_addresses.read().await.clone();
+                if stale.is_empty() {
+                    return Err(e);
+                }
+                warn!(
+                    "Failed resolving {}, keeping previous addresses {:?}: {}",
+                    &self.address, &stale, e
+                );
+                return Ok(stale);
             }
         };
 
@@ -103,8 +131,8 @@ impl UpstreamAddress {
         };
 
         debug!(
-            "Got {} addresses for {}: {:?}",
-            &mode, &self.address, &addresses
+            "Got {} addresses for {}, valid for {:?}: {:?}",
+            &mode, &self.address, ttl, &addresses
         );
         debug!(
             "Resolved at {}",
@@ -115,10 +143,40 @@ impl UpstreamAddress {
 
         self.resolved_addresses.write().await.clone_from(&addresses);
         *self.resolved_time.write().await = Some(Instant::now());
-        *self.ttl.write().await = Some(Duration::minutes(1));
+        *self.ttl.write().await = Some(to_ttl(ttl));
 
         Ok(addresses)
     }
+
+    /// Re-resolve shortly before the cached answer expires, until `token` is
+    /// cancelled, so connects never wait for DNS. Returns at once for IP
+    /// literals.
+    pub async fn keep_fresh(&self, mode: ResolutionMode, token: CancellationToken) {
+        if resolver::is_literal(&self.address) {
+            return;
+        }
+        loop {
+            if let Err(e) = self.refresh(mode.clone()).await {
+                debug!("Background refresh of {} failed: {}", &self.address, e);
+            }
+            let remaining = self.time_remaining().await;
+            let wait = std::time::Duration::try_from(remaining * 0.9)
+                .unwrap_or_default()
+                .max(MIN_REFRESH_INTERVAL);
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = token.cancelled() => return,
+            }
+        }
+    }
+}
+
+/// Floor for the background refresh interval, also of SRV upstreams, so a
+/// zero TTL does not turn the refresh into a busy loop.
+pub(crate) const MIN_REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+fn to_ttl(ttl: std::time::Duration) -> Duration {
+    Duration::try_from(ttl).unwrap_or(Duration::MAX)
 }
 
 // ---------------------------------------------------------------------------
@@ -138,7 +196,7 @@ impl From<&str> for ResolutionMode {
         match value {
             "tcp4" => ResolutionMode::Ipv4,
             "tcp6" => ResolutionMode::Ipv6,
-            "tcp" => ResolutionMode::Ipv4AndIpv6,
+            "tcp" | "socks5" => ResolutionMode::Ipv4AndIpv6,
             _ => {
                 error!("Unknown protocol '{}', defaulting to IPv4AndIpv6", value);
                 ResolutionMode::Ipv4AndIpv6
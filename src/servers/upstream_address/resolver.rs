@@ -0,0 +1,205 @@
+use hickory_resolver::TokioAsyncResolver;
+use hickory_resolver::config::{
+    LookupIpStrategy, NameServerConfig, NameServerConfigGroup, Protocol, ResolverConfig,
+    ResolverOpts,
+};
+use log::{debug, warn};
+use std::io::{Error, ErrorKind, Result};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::config::DnsConfig;
+
+// ---------------------------------------------------------------------------
+// Resolver — process-wide DNS resolver for upstream host names
+//
+// Answers carry their real TTL, clamped to `dns.min_ttl`..`dns.max_ttl`.
+// `Server::serve` calls `configure` when it starts and after each reload
+// (`--check --resolve` once); until then the system configuration is used
+// with the default bounds.
+// ---------------------------------------------------------------------------
+
+static RESOLVER: RwLock<Option<Arc<Resolver>>> = RwLock::new(None);
+
+pub(crate) struct Resolver {
+    inner: TokioAsyncResolver,
+    pub config: DnsConfig,
+}
+
+/// Replace the process-wide resolver.
+pub(crate) fn configure(config: &DnsConfig) {
+    let resolver = Arc::new(Resolver::new(config.clone()));
+    *RESOLVER.write().unwrap_or_else(|e| e.into_inner()) = Some(resolver);
+}
+
+/// The process-wide resolver, created from the system configuration on
+/// first use if `configure` was never called.
+pub(crate) fn current() -> Arc<Resolver> {
+    if let Some(resolver) = RESOLVER.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        return resolver.clone();
+    }
+    let mut slot = RESOLVER.write().unwrap_or_else(|e| e.into_inner());
+    slot.get_or_insert_with(|| Arc::new(Resolver::new(DnsConfig::default())))
+        .clone()
+}
+
+impl Resolver {
+    pub fn new(config: DnsConfig) -> Self {
+        let mut opts = ResolverOpts::default();
+        opts.ip_strategy = LookupIpStrategy::Ipv4AndIpv6;
+        opts.positive_min_ttl = Some(config.min_ttl);
+        opts.positive_max_ttl = Some(config.max_ttl);
+        opts.negative_max_ttl = Some(config.negative_ttl);
+
+        // Nameservers were validated by the config loader.
+        let nameservers = config.nameserver_addrs().unwrap_or_default();
+        let inner = if nameservers.is_empty() {
+            match hickory_resolver::system_conf::read_system_conf() {
+                Ok((system, mut system_opts)) => {
+                    system_opts.ip_strategy = opts.ip_strategy;
+                    system_opts.positive_min_ttl = opts.positive_min_ttl;
+                    system_opts.positive_max_ttl = opts.positive_max_ttl;
+                    system_opts.negative_max_ttl = opts.negative_max_ttl;
+                    TokioAsyncResolver::tokio(system, system_opts)
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed reading system DNS configuration, using defaults: {}",
+                        e
+                    );
+                    TokioAsyncResolver::tokio(ResolverConfig::default(), opts)
+                }
+            }
+        } else {
+            debug!("Using DNS nameservers {:?}", nameservers);
+            let mut group = NameServerConfigGroup::with_capacity(nameservers.len() * 2);
+            for addr in nameservers {
+                group.push(NameServerConfig::new(addr, Protocol::Udp));
+                group.push(NameServerConfig::new(addr, Protocol::Tcp));
+            }
+            TokioAsyncResolver::tokio(ResolverConfig::from_parts(None, vec![], group), opts)
+        };
+        Resolver { inner, config }
+    }
+
+    /// Resolve `host:port`. Returns the addresses and how long they may be
+    /// cached. IP literals are returned as-is and cached for `max_ttl`.
+    pub async fn lookup(&self, address: &str) -> Result<(Vec<SocketAddr>, Duration)> {
+        if let Ok(addr) = address.parse::<SocketAddr>() {
+            return Ok((vec![addr], self.config.max_ttl));
+        }
+        let (host, port) = split_host_port(address)?;
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Ok((vec![SocketAddr::new(ip, port)], self.config.max_ttl));
+        }
+
+        let lookup = self
+            .inner
+            .lookup_ip(host)
+            .await
+            .map_err(|e| Error::other(format!("failed to resolve {}: {}", host, e)))?;
+        let ttl = self.clamp_ttl(
+            lookup
+                .valid_until()
+                .saturating_duration_since(Instant::now()),
+        );
+        let mut addresses: Vec<SocketAddr> =
+            lookup.iter().map(|ip| SocketAddr::new(ip, port)).collect();
+        // IPv6 first, as Happy Eyeballs prefers; the sort is stable, so each
+        // family keeps the order of the answer.
+        addresses.sort_by_key(|a| a.is_ipv4());
+        if addresses.is_empty() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("no addresses for {}", host),
+            ));
+        }
+        Ok((addresses, ttl))
+    }
+
+    /// Look up the SRV records of `name`, e.g. `_proxy._tcp.corp.org`.
+    /// Returns the records and how long they may be cached.
+    pub async fn lookup_srv(&self, name: &str) -> Result<(Vec<SrvRecord>, Duration)> {
+        let lookup = self
+            .inner
+            .srv_lookup(name)
+            .await
+            .map_err(|e| Error::other(format!("failed to resolve SRV {}: {}", name, e)))?;
+        let ttl = self.clamp_ttl(
+            lookup
+                .as_lookup()
+                .valid_until()
+                .saturating_duration_since(Instant::now()),
+        );
+        let records: Vec<SrvRecord> = lookup
+            .iter()
+            .map(|srv| SrvRecord {
+                priority: srv.priority(),
+                weight: srv.weight(),
+                port: srv.port(),
+                target: srv.target().to_utf8().trim_end_matches('.').to_string(),
+            })
+            // A target of "." means the service is not available (RFC 2782).
+            .filter(|r| !r.target.is_empty())
+            .collect();
+        if records.is_empty() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("no SRV records for {}", name),
+            ));
+        }
+        Ok((records, ttl))
+    }
+
+    /// Bound an answer's TTL to the configured range.
+    pub fn clamp_ttl(&self, ttl: Duration) -> Duration {
+        ttl.clamp(self.config.min_ttl, self.config.max_ttl)
+    }
+}
+
+/// One SRV answer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SrvRecord {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: String,
+}
+
+impl SrvRecord {
+    /// `target:port`, bracketed for IPv6 literals.
+    pub fn address(&self) -> String {
+        if self.target.contains(':') {
+            format!("[{}]:{}", self.target, self.port)
+        } else {
+            format!("{}:{}", self.target, self.port)
+        }
+    }
+}
+
+/// Whether `address` is an IP literal that needs no lookup.
+pub(crate) fn is_literal(address: &str) -> bool {
+    address.parse::<SocketAddr>().is_ok()
+        || split_host_port(address).is_ok_and(|(host, _)| host.parse::<IpAddr>().is_ok())
+}
+
+/// Split `host:port` or `[v6]:port`.
+fn split_host_port(address: &str) -> Result<(&str, u16)> {
+    let invalid = || {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid address '{}': expected host:port", address),
+        )
+    };
+    let (host, port) = address.rsplit_once(':').ok_or_else(invalid)?;
+    let port = port.parse::<u16>().map_err(|_| invalid())?;
+    let host = host
+        .strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host);
+    if host.is_empty() {
+        return Err(invalid());
+    }
+    Ok((host, port))
+}
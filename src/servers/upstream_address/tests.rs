@@ -128,3 +128,77 @@ async fn test_resolve_ipv6_filter() {
         Err(e) => panic!("resolve([::1]) failed: {}", e),
     }
 }
+
+#[tokio::test]
+async fn test_refresh_failure_keeps_previous_addresses() {
+    let addr = UpstreamAddress::new("this.host.does.not.exist.invalid:80".to_string());
+    let previous: SocketAddr = "192.0.2.1:80".parse().unwrap();
+    *addr.resolved_addresses.write().await = vec![previous];
+
+    let addrs = addr.refresh(ResolutionMode::Ipv4AndIpv6).await.unwrap();
+    assert_eq!(addrs, vec![previous]);
+    // Retried after the negative TTL rather than on every connect.
+    assert!(addr.is_valid().await);
+}
+
+#[tokio::test]
+async fn test_keep_fresh_returns_for_ip_literal() {
+    let addr = UpstreamAddress::new("127.0.0.1:80".to_string());
+    let token = CancellationToken::new();
+    tokio::time::timeout(
+        std::time::Duration::from_secs(1),
+        addr.keep_fresh(ResolutionMode::Ipv4AndIpv6, token),
+    )
+    .await
+    .expect("keep_fresh should not loop for an IP literal");
+}
+
+#[tokio::test]
+async fn test_keep_fresh_stops_on_cancel() {
+    let addr = UpstreamAddress::new("localhost:80".to_string());
+    let token = CancellationToken::new();
+    let task = tokio::spawn({
+        let addr = addr.clone();
+        let token = token.clone();
+        async move { addr.keep_fresh(ResolutionMode::Ipv4AndIpv6, token).await }
+    });
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    token.cancel();
+    tokio::time::timeout(std::time::Duration::from_secs(1), task)
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+// --- Resolver ---
+
+#[test]
+fn test_clamp_ttl() {
+    let resolver = resolver::Resolver::new(crate::config::DnsConfig::default());
+    let secs = std::time::Duration::from_secs;
+    assert_eq!(resolver.clamp_ttl(secs(0)), secs(5));
+    assert_eq!(resolver.clamp_ttl(secs(60)), secs(60));
+    assert_eq!(resolver.clamp_ttl(secs(86400)), secs(300));
+}
+
+#[test]
+fn test_is_literal() {
+    assert!(resolver::is_literal("127.0.0.1:80"));
+    assert!(resolver::is_literal("[::1]:443"));
+    assert!(!resolver::is_literal("localhost:80"));
+    assert!(!resolver::is_literal("proxy.example.com:3128"));
+}
+
+#[tokio::test]
+async fn test_lookup_ip_literal_uses_max_ttl() {
+    let resolver = resolver::Resolver::new(crate::config::DnsConfig::default());
+    let (addrs, ttl) = resolver.lookup("[::1]:443").await.unwrap();
+    assert_eq!(addrs, vec!["[::1]:443".parse().unwrap()]);
+    assert_eq!(ttl, std::time::Duration::from_secs(300));
+}
+
+#[tokio::test]
+async fn test_lookup_rejects_missing_port() {
+    let resolver = resolver::Resolver::new(crate::config::DnsConfig::default());
+    assert!(resolver.lookup("example.com").await.is_err());
+}
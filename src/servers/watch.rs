@@ -0,0 +1,70 @@
+use log::{debug, info};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+
+// ---------------------------------------------------------------------------
+// Config file watcher
+//
+// Polls the config file and requests a reload when it changes. The path is
+// resolved through symlinks on every check, so the Kubernetes ConfigMap
+// pattern — `config.yaml -> ..data/config.yaml`, with `..data` re-pointed to
+// a new directory on update — is seen as a change even when the new file has
+// the same size and timestamp.
+// ---------------------------------------------------------------------------
+
+/// What identifies one version of the config file.
+#[derive(Debug, PartialEq, Eq)]
+pub(super) struct Fingerprint {
+    target: PathBuf,
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+/// `None` while the file cannot be read, e.g. in the middle of a swap.
+pub(super) fn fingerprint(path: &str) -> Option<Fingerprint> {
+    let target = fs::canonicalize(path).ok()?;
+    let metadata = fs::metadata(&target).ok()?;
+    Some(Fingerprint {
+        modified: metadata.modified().ok(),
+        len: metadata.len(),
+        target,
+    })
+}
+
+/// Check `path` every `interval` and notify `reload` when it changed, until
+/// `token` is cancelled.
+pub(super) async fn run(
+    path: String,
+    interval: Duration,
+    reload: Arc<Notify>,
+    token: CancellationToken,
+) {
+    let mut last = fingerprint(&path);
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = token.cancelled() => return,
+        }
+        let Some(current) = fingerprint(&path) else {
+            debug!("Config file {} is not readable, checking again later", path);
+            continue;
+        };
+        if last.as_ref() != Some(&current) {
+            info!(
+                "Config file {} changed ({}), reloading.",
+                path,
+                current.target.display()
+            );
+            last = Some(current);
+            reload.notify_one();
+        }
+    }
+}
+
+#[cfg(test)]
+#[path = "watch_tests.rs"]
+mod tests;
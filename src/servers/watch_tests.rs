@@ -0,0 +1,77 @@
+use super::*;
+use std::os::unix::fs::symlink;
+use std::path::Path;
+
+/// Lay out a directory like a mounted ConfigMap:
+/// `config.yaml -> ..data/config.yaml`, `..data -> ..v1`.
+fn configmap(dir: &Path) -> String {
+    for version in ["..v1", "..v2"] {
+        fs::create_dir(dir.join(version)).unwrap();
+        fs::write(dir.join(version).join("config.yaml"), "version: 1\n").unwrap();
+    }
+    // Same size and timestamp: only the symlink target tells them apart.
+    let modified = fs::metadata(dir.join("..v1/config.yaml"))
+        .unwrap()
+        .modified()
+        .unwrap();
+    fs::File::options()
+        .write(true)
+        .open(dir.join("..v2/config.yaml"))
+        .unwrap()
+        .set_modified(modified)
+        .unwrap();
+    symlink("..v1", dir.join("..data")).unwrap();
+    symlink("..data/config.yaml", dir.join("config.yaml")).unwrap();
+    dir.join("config.yaml").to_string_lossy().into_owned()
+}
+
+/// Atomically re-point `..data`, as the kubelet does.
+fn swap_data(dir: &Path, version: &str) {
+    symlink(version, dir.join("..data_tmp")).unwrap();
+    fs::rename(dir.join("..data_tmp"), dir.join("..data")).unwrap();
+}
+
+#[test]
+fn test_fingerprint_follows_configmap_symlink_swap() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = configmap(dir.path());
+    let before = fingerprint(&path).unwrap();
+    assert_eq!(fingerprint(&path).unwrap(), before);
+
+    swap_data(dir.path(), "..v2");
+    assert_ne!(fingerprint(&path).unwrap(), before);
+}
+
+#[test]
+fn test_fingerprint_missing_file() {
+    assert!(fingerprint("/nonexistent/tpt/config.yaml").is_none());
+}
+
+#[tokio::test]
+async fn test_run_notifies_on_change() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = configmap(dir.path());
+    let reload = Arc::new(Notify::new());
+    let token = CancellationToken::new();
+    let watcher = tokio::spawn(run(
+        path,
+        Duration::from_millis(20),
+        reload.clone(),
+        token.clone(),
+    ));
+
+    // Nothing changed yet.
+    assert!(
+        tokio::time::timeout(Duration::from_millis(100), reload.notified())
+            .await
+            .is_err()
+    );
+
+    swap_data(dir.path(), "..v2");
+    tokio::time::timeout(Duration::from_secs(2), reload.notified())
+        .await
+        .expect("no reload after the ConfigMap update");
+
+    token.cancel();
+    watcher.await.unwrap();
+}
@@ -0,0 +1,481 @@
+use http_body_util::Full;
+use hyper::Request;
+use hyper::body::Bytes;
+use hyper::header::{CONTENT_TYPE, HOST};
+use hyper_util::rt::TokioIo;
+use log::{debug, info, warn};
+use serde_json::{Value as Json, json};
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::TcpStream;
+use tokio_util::sync::CancellationToken;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Span, Subscriber, dispatcher};
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::registry::{LookupSpan, Registry};
+use url::{Host, Url};
+
+use crate::config::TracingConfig;
+
+// ---------------------------------------------------------------------------
+// Connection tracing
+//
+// Each connection is a trace: an `accept` span with `route`, `resolve`,
+// `connect`, `http_connect`/`socks5_connect` and `relay` spans below it.
+// With `tracing.otlp_endpoint` set, `SpanLayer` collects the spans of this
+// crate as they close and `export` posts them to the collector every
+// `export_interval`, in the OTLP/HTTP JSON encoding. Without it no subscriber
+// is installed and the spans cost next to nothing.
+//
+// A field named `error` becomes the span's error status rather than an
+// attribute.
+//
+// Traces cross proxies with W3C trace context: the `traceparent` header of a
+// client's CONNECT request becomes the parent of its `accept` span, and a
+// chained CONNECT carries the `http_connect` span as its `traceparent`.
+// ---------------------------------------------------------------------------
+
+/// Spans held while the collector is unreachable; later ones are dropped.
+const MAX_PENDING_SPANS: usize = 8192;
+
+/// How long one export request may take.
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    Str(String),
+    Int(i64),
+    Bool(bool),
+    Float(f64),
+}
+
+impl Value {
+    fn to_json(&self) -> Json {
+        match self {
+            // OTLP JSON encodes 64-bit integers as strings.
+            Value::Str(s) => json!({ "stringValue": s }),
+            Value::Int(i) => json!({ "intValue": i.to_string() }),
+            Value::Bool(b) => json!({ "boolValue": b }),
+            Value::Float(f) => json!({ "doubleValue": f }),
+        }
+    }
+}
+
+/// A closed span, ready to export.
+#[derive(Debug, Clone)]
+pub(crate) struct SpanRecord {
+    pub trace_id: u128,
+    pub span_id: u64,
+    pub parent_span_id: Option<u64>,
+    pub name: &'static str,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    pub attributes: Vec<(&'static str, Value)>,
+    pub error: Option<String>,
+    /// The connection's span, with no parent or a remote one.
+    pub server: bool,
+}
+
+impl SpanRecord {
+    fn to_json(&self) -> Json {
+        let mut span = json!({
+            "traceId": format!("{:032x}", self.trace_id),
+            "spanId": format!("{:016x}", self.span_id),
+            "name": self.name,
+            // SERVER for the connection, INTERNAL for the steps within it.
+            "kind": if self.server { 2 } else { 1 },
+            "startTimeUnixNano": unix_nanos(self.start).to_string(),
+            "endTimeUnixNano": unix_nanos(self.end).to_string(),
+            "attributes": self.attributes.iter()
+                .map(|(key, value)| json!({ "key": key, "value": value.to_json() }))
+                .collect::<Vec<_>>(),
+        });
+        if let Some(parent) = self.parent_span_id {
+            span["parentSpanId"] = json!(format!("{:016x}", parent));
+        }
+        if let Some(error) = &self.error {
+            span["status"] = json!({ "code": 2, "message": error });
+        }
+        span
+    }
+}
+
+fn unix_nanos(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos())
+}
+
+/// OTLP `ExportTraceServiceRequest` for `spans`.
+pub(crate) fn encode(service_name: &str, spans: &[SpanRecord]) -> Json {
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [{
+                    "key": "service.name",
+                    "value": { "stringValue": service_name },
+                }],
+            },
+            "scopeSpans": [{
+                "scope": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                },
+                "spans": spans.iter().map(SpanRecord::to_json).collect::<Vec<_>>(),
+            }],
+        }],
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Collecting spans
+// ---------------------------------------------------------------------------
+
+/// Closed spans waiting for the exporter.
+#[derive(Debug, Default)]
+pub(crate) struct Collector {
+    /// Running exporters; spans are only kept while there is one.
+    exporters: AtomicUsize,
+    spans: Mutex<Vec<SpanRecord>>,
+    dropped: AtomicU64,
+}
+
+impl Collector {
+    fn spans(&self) -> MutexGuard<'_, Vec<SpanRecord>> {
+        self.spans.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn is_active(&self) -> bool {
+        self.exporters.load(Ordering::Relaxed) > 0
+    }
+
+    fn push(&self, span: SpanRecord) {
+        let mut spans = self.spans();
+        if spans.len() < MAX_PENDING_SPANS {
+            spans.push(span);
+        } else {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Take all pending spans.
+    pub fn take(&self) -> Vec<SpanRecord> {
+        std::mem::take(&mut *self.spans())
+    }
+
+    /// Record spans until the returned guard is dropped.
+    pub fn exporting(&self) -> Exporting<'_> {
+        self.exporters.fetch_add(1, Ordering::Relaxed);
+        Exporting(self)
+    }
+}
+
+/// Held by a running exporter; see `Collector::exporting`.
+pub(crate) struct Exporting<'a>(&'a Collector);
+
+impl Drop for Exporting<'_> {
+    fn drop(&mut self) {
+        self.0.exporters.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The process-wide collector fed by the installed `SpanLayer`.
+fn collector() -> &'static Arc<Collector> {
+    static COLLECTOR: OnceLock<Arc<Collector>> = OnceLock::new();
+    COLLECTOR.get_or_init(Arc::default)
+}
+
+/// What is known about a span while it is open; kept in its extensions.
+struct OpenSpan {
+    trace_id: u128,
+    span_id: u64,
+    parent_span_id: Option<u64>,
+    start: SystemTime,
+    attributes: Vec<(&'static str, Value)>,
+    error: Option<String>,
+    server: bool,
+}
+
+impl Visit for OpenSpan {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.set(field, Value::Str(format!("{:?}", value)));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.set(field, Value::Str(value.to_string()));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.set(field, Value::Int(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.set(field, Value::Int(i64::try_from(value).unwrap_or(i64::MAX)));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.set(field, Value::Bool(value));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.set(field, Value::Float(value));
+    }
+}
+
+impl OpenSpan {
+    fn set(&mut self, field: &Field, value: Value) {
+        if field.name() == "error" {
+            self.error = Some(match value {
+                Value::Str(s) => s,
+                other => format!("{:?}", other),
+            });
+        } else if let Some(slot) = self.attributes.iter_mut().find(|(k, _)| *k == field.name()) {
+            slot.1 = value;
+        } else {
+            self.attributes.push((field.name(), value));
+        }
+    }
+}
+
+/// Records this crate's spans into a `Collector`.
+pub(crate) struct SpanLayer {
+    collector: Arc<Collector>,
+}
+
+impl SpanLayer {
+    pub fn new(collector: Arc<Collector>) -> Self {
+        SpanLayer { collector }
+    }
+}
+
+impl<S> Layer<S> for SpanLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if !self.collector.is_active()
+            || !attrs
+                .metadata()
+                .target()
+                .starts_with(env!("CARGO_CRATE_NAME"))
+        {
+            return;
+        }
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let parent = span.parent().and_then(|parent| {
+            let extensions = parent.extensions();
+            extensions
+                .get::<OpenSpan>()
+                .map(|open| (open.trace_id, open.span_id))
+        });
+        let mut open = OpenSpan {
+            trace_id: parent.map_or_else(|| fastrand::u128(1..), |(trace_id, _)| trace_id),
+            span_id: fastrand::u64(1..),
+            parent_span_id: parent.map(|(_, span_id)| span_id),
+            start: SystemTime::now(),
+            attributes: Vec::new(),
+            error: None,
+            server: parent.is_none(),
+        };
+        attrs.record(&mut open);
+        span.extensions_mut().insert(open);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id)
+            && let Some(open) = span.extensions_mut().get_mut::<OpenSpan>()
+        {
+            values.record(open);
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(open) = span.extensions_mut().remove::<OpenSpan>() else {
+            return;
+        };
+        if !self.collector.is_active() {
+            return;
+        }
+        self.collector.push(SpanRecord {
+            trace_id: open.trace_id,
+            span_id: open.span_id,
+            parent_span_id: open.parent_span_id,
+            name: span.name(),
+            start: open.start,
+            end: SystemTime::now(),
+            attributes: open.attributes,
+            error: open.error,
+            server: open.server,
+        });
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Trace context
+// ---------------------------------------------------------------------------
+
+/// Trace and parent span ID of a W3C `traceparent` header value.
+fn parse_traceparent(value: &str) -> Option<(u128, u64)> {
+    let mut parts = value.trim().split('-');
+    let (Some(version), Some(trace_id), Some(span_id), Some(flags)) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return None;
+    };
+    let hex = |s: &str, len: usize| s.len() == len && s.bytes().all(|b| b.is_ascii_hexdigit());
+    // Later versions may append fields; version 00 has exactly four.
+    if !hex(version, 2) || version == "ff" || (version == "00" && parts.next().is_some()) {
+        return None;
+    }
+    if !hex(trace_id, 32) || !hex(span_id, 16) || !hex(flags, 2) {
+        return None;
+    }
+    let trace_id = u128::from_str_radix(trace_id, 16).ok()?;
+    let span_id = u64::from_str_radix(span_id, 16).ok()?;
+    (trace_id != 0 && span_id != 0).then_some((trace_id, span_id))
+}
+
+/// Run `f` on `span`'s `OpenSpan`, if it is being recorded.
+fn with_open_span<T>(span: &Span, f: impl FnOnce(&mut OpenSpan) -> T) -> Option<T> {
+    span.with_subscriber(|(id, dispatch)| {
+        let registry = dispatch.downcast_ref::<Registry>()?;
+        let span = registry.span(id)?;
+        let mut extensions = span.extensions_mut();
+        extensions.get_mut::<OpenSpan>().map(f)
+    })
+    .flatten()
+}
+
+/// Continue the trace named by a `traceparent` header in `span`. Call it
+/// before `span` has children; an invalid value is ignored.
+pub(crate) fn set_remote_parent(span: &Span, traceparent: &str) {
+    let Some((trace_id, parent_span_id)) = parse_traceparent(traceparent) else {
+        debug!("Ignoring invalid traceparent {:?}", traceparent);
+        return;
+    };
+    with_open_span(span, |open| {
+        open.trace_id = trace_id;
+        open.parent_span_id = Some(parent_span_id);
+    });
+}
+
+/// `traceparent` header value naming the current span, if it is recorded.
+pub(crate) fn traceparent() -> Option<String> {
+    with_open_span(&Span::current(), |open| {
+        format!("00-{:032x}-{:016x}-01", open.trace_id, open.span_id)
+    })
+}
+
+/// Make `SpanLayer` on the process-wide collector the global subscriber.
+fn install() {
+    static INSTALLED: OnceLock<()> = OnceLock::new();
+    INSTALLED.get_or_init(|| {
+        let subscriber = Registry::default().with(SpanLayer::new(collector().clone()));
+        if let Err(e) = dispatcher::set_global_default(subscriber.into()) {
+            warn!("Could not install the span collector: {}", e);
+        }
+    });
+}
+
+// ---------------------------------------------------------------------------
+// Exporting spans
+// ---------------------------------------------------------------------------
+
+/// Post collected spans to `config.otlp_endpoint` every `export_interval`
+/// until `token` is cancelled, then once more.
+pub(crate) async fn export(config: TracingConfig, token: CancellationToken) {
+    let Some(endpoint) = config.otlp_endpoint else {
+        return;
+    };
+    install();
+    let collector = collector();
+    let _exporting = collector.exporting();
+    info!(
+        "Exporting spans to {} every {:?}",
+        endpoint, config.export_interval
+    );
+
+    let mut ticker = tokio::time::interval(config.export_interval);
+    ticker.tick().await; // skip the first immediate tick
+    loop {
+        let stop = tokio::select! {
+            _ = ticker.tick() => false,
+            _ = token.cancelled() => true,
+        };
+        flush(&endpoint, &config.service_name, collector).await;
+        if stop {
+            break;
+        }
+    }
+}
+
+/// Send everything in `collector` to `endpoint`. Spans that cannot be sent
+/// are dropped.
+pub(crate) async fn flush(endpoint: &Url, service_name: &str, collector: &Collector) {
+    let dropped = collector.dropped.swap(0, Ordering::Relaxed);
+    if dropped > 0 {
+        warn!(
+            "Dropped {} spans: more than {} waiting for export",
+            dropped, MAX_PENDING_SPANS
+        );
+    }
+    let spans = collector.take();
+    if spans.is_empty() {
+        return;
+    }
+    let body = encode(service_name, &spans).to_string();
+    match tokio::time::timeout(EXPORT_TIMEOUT, post(endpoint, body)).await {
+        Ok(Ok(())) => debug!("Exported {} spans to {}", spans.len(), endpoint),
+        Ok(Err(e)) => warn!(
+            "Could not export {} spans to {}: {}",
+            spans.len(),
+            endpoint,
+            e
+        ),
+        Err(_) => warn!(
+            "Could not export {} spans to {}: timed out after {:?}",
+            spans.len(),
+            endpoint,
+            EXPORT_TIMEOUT
+        ),
+    }
+}
+
+/// `/v1/traces` below the endpoint's path.
+fn traces_path(endpoint: &Url) -> String {
+    format!("{}/v1/traces", endpoint.path().trim_end_matches('/'))
+}
+
+async fn post(endpoint: &Url, body: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (host, authority) = match (endpoint.host(), endpoint.host_str()) {
+        (Some(Host::Ipv6(ip)), Some(authority)) => (ip.to_string(), authority),
+        (Some(host), Some(authority)) => (host.to_string(), authority),
+        _ => return Err("endpoint has no host".into()),
+    };
+    let port = endpoint.port_or_known_default().unwrap_or(80);
+    let stream = TcpStream::connect((host.as_str(), port)).await?;
+    let (mut sender, connection) =
+        hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+    tokio::spawn(connection);
+
+    let request = Request::post(traces_path(endpoint))
+        .header(HOST, format!("{}:{}", authority, port))
+        .header(CONTENT_TYPE, "application/json")
+        .body(Full::new(Bytes::from(body)))?;
+    let response = sender.send_request(request).await?;
+    if !response.status().is_success() {
+        return Err(format!("collector answered {}", response.status()).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[path = "telemetry_tests.rs"]
+mod tests;
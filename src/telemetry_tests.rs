@@ -0,0 +1,265 @@
+use super::*;
+use http_body_util::BodyExt;
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Response, StatusCode};
+use std::convert::Infallible;
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tracing::field::Empty;
+use tracing::info_span;
+
+fn subscriber(collector: &Arc<Collector>) -> impl Subscriber {
+    Registry::default().with(SpanLayer::new(collector.clone()))
+}
+
+fn span(name: &'static str, parent_span_id: Option<u64>) -> SpanRecord {
+    SpanRecord {
+        trace_id: 0xabc,
+        span_id: 7,
+        parent_span_id,
+        name,
+        start: UNIX_EPOCH + Duration::from_secs(1),
+        end: UNIX_EPOCH + Duration::from_millis(1500),
+        attributes: vec![
+            ("route", Value::Str("default".to_string())),
+            ("bytes_tx", Value::Int(517)),
+        ],
+        error: None,
+        server: parent_span_id.is_none(),
+    }
+}
+
+#[test]
+fn test_layer_records_span_tree() {
+    let collector = Arc::new(Collector::default());
+    let _exporting = collector.exporting();
+    tracing::subscriber::with_default(subscriber(&collector), || {
+        let root = info_span!("accept", conn_id = "c1", error = Empty);
+        root.in_scope(|| {
+            let child = info_span!("route", route = Empty, hits = 1u64);
+            child.record("route", "*.example.com");
+        });
+        root.record("error", "upstream refused");
+    });
+
+    let spans = collector.take();
+    assert_eq!(spans.len(), 2);
+    let (child, root) = (&spans[0], &spans[1]);
+    assert_eq!((child.name, root.name), ("route", "accept"));
+    assert_eq!(root.parent_span_id, None);
+    assert_eq!(child.parent_span_id, Some(root.span_id));
+    assert_eq!(child.trace_id, root.trace_id);
+    assert_eq!(
+        child.attributes,
+        vec![
+            ("hits", Value::Int(1)),
+            ("route", Value::Str("*.example.com".to_string())),
+        ]
+    );
+    assert_eq!(root.error.as_deref(), Some("upstream refused"));
+    assert!(root.end >= child.end);
+}
+
+#[test]
+fn test_parse_traceparent() {
+    assert_eq!(
+        parse_traceparent("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"),
+        Some((0x0af7651916cd43dd8448eb211c80319c, 0xb7ad6b7169203331))
+    );
+    // A later version may add fields.
+    assert!(
+        parse_traceparent("01-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01-x").is_some()
+    );
+    for invalid in [
+        "",
+        "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331",
+        "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01-x",
+        "ff-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+        "00-00000000000000000000000000000000-b7ad6b7169203331-01",
+        "00-0af7651916cd43dd8448eb211c80319c-0000000000000000-01",
+        "00-0af7651916cd43dd8448eb211c8031-b7ad6b7169203331-01",
+        "00-0af7651916cd43dd8448eb211c80319g-b7ad6b7169203331-01",
+    ] {
+        assert_eq!(parse_traceparent(invalid), None, "{}", invalid);
+    }
+}
+
+#[test]
+fn test_remote_parent_and_traceparent() {
+    let collector = Arc::new(Collector::default());
+    let _exporting = collector.exporting();
+    let header = tracing::subscriber::with_default(subscriber(&collector), || {
+        let root = info_span!("accept");
+        set_remote_parent(
+            &root,
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+        );
+        root.in_scope(|| info_span!("http_connect").in_scope(traceparent))
+    });
+
+    let spans = collector.take();
+    let (child, root) = (&spans[0], &spans[1]);
+    assert_eq!(root.trace_id, 0x0af7651916cd43dd8448eb211c80319c);
+    assert_eq!(root.parent_span_id, Some(0xb7ad6b7169203331));
+    assert!(root.server && !child.server);
+    assert_eq!(child.trace_id, root.trace_id);
+    assert_eq!(
+        header,
+        Some(format!(
+            "00-0af7651916cd43dd8448eb211c80319c-{:016x}-01",
+            child.span_id
+        ))
+    );
+}
+
+#[test]
+fn test_traceparent_without_recorded_span() {
+    assert_eq!(traceparent(), None);
+}
+
+#[test]
+fn test_layer_records_nothing_without_exporter() {
+    let collector = Arc::new(Collector::default());
+    tracing::subscriber::with_default(subscriber(&collector), || {
+        info_span!("accept").in_scope(|| {});
+    });
+    assert!(collector.take().is_empty());
+}
+
+#[test]
+fn test_layer_caps_pending_spans() {
+    let collector = Arc::new(Collector::default());
+    let _exporting = collector.exporting();
+    tracing::subscriber::with_default(subscriber(&collector), || {
+        for _ in 0..MAX_PENDING_SPANS + 3 {
+            info_span!("accept").in_scope(|| {});
+        }
+    });
+    assert_eq!(collector.take().len(), MAX_PENDING_SPANS);
+    assert_eq!(collector.dropped.load(Ordering::Relaxed), 3);
+}
+
+#[test]
+fn test_encode_otlp_json() {
+    let mut failed = span("connect", Some(3));
+    failed.error = Some("connection refused".to_string());
+    let request = encode("edge-proxy", &[span("accept", None), failed]);
+
+    let resource = &request["resourceSpans"][0];
+    assert_eq!(
+        resource["resource"]["attributes"][0],
+        json!({ "key": "service.name", "value": { "stringValue": "edge-proxy" } })
+    );
+    let spans = &resource["scopeSpans"][0]["spans"];
+    assert_eq!(spans[0]["traceId"], "00000000000000000000000000000abc");
+    assert_eq!(spans[0]["spanId"], "0000000000000007");
+    assert_eq!(spans[0]["kind"], 2);
+    assert_eq!(spans[0]["startTimeUnixNano"], "1000000000");
+    assert_eq!(spans[0]["endTimeUnixNano"], "1500000000");
+    assert!(spans[0].get("parentSpanId").is_none());
+    assert!(spans[0].get("status").is_none());
+    assert_eq!(
+        spans[0]["attributes"][1],
+        json!({ "key": "bytes_tx", "value": { "intValue": "517" } })
+    );
+    assert_eq!(spans[1]["kind"], 1);
+    assert_eq!(spans[1]["parentSpanId"], "0000000000000003");
+    assert_eq!(
+        spans[1]["status"],
+        json!({ "code": 2, "message": "connection refused" })
+    );
+}
+
+#[test]
+fn test_traces_path() {
+    for (endpoint, path) in [
+        ("http://127.0.0.1:4318", "/v1/traces"),
+        ("http://collector/", "/v1/traces"),
+        ("http://collector/otel/", "/otel/v1/traces"),
+    ] {
+        assert_eq!(traces_path(&Url::parse(endpoint).unwrap()), path);
+    }
+}
+
+/// What the stand-in collector received.
+struct Export {
+    path: String,
+    content_type: String,
+    body: Json,
+}
+
+/// OTLP/HTTP collector stand-in: answers one request with `status` and
+/// hands it over.
+async fn collector_stand_in(status: StatusCode) -> (Url, oneshot::Receiver<Export>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+    let (tx, rx) = oneshot::channel();
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let tx = Mutex::new(Some(tx));
+        let service = service_fn(move |request: Request<Incoming>| {
+            let tx = tx.lock().unwrap().take();
+            async move {
+                let path = request.uri().path().to_string();
+                let content_type = request.headers()[CONTENT_TYPE]
+                    .to_str()
+                    .unwrap()
+                    .to_string();
+                let body = request.into_body().collect().await.unwrap().to_bytes();
+                if let Some(tx) = tx {
+                    let _ = tx.send(Export {
+                        path,
+                        content_type,
+                        body: serde_json::from_slice(&body).unwrap(),
+                    });
+                }
+                let mut response = Response::new(Full::new(Bytes::new()));
+                *response.status_mut() = status;
+                Ok::<_, Infallible>(response)
+            }
+        });
+        let _ = http1::Builder::new()
+            .serve_connection(TokioIo::new(stream), service)
+            .await;
+    });
+    (url, rx)
+}
+
+#[tokio::test]
+async fn test_flush_posts_spans_to_collector() {
+    let (endpoint, received) = collector_stand_in(StatusCode::OK).await;
+    let collector = Collector::default();
+    collector.push(span("accept", None));
+    collector.push(span("relay", Some(7)));
+
+    flush(&endpoint, "edge-proxy", &collector).await;
+
+    let export = received.await.unwrap();
+    assert_eq!(export.path, "/v1/traces");
+    assert_eq!(export.content_type, "application/json");
+    let spans = &export.body["resourceSpans"][0]["scopeSpans"][0]["spans"];
+    assert_eq!(spans.as_array().unwrap().len(), 2);
+    assert_eq!(spans[1]["name"], "relay");
+    assert!(collector.take().is_empty());
+}
+
+#[tokio::test]
+async fn test_post_reports_collector_errors() {
+    let (endpoint, received) = collector_stand_in(StatusCode::SERVICE_UNAVAILABLE).await;
+    let body = encode("edge-proxy", &[span("accept", None)]).to_string();
+
+    let error = post(&endpoint, body).await.unwrap_err();
+    assert!(error.to_string().contains("503"), "{}", error);
+    assert!(received.await.is_ok());
+}
+
+#[tokio::test]
+async fn test_post_reports_unreachable_collector() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let endpoint = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+    drop(listener);
+
+    assert!(post(&endpoint, "{}".to_string()).await.is_err());
+}
@@ -99,3 +99,31 @@ fn test_run_help() {
 fn test_run_config_not_found() {
     assert_eq!(run(&s(&["-c", "/nonexistent/path/tpt.yaml"])), Err(1));
 }
+
+// parse_args: --check → Check without resolving
+#[test]
+fn test_parse_args_check() {
+    let result = parse_args(&s(&["--check", "-c", "my.yaml"]));
+    assert!(matches!(
+        result,
+        Ok(Cli::Check { config_path: Some(ref p), resolve: false }) if p == "my.yaml"
+    ));
+}
+
+// parse_args: validate --resolve → Check with resolving, config searched
+#[test]
+fn test_parse_args_validate_resolve() {
+    assert!(matches!(
+        parse_args(&s(&["validate", "--resolve"])),
+        Ok(Cli::Check {
+            config_path: None,
+            resolve: true
+        })
+    ));
+}
+
+// parse_args: --resolve without --check → Err
+#[test]
+fn test_parse_args_resolve_requires_check() {
+    assert!(parse_args(&s(&["--resolve"])).is_err());
+}
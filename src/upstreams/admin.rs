@@ -0,0 +1,169 @@
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE};
+use hyper::{Method, Request, Response, StatusCode};
+use log::info;
+use percent_encoding::percent_decode_str;
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::access_log::ConnectionId;
+use crate::config::AdminConfig;
+use crate::connections;
+
+use super::MetricsData;
+
+// ---------------------------------------------------------------------------
+// Admin API — served below /admin/ by `health` servers
+//
+//   GET    /admin/tunnels               open tunnels, oldest first
+//   DELETE /admin/tunnels/{id}          kill one
+//   POST   /admin/servers/{name}/drain  stop taking new connections
+//   DELETE /admin/servers/{name}/drain  take them again
+//   GET    /admin/config                effective running config
+//
+// Disabled (404) unless `admin.token` is set; every request must carry it as
+// `Authorization: Bearer <token>`. The health port speaks plain HTTP, so only
+// connections to a loopback address are served unless `admin.allow_remote`
+// is set. Tunnels and draining come from the process-wide
+// `connections::registry()`.
+// ---------------------------------------------------------------------------
+
+/// Admin API settings of the running config.
+#[derive(Clone, Default)]
+pub struct AdminApi {
+    token: Option<String>,
+    allow_remote: bool,
+    config: Arc<Value>,
+}
+
+/// Leaves the token and the config out of the server's debug output.
+impl fmt::Debug for AdminApi {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AdminApi")
+            .field("enabled", &self.token.is_some())
+            .finish()
+    }
+}
+
+impl AdminApi {
+    /// `config` is what `/admin/config` returns; see `ParsedConfig::to_json`.
+    pub fn new(settings: &AdminConfig, config: Value) -> Self {
+        AdminApi {
+            token: settings.token.clone(),
+            allow_remote: settings.allow_remote,
+            config: Arc::new(config),
+        }
+    }
+
+    fn authorized<B>(token: &str, req: &Request<B>) -> bool {
+        req.headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|given| digests_eq(given, token))
+    }
+}
+
+/// Compare SHA-256 digests without returning early, so the time taken says
+/// nothing about the token's contents or length.
+fn digests_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (Sha256::digest(a), Sha256::digest(b));
+    a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Answer a request for a path below `/admin/`, received on `local`.
+pub(super) fn handle<B>(
+    req: &Request<B>,
+    metrics: &MetricsData,
+    local: SocketAddr,
+) -> Response<Full<Bytes>> {
+    let admin = &metrics.admin;
+    let Some(token) = &admin.token else {
+        return error(StatusCode::NOT_FOUND, "not found");
+    };
+    if !admin.allow_remote && !local.ip().to_canonical().is_loopback() {
+        return error(StatusCode::NOT_FOUND, "not found");
+    }
+    if !AdminApi::authorized(token, req) {
+        let mut response = error(StatusCode::UNAUTHORIZED, "missing or invalid token");
+        response
+            .headers_mut()
+            .insert(WWW_AUTHENTICATE, "Bearer".parse().unwrap());
+        return response;
+    }
+
+    let path = req.uri().path().trim_start_matches("/admin/");
+    let segments: Vec<&str> = path.trim_end_matches('/').split('/').collect();
+    match (req.method(), segments.as_slice()) {
+        (&Method::GET, ["tunnels"]) => {
+            let tunnels = connections::registry().list();
+            respond(StatusCode::OK, json!({ "tunnels": tunnels }))
+        }
+        (&Method::DELETE, ["tunnels", id]) => kill(id),
+        (&Method::POST, ["servers", name, "drain"]) => drain(metrics, name, true),
+        (&Method::DELETE, ["servers", name, "drain"]) => drain(metrics, name, false),
+        (&Method::GET, ["config"]) => {
+            respond(StatusCode::OK, metrics.admin.config.as_ref().clone())
+        }
+        (_, ["tunnels"] | ["tunnels", _] | ["servers", _, "drain"] | ["config"]) => {
+            error(StatusCode::METHOD_NOT_ALLOWED, "method not allowed")
+        }
+        _ => error(StatusCode::NOT_FOUND, "not found"),
+    }
+}
+
+fn kill(id: &str) -> Response<Full<Bytes>> {
+    let id: ConnectionId = match id.parse() {
+        Ok(id) => id,
+        Err(e) => return error(StatusCode::BAD_REQUEST, &e),
+    };
+    if !connections::registry().kill(id) {
+        return error(StatusCode::NOT_FOUND, "no such tunnel");
+    }
+    info!("Admin API: killing connection {}", id);
+    respond(StatusCode::OK, json!({ "killed": id }))
+}
+
+fn drain(metrics: &MetricsData, name: &str, draining: bool) -> Response<Full<Bytes>> {
+    let name = percent_decode_str(name).decode_utf8_lossy();
+    if !metrics.servers.iter().any(|s| s.name == name) {
+        return error(StatusCode::NOT_FOUND, "no such server");
+    }
+    let registry = connections::registry();
+    if registry.is_draining(&name) != draining {
+        info!(
+            "Admin API: {} server '{}'",
+            if draining { "draining" } else { "resuming" },
+            name
+        );
+    }
+    registry.set_draining(&name, draining);
+    respond(
+        StatusCode::OK,
+        json!({
+            "server": name,
+            "draining": draining,
+            "active": registry.active(&name),
+        }),
+    )
+}
+
+fn respond(status: StatusCode, body: Value) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Full::new(Bytes::from(body.to_string())))
+        .unwrap()
+}
+
+fn error(status: StatusCode, message: &str) -> Response<Full<Bytes>> {
+    respond(status, json!({ "error": message }))
+}
+
+#[cfg(test)]
+#[path = "admin_tests.rs"]
+mod tests;
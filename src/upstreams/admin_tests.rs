@@ -0,0 +1,207 @@
+use super::*;
+use crate::connections::Connection;
+use crate::upstreams::MetricsEntry;
+use http_body_util::BodyExt;
+use std::sync::atomic::AtomicU64;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+const TOKEN: &str = "s3cret";
+
+fn loopback() -> SocketAddr {
+    "127.0.0.1:8080".parse().unwrap()
+}
+
+fn settings(token: Option<&str>) -> AdminConfig {
+    AdminConfig {
+        token: token.map(String::from),
+        ..Default::default()
+    }
+}
+
+fn metrics(token: Option<&str>, server: &str) -> MetricsData {
+    MetricsData {
+        servers: vec![MetricsEntry {
+            name: server.to_string(),
+            listen: "127.0.0.1:8443".to_string(),
+            maxclients_limit: 10,
+            semaphore: Arc::new(Semaphore::new(10)),
+            client_hello_timeouts: Arc::new(AtomicU64::new(0)),
+        }],
+        upstreams: Vec::new(),
+        admin: AdminApi::new(
+            &settings(token),
+            json!({ "version": 1, "admin": { "token": "redacted" } }),
+        ),
+    }
+}
+
+fn request(method: Method, path: &str, token: Option<&str>) -> Request<()> {
+    let mut builder = Request::builder().method(method).uri(path);
+    if let Some(token) = token {
+        builder = builder.header(AUTHORIZATION, format!("Bearer {}", token));
+    }
+    builder.body(()).unwrap()
+}
+
+async fn call(metrics: &MetricsData, request: Request<()>) -> (StatusCode, Value) {
+    let response = handle(&request, metrics, loopback());
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+#[tokio::test]
+async fn test_admin_disabled_without_token() {
+    let metrics = metrics(None, "web");
+    for token in [None, Some(TOKEN)] {
+        let (status, _) = call(&metrics, request(Method::GET, "/admin/config", token)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
+
+#[tokio::test]
+async fn test_admin_requires_token() {
+    let metrics = metrics(Some(TOKEN), "web");
+    for token in [
+        None,
+        Some("wrong"),
+        Some("s3cre"),
+        Some("s3crets"),
+        Some(""),
+    ] {
+        let response = handle(
+            &request(Method::GET, "/admin/config", token),
+            &metrics,
+            loopback(),
+        );
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{:?}", token);
+        assert_eq!(response.headers()[WWW_AUTHENTICATE], "Bearer");
+    }
+    let basic = Request::builder()
+        .uri("/admin/config")
+        .header(AUTHORIZATION, format!("Basic {}", TOKEN))
+        .body(())
+        .unwrap();
+    assert_eq!(
+        handle(&basic, &metrics, loopback()).status(),
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[test]
+fn test_admin_loopback_only_by_default() {
+    let request = request(Method::GET, "/admin/config", Some(TOKEN));
+    let mut metrics = metrics(Some(TOKEN), "web");
+    for local in ["127.0.0.1:8080", "[::1]:8080", "[::ffff:127.0.0.1]:8080"] {
+        let response = handle(&request, &metrics, local.parse().unwrap());
+        assert_eq!(response.status(), StatusCode::OK, "{}", local);
+    }
+    let remote: SocketAddr = "192.0.2.10:8080".parse().unwrap();
+    assert_eq!(
+        handle(&request, &metrics, remote).status(),
+        StatusCode::NOT_FOUND
+    );
+
+    metrics.admin = AdminApi::new(
+        &AdminConfig {
+            allow_remote: true,
+            ..settings(Some(TOKEN))
+        },
+        json!({}),
+    );
+    assert_eq!(handle(&request, &metrics, remote).status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_admin_config() {
+    let metrics = metrics(Some(TOKEN), "web");
+    let (status, body) = call(&metrics, request(Method::GET, "/admin/config", Some(TOKEN))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["version"], 1);
+
+    let (status, _) = call(
+        &metrics,
+        request(Method::POST, "/admin/config", Some(TOKEN)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+    let (status, _) = call(
+        &metrics,
+        request(Method::GET, "/admin/nothing", Some(TOKEN)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_admin_lists_and_kills_tunnels() {
+    let metrics = metrics(Some(TOKEN), "web");
+    let conn = Arc::new(Connection::new(ConnectionId::new(), "web"));
+    let _registered = connections::registry().register(conn.clone());
+
+    let (status, body) = call(
+        &metrics,
+        request(Method::GET, "/admin/tunnels", Some(TOKEN)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let listed = body["tunnels"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|t| t["id"] == conn.id.to_string())
+        .expect("registered tunnel is listed");
+    assert_eq!(listed["server"], "web");
+    assert!(listed["age_ms"].is_u64());
+
+    let path = format!("/admin/tunnels/{}", conn.id);
+    let (status, body) = call(&metrics, request(Method::DELETE, &path, Some(TOKEN))).await;
+    assert_eq!((status, &body["killed"]), (StatusCode::OK, &json!(conn.id)));
+    tokio::time::timeout(Duration::from_secs(1), conn.killed())
+        .await
+        .unwrap();
+
+    let path = format!("/admin/tunnels/{}", ConnectionId::new());
+    let (status, _) = call(&metrics, request(Method::DELETE, &path, Some(TOKEN))).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, body) = call(
+        &metrics,
+        request(Method::DELETE, "/admin/tunnels/nope", Some(TOKEN)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], r#"invalid connection ID "nope""#);
+}
+
+#[tokio::test]
+async fn test_admin_drains_server() {
+    let metrics = metrics(Some(TOKEN), "admin drain test");
+    let path = "/admin/servers/admin%20drain%20test/drain";
+
+    let (status, body) = call(&metrics, request(Method::POST, path, Some(TOKEN))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body,
+        json!({ "server": "admin drain test", "draining": true, "active": 0 })
+    );
+    assert!(connections::registry().is_draining("admin drain test"));
+
+    let (status, body) = call(&metrics, request(Method::DELETE, path, Some(TOKEN))).await;
+    assert_eq!((status, &body["draining"]), (StatusCode::OK, &json!(false)));
+    assert!(!connections::registry().is_draining("admin drain test"));
+
+    let (status, _) = call(
+        &metrics,
+        request(Method::POST, "/admin/servers/unknown/drain", Some(TOKEN)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn test_debug_hides_token_and_config() {
+    let admin = AdminApi::new(&settings(Some(TOKEN)), json!({ "secret": "value" }));
+    let debug = format!("{:?}", admin);
+    assert_eq!(debug, "AdminApi { enabled: true }");
+}
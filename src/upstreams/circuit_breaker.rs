@@ -0,0 +1,170 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::time::Instant;
+
+use crate::config::CircuitBreakerConfig;
+
+// ---------------------------------------------------------------------------
+// CircuitBreaker — connect outcomes for one ProxyToUpstream
+//
+// Closed: connects go through; `consecutive_failures` failures in a row, or
+// an `error_rate` over the last `window` connects, open the breaker.
+// Open: connects are rejected until `cooldown` has passed, then one trial
+// connect is let through (half-open). Its success closes the breaker, its
+// failure opens it for another cooldown.
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+impl CircuitState {
+    /// Value of the `tpt_upstream_circuit_state` gauge.
+    pub fn as_metric(self) -> u8 {
+        match self {
+            CircuitState::Closed => 0,
+            CircuitState::Open => 1,
+            CircuitState::HalfOpen => 2,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    state: CircuitState,
+    /// When the breaker opened, or when the half-open trial started.
+    since: Instant,
+    consecutive_failures: u32,
+    /// Recent outcomes, `true` = failed.
+    recent: VecDeque<bool>,
+}
+
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    pub config: CircuitBreakerConfig,
+    inner: Mutex<Inner>,
+    trips_total: AtomicU64,
+    rejections_total: AtomicU64,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        CircuitBreaker {
+            inner: Mutex::new(Inner {
+                state: CircuitState::Closed,
+                since: Instant::now(),
+                consecutive_failures: 0,
+                recent: VecDeque::with_capacity(config.window as usize),
+            }),
+            config,
+            trips_total: AtomicU64::new(0),
+            rejections_total: AtomicU64::new(0),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn state(&self) -> CircuitState {
+        self.lock().state
+    }
+
+    /// Times the breaker opened, including re-opening after a failed trial.
+    pub fn trips_total(&self) -> u64 {
+        self.trips_total.load(Ordering::Relaxed)
+    }
+
+    /// Connects rejected while the breaker was open.
+    pub fn rejections_total(&self) -> u64 {
+        self.rejections_total.load(Ordering::Relaxed)
+    }
+
+    /// Whether `allow` would currently let a connect through. Does not start
+    /// a half-open trial.
+    pub fn would_allow(&self) -> bool {
+        let inner = self.lock();
+        inner.state == CircuitState::Closed || inner.since.elapsed() >= self.config.cooldown
+    }
+
+    /// Ask to connect. Once the cooldown has passed a single trial is let
+    /// through; if its outcome is never recorded, another one is allowed
+    /// after a further cooldown.
+    pub fn allow(&self) -> bool {
+        let mut inner = self.lock();
+        if inner.state == CircuitState::Closed {
+            return true;
+        }
+        if inner.since.elapsed() >= self.config.cooldown {
+            inner.state = CircuitState::HalfOpen;
+            inner.since = Instant::now();
+            return true;
+        }
+        self.rejections_total.fetch_add(1, Ordering::Relaxed);
+        false
+    }
+
+    /// Record the outcome of an allowed connect. Returns the new state if it
+    /// changed.
+    pub fn record(&self, failed: bool) -> Option<CircuitState> {
+        let mut inner = self.lock();
+        match inner.state {
+            CircuitState::HalfOpen if failed => {
+                self.open(&mut inner);
+                Some(CircuitState::Open)
+            }
+            CircuitState::HalfOpen => {
+                inner.state = CircuitState::Closed;
+                inner.consecutive_failures = 0;
+                inner.recent.clear();
+                Some(CircuitState::Closed)
+            }
+            // A connect that started before the breaker opened.
+            CircuitState::Open => None,
+            CircuitState::Closed => {
+                if inner.recent.len() >= self.config.window as usize {
+                    inner.recent.pop_front();
+                }
+                inner.recent.push_back(failed);
+                if !failed {
+                    inner.consecutive_failures = 0;
+                    return None;
+                }
+                inner.consecutive_failures += 1;
+                if self.should_trip(&inner) {
+                    self.open(&mut inner);
+                    return Some(CircuitState::Open);
+                }
+                None
+            }
+        }
+    }
+
+    fn should_trip(&self, inner: &Inner) -> bool {
+        let consecutive = self.config.consecutive_failures;
+        if consecutive > 0 && inner.consecutive_failures >= consecutive {
+            return true;
+        }
+        match self.config.error_rate {
+            Some(rate) if inner.recent.len() >= self.config.window as usize => {
+                let failures = inner.recent.iter().filter(|f| **f).count();
+                failures as f64 / inner.recent.len() as f64 >= rate
+            }
+            _ => false,
+        }
+    }
+
+    fn open(&self, inner: &mut Inner) {
+        inner.state = CircuitState::Open;
+        inner.since = Instant::now();
+        self.trips_total.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+#[path = "circuit_breaker_tests.rs"]
+mod tests;
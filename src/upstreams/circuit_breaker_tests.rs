@@ -0,0 +1,96 @@
+use super::*;
+use std::time::Duration;
+
+fn breaker(yaml: &str) -> CircuitBreaker {
+    CircuitBreaker::new(serde_yaml_ng::from_str(yaml).unwrap())
+}
+
+#[test]
+fn test_defaults() {
+    let config: CircuitBreakerConfig = serde_yaml_ng::from_str("{}").unwrap();
+    assert_eq!(config.consecutive_failures, 5);
+    assert_eq!(config.error_rate, None);
+    assert_eq!(config.window, 20);
+    assert_eq!(config.cooldown, Duration::from_secs(30));
+}
+
+#[test]
+fn test_opens_after_consecutive_failures() {
+    let b = breaker("{consecutive_failures: 3}");
+    assert_eq!(b.record(true), None);
+    assert_eq!(b.record(true), None);
+    assert_eq!(b.record(false), None);
+    assert_eq!(b.record(true), None);
+    assert_eq!(b.record(true), None);
+    assert_eq!(b.record(true), Some(CircuitState::Open));
+    assert_eq!(b.state(), CircuitState::Open);
+    assert_eq!(b.trips_total(), 1);
+
+    assert!(!b.would_allow());
+    assert!(!b.allow());
+    assert!(!b.allow());
+    assert_eq!(b.rejections_total(), 2);
+}
+
+#[test]
+fn test_opens_on_error_rate() {
+    let b = breaker("{consecutive_failures: 0, error_rate: 0.5, window: 4}");
+    // Not evaluated before the window is full.
+    assert_eq!(b.record(true), None);
+    assert_eq!(b.record(false), None);
+    assert_eq!(b.record(false), None);
+    assert_eq!(b.record(false), None);
+    // Window: ok ok ok fail → 25 %.
+    assert_eq!(b.record(true), None);
+    // Window: ok ok fail fail → 50 %.
+    assert_eq!(b.record(true), Some(CircuitState::Open));
+}
+
+#[tokio::test]
+async fn test_half_open_success_closes() {
+    let b = breaker("{consecutive_failures: 1, cooldown: 30ms}");
+    b.record(true);
+    assert!(!b.allow());
+
+    tokio::time::sleep(Duration::from_millis(40)).await;
+    assert!(b.would_allow());
+    assert!(b.allow());
+    assert_eq!(b.state(), CircuitState::HalfOpen);
+    // Only one trial at a time.
+    assert!(!b.allow());
+
+    assert_eq!(b.record(false), Some(CircuitState::Closed));
+    assert!(b.allow());
+    // The failure streak starts over.
+    assert_eq!(b.record(true), Some(CircuitState::Open));
+}
+
+#[tokio::test]
+async fn test_half_open_failure_reopens() {
+    let b = breaker("{consecutive_failures: 1, cooldown: 30ms}");
+    b.record(true);
+    tokio::time::sleep(Duration::from_millis(40)).await;
+    assert!(b.allow());
+    assert_eq!(b.record(true), Some(CircuitState::Open));
+    assert_eq!(b.trips_total(), 2);
+    assert!(!b.allow());
+}
+
+#[tokio::test]
+async fn test_lost_trial_is_retried_after_cooldown() {
+    let b = breaker("{consecutive_failures: 1, cooldown: 30ms}");
+    b.record(true);
+    tokio::time::sleep(Duration::from_millis(40)).await;
+    assert!(b.allow());
+    tokio::time::sleep(Duration::from_millis(40)).await;
+    assert!(b.allow());
+    assert_eq!(b.state(), CircuitState::HalfOpen);
+}
+
+#[test]
+fn test_outcome_while_open_is_ignored() {
+    let b = breaker("{consecutive_failures: 1}");
+    b.record(true);
+    assert_eq!(b.record(false), None);
+    assert_eq!(b.state(), CircuitState::Open);
+}
@@ -0,0 +1,138 @@
+use log::{debug, info, warn};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use tokio_util::sync::CancellationToken;
+
+use crate::config::HealthCheckConfig;
+use crate::upstreams::UpstreamTarget;
+
+// ---------------------------------------------------------------------------
+// HealthState — result of the background probes for one ProxyToUpstream
+//
+// An upstream starts out healthy. `fall` consecutive failed probes mark it
+// unhealthy, `rise` consecutive successful probes bring it back.
+// ---------------------------------------------------------------------------
+
+#[derive(Debug)]
+pub struct HealthState {
+    pub config: HealthCheckConfig,
+    healthy: AtomicBool,
+    consecutive_successes: AtomicU32,
+    consecutive_failures: AtomicU32,
+    checks_total: AtomicU64,
+    failures_total: AtomicU64,
+    last_error: Mutex<Option<String>>,
+}
+
+impl HealthState {
+    pub fn new(config: HealthCheckConfig) -> Self {
+        HealthState {
+            config,
+            healthy: AtomicBool::new(true),
+            consecutive_successes: AtomicU32::new(0),
+            consecutive_failures: AtomicU32::new(0),
+            checks_total: AtomicU64::new(0),
+            failures_total: AtomicU64::new(0),
+            last_error: Mutex::new(None),
+        }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    pub fn consecutive_successes(&self) -> u32 {
+        self.consecutive_successes.load(Ordering::Relaxed)
+    }
+
+    pub fn consecutive_failures(&self) -> u32 {
+        self.consecutive_failures.load(Ordering::Relaxed)
+    }
+
+    pub fn checks_total(&self) -> u64 {
+        self.checks_total.load(Ordering::Relaxed)
+    }
+
+    pub fn failures_total(&self) -> u64 {
+        self.failures_total.load(Ordering::Relaxed)
+    }
+
+    pub fn last_error(&self) -> Option<String> {
+        self.last_error
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Record one probe result. Returns the new state if it changed.
+    pub fn record(&self, result: Result<(), String>) -> Option<bool> {
+        self.checks_total.fetch_add(1, Ordering::Relaxed);
+        match result {
+            Ok(()) => {
+                self.consecutive_failures.store(0, Ordering::Relaxed);
+                let successes = self.consecutive_successes.fetch_add(1, Ordering::Relaxed) + 1;
+                if !self.is_healthy() && successes >= self.config.rise {
+                    self.healthy.store(true, Ordering::Relaxed);
+                    return Some(true);
+                }
+            }
+            Err(e) => {
+                self.failures_total.fetch_add(1, Ordering::Relaxed);
+                self.consecutive_successes.store(0, Ordering::Relaxed);
+                *self.last_error.lock().unwrap_or_else(|e| e.into_inner()) = Some(e);
+                let failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+                if self.is_healthy() && failures >= self.config.fall {
+                    self.healthy.store(false, Ordering::Relaxed);
+                    return Some(false);
+                }
+            }
+        }
+        None
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Probe loop
+// ---------------------------------------------------------------------------
+
+/// Probe `target` every `interval` until `token` is cancelled.
+pub(crate) async fn run(target: UpstreamTarget, token: CancellationToken) {
+    let Some(state) = target.proxy.health() else {
+        return;
+    };
+    let check = &state.config;
+    loop {
+        let result = match tokio::time::timeout(check.timeout, target.proxy.probe(check)).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => Err(format!("probe timed out after {:?}", check.timeout)),
+        };
+        debug!(
+            "Health check {} ({}): {:?}",
+            target.upstream, target.proxy.addr, result
+        );
+        match state.record(result) {
+            Some(true) => info!(
+                "Upstream {} ({}) is healthy again",
+                target.upstream, target.proxy.addr
+            ),
+            Some(false) => warn!(
+                "Upstream {} ({}) marked unhealthy after {} failed checks: {}",
+                target.upstream,
+                target.proxy.addr,
+                check.fall,
+                state.last_error().unwrap_or_default()
+            ),
+            None => {}
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(check.interval) => {}
+            _ = token.cancelled() => return,
+        }
+    }
+}
+
+#[cfg(test)]
+#[path = "health_check_tests.rs"]
+mod tests;
@@ -0,0 +1,110 @@
+use super::*;
+use crate::upstreams::ProxyToUpstream;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+fn check(rise: u32, fall: u32) -> HealthCheckConfig {
+    serde_yaml_ng::from_str(&format!(
+        "{{interval: 20ms, timeout: 200ms, rise: {}, fall: {}}}",
+        rise, fall
+    ))
+    .unwrap()
+}
+
+#[test]
+fn test_defaults() {
+    let config: HealthCheckConfig = serde_yaml_ng::from_str("{}").unwrap();
+    assert_eq!(config.interval, Duration::from_secs(10));
+    assert_eq!(config.timeout, Duration::from_secs(3));
+    assert_eq!((config.rise, config.fall), (2, 3));
+    assert!(config.connect_target.is_none());
+}
+
+#[test]
+fn test_fall_then_rise() {
+    let state = HealthState::new(check(2, 3));
+    assert!(state.is_healthy());
+
+    assert_eq!(state.record(Err("refused".into())), None);
+    assert_eq!(state.record(Err("refused".into())), None);
+    assert_eq!(state.record(Err("refused".into())), Some(false));
+    assert!(!state.is_healthy());
+    assert_eq!(state.last_error().as_deref(), Some("refused"));
+
+    assert_eq!(state.record(Ok(())), None);
+    assert_eq!(state.record(Ok(())), Some(true));
+    assert!(state.is_healthy());
+    assert_eq!(state.checks_total(), 5);
+    assert_eq!(state.failures_total(), 3);
+}
+
+#[test]
+fn test_success_resets_failure_streak() {
+    let state = HealthState::new(check(1, 2));
+    state.record(Err("x".into()));
+    state.record(Ok(()));
+    assert_eq!(state.record(Err("x".into())), None);
+    assert!(state.is_healthy());
+}
+
+#[tokio::test]
+async fn test_run_marks_refused_upstream_unhealthy() {
+    let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = closed.local_addr().unwrap();
+    drop(closed);
+
+    let proxy =
+        ProxyToUpstream::new(addr.to_string(), "tcp".to_string()).with_health_check(check(1, 2));
+    let token = CancellationToken::new();
+    let task = tokio::spawn(run(
+        UpstreamTarget {
+            upstream: "dead".to_string(),
+            proxy: proxy.clone(),
+        },
+        token.clone(),
+    ));
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!proxy.is_healthy());
+    token.cancel();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_run_connect_canary() {
+    // A CONNECT proxy that answers 200 to the canary target.
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let requests = tokio::spawn(async move {
+        let (mut s, _) = listener.accept().await.unwrap();
+        let mut buf = vec![0u8; 1024];
+        let n = s.read(&mut buf).await.unwrap();
+        s.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+            .await
+            .unwrap();
+        String::from_utf8_lossy(&buf[..n]).to_string()
+    });
+
+    let mut config = check(1, 1);
+    config.connect_target = Some("canary.example.com:443".to_string());
+    let proxy = ProxyToUpstream::new(addr.to_string(), "tcp".to_string()).with_health_check(config);
+    let token = CancellationToken::new();
+    let task = tokio::spawn(run(
+        UpstreamTarget {
+            upstream: "corp".to_string(),
+            proxy: proxy.clone(),
+        },
+        token.clone(),
+    ));
+
+    let request = requests.await.unwrap();
+    assert!(request.starts_with("CONNECT canary.example.com:443 HTTP/1.1\r\n"));
+    token.cancel();
+    task.await.unwrap();
+    let state = proxy.health().unwrap();
+    assert_eq!(state.failures_total(), 0);
+    assert!(state.checks_total() >= 1);
+    assert!(Arc::strong_count(state) >= 1);
+}
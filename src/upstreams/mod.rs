@@ -1,23 +1,39 @@
+mod admin;
+pub(crate) mod circuit_breaker;
+pub(crate) mod health_check;
+mod pool;
 mod proxy_to_upstream;
+mod srv;
 
+use crate::access_log::ConnectionId;
+use crate::access_log::TunnelInfo;
 use crate::config::ViaUpstream;
+use crate::metrics::{self, TunnelLabels};
 use http_body_util::Full;
 use hyper::body::Bytes;
 use hyper::server::conn::http1;
 use hyper::service::service_fn;
 use hyper::{Request, Response};
 use hyper_util::rt::TokioIo;
-use log::{debug, error};
+use log::{debug, error, warn};
+use std::collections::HashMap;
 use std::convert::Infallible;
 use std::error::Error;
 use std::fmt::Write as _;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use tokio::io;
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tokio::sync::Semaphore;
 
+use crate::servers::protocol::{ConnectOutcome, ConnectReply};
+pub use crate::upstreams::admin::AdminApi;
+pub use crate::upstreams::pool::{PoolMember, UpstreamPool};
 pub use crate::upstreams::proxy_to_upstream::ProxyToUpstream;
+use crate::upstreams::proxy_to_upstream::{ProxyError, Tunnel, UpstreamUnavailable};
+pub use crate::upstreams::srv::SrvUpstream;
 
 // ---------------------------------------------------------------------------
 // Metrics — shared live view of per-proxy connection counts
@@ -30,10 +46,96 @@ pub struct MetricsEntry {
     pub maxclients_limit: usize,
     /// Shared semaphore from the Proxy — available_permits() gives free slots.
     pub semaphore: Arc<Semaphore>,
+    /// Shared counter from the Proxy — connections that hit `client_hello_timeout`.
+    pub client_hello_timeouts: Arc<AtomicU64>,
+}
+
+/// A proxy with a health check or circuit breaker, and the upstream name it
+/// belongs to.
+#[derive(Debug, Clone)]
+pub struct UpstreamTarget {
+    pub upstream: String,
+    pub proxy: ProxyToUpstream,
+}
+
+/// All proxies with a `health_check` or `circuit_breaker`, including pool
+/// members, sorted by upstream name.
+pub fn monitored_upstreams(upstreams: &HashMap<String, Upstream>) -> Vec<UpstreamTarget> {
+    let mut targets: Vec<UpstreamTarget> = upstreams
+        .iter()
+        .flat_map(|(name, upstream)| {
+            upstream
+                .proxies()
+                .into_iter()
+                .filter(|p| p.health().is_some() || p.circuit_breaker().is_some())
+                .map(|p| UpstreamTarget {
+                    upstream: name.clone(),
+                    proxy: p.clone(),
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    targets.sort_by(|a, b| (&a.upstream, &a.proxy.addr).cmp(&(&b.upstream, &b.proxy.addr)));
+    targets
+}
+
+/// Live view of all servers and monitored upstreams.
+#[derive(Debug, Default)]
+pub struct MetricsData {
+    pub servers: Vec<MetricsEntry>,
+    pub upstreams: Vec<UpstreamTarget>,
+    /// Served below `/admin/`.
+    pub admin: AdminApi,
 }
 
 /// Cheaply cloneable snapshot of all proxy metrics.
-pub type Metrics = Arc<Vec<MetricsEntry>>;
+pub type Metrics = Arc<MetricsData>;
+
+// ---------------------------------------------------------------------------
+// ConnectionContext — what is known about the client before routing
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone)]
+pub struct ConnectionContext {
+    pub client_addr: SocketAddr,
+    /// Address the client connected to (our listen socket).
+    pub local_addr: SocketAddr,
+    /// First SNI from the ClientHello, if any.
+    pub sni: Option<String>,
+    /// Client's preferred ALPN protocol from the ClientHello, if any.
+    pub alpn: Option<String>,
+    /// Set on `http-connect` and `socks5` servers: the client is waiting for
+    /// a reply to its request before it starts the tunnel.
+    pub reply_to_connect: Option<ConnectReply>,
+    /// Assigned at accept time; see `connection_id`.
+    pub id: ConnectionId,
+    /// Server, route and upstream the traffic metrics are recorded under.
+    pub labels: TunnelLabels,
+    /// Filled in by the upstream side for the access log.
+    pub tunnel: Arc<TunnelInfo>,
+}
+
+impl ConnectionContext {
+    pub fn new(client_addr: SocketAddr, local_addr: SocketAddr) -> Self {
+        ConnectionContext {
+            client_addr,
+            local_addr,
+            sni: None,
+            alpn: None,
+            reply_to_connect: None,
+            id: ConnectionId::new(),
+            labels: TunnelLabels::default(),
+            tunnel: Arc::default(),
+        }
+    }
+
+    /// This context for an attempt through the upstream named `upstream`.
+    fn for_upstream(&self, upstream: &str) -> Self {
+        let mut ctx = self.clone();
+        ctx.labels.upstream = upstream.to_string();
+        ctx
+    }
+}
 
 // ---------------------------------------------------------------------------
 // Upstream variants
@@ -47,33 +149,60 @@ pub enum Upstream {
     /// Populated by `From<ParsedConfig> for Server` after all proxies are built.
     Health(Metrics),
     Proxy(ProxyToUpstream),
+    /// Several proxies/backends sharing the load.
+    Pool(UpstreamPool),
+    /// A pool discovered from DNS SRV records (`srv://`).
+    Srv(SrvUpstream),
+    /// Connect straight to the route's target (`via.target` or the SNI),
+    /// typically the last resort in a fallback list.
+    Direct,
 }
 
 impl Upstream {
+    /// The proxies behind this upstream: itself, or every pool member.
+    /// SRV targets change at runtime and are managed by `SrvUpstream`.
+    pub fn proxies(&self) -> Vec<&ProxyToUpstream> {
+        match self {
+            Upstream::Proxy(proxy) => vec![proxy],
+            Upstream::Pool(pool) => pool.members.iter().map(|m| &m.upstream).collect(),
+            _ => Vec::new(),
+        }
+    }
+
     pub(crate) async fn process(
         &self,
         mut inbound: TcpStream,
         via: &ViaUpstream,
         connect_target: Option<String>,
+        ctx: &ConnectionContext,
     ) -> Result<(), Box<dyn Error>> {
         match self {
             Upstream::Ban => {
+                if let Some(reply) = ctx.reply_to_connect {
+                    reply.send(&mut inbound, ConnectOutcome::Forbidden).await?;
+                }
                 inbound.shutdown().await?;
             }
             Upstream::Echo => {
+                if let Some(reply) = ctx.reply_to_connect {
+                    reply
+                        .send(&mut inbound, ConnectOutcome::Established)
+                        .await?;
+                }
                 let (mut ri, mut wi) = io::split(inbound);
                 let inbound_to_inbound = copy(&mut ri, &mut wi);
                 let bytes_tx = inbound_to_inbound.await;
                 debug!("Bytes read: {:?}", bytes_tx);
             }
             Upstream::Health(metrics) => {
+                let local = inbound.local_addr()?;
                 let io = TokioIo::new(inbound);
                 let metrics = metrics.clone();
                 tokio::task::spawn(async move {
                     if let Err(err) = http1::Builder::new()
                         .serve_connection(
                             io,
-                            service_fn(move |req| health_handler(req, metrics.clone())),
+                            service_fn(move |req| health_handler(req, metrics.clone(), local)),
                         )
                         .await
                     {
@@ -81,12 +210,112 @@ impl Upstream {
                     }
                 });
             }
-            Upstream::Proxy(config) => {
-                config.proxy(inbound, via, connect_target).await?;
+            Upstream::Proxy(_) | Upstream::Pool(_) | Upstream::Srv(_) | Upstream::Direct => {
+                // The error is flattened to text so it is not held across the
+                // await that answers an http-connect/socks5 client.
+                let connected = match self.connect(via, connect_target.as_deref(), ctx).await {
+                    Ok(tunnel) => Ok(tunnel),
+                    Err(e) if ctx.reply_to_connect.is_none() => return Err(e),
+                    Err(e) => Err(e.to_string()),
+                };
+                return match connected {
+                    Ok(tunnel) => tunnel.run(inbound, via, ctx).await,
+                    Err(failed) => refuse(inbound, ctx, failed).await,
+                };
             }
         };
         Ok(())
     }
+
+    /// Open the upstream side of a tunnel without touching the client
+    /// connection. Only proxies, pools (including SRV) and `direct` have one.
+    async fn connect(
+        &self,
+        via: &ViaUpstream,
+        connect_target: Option<&str>,
+        ctx: &ConnectionContext,
+    ) -> Result<Tunnel, Box<dyn Error>> {
+        match self {
+            Upstream::Proxy(proxy) => proxy.connect(via, connect_target, ctx).await,
+            Upstream::Pool(pool) => pool.connect(via, connect_target, ctx).await,
+            Upstream::Srv(srv) => srv.connect(via, connect_target, ctx).await,
+            Upstream::Direct => {
+                let Some(target) = connect_target else {
+                    return Err(Box::new(ProxyError(
+                        "direct upstream needs a target: set via.target or via.use_sni_as_target"
+                            .to_string(),
+                    )));
+                };
+                ProxyToUpstream::new(target.to_string(), "tcp".to_string())
+                    .connect(via, None, ctx)
+                    .await
+            }
+            Upstream::Ban | Upstream::Echo | Upstream::Health(_) => {
+                Err("built-in upstream has no upstream connection".into())
+            }
+        }
+    }
+}
+
+/// Hand the connection to the first upstream in `chain` that can be reached.
+///
+/// Nothing is read from the client or sent to it before an upstream tunnel is
+/// established (the ClientHello is only peeked), so when a proxy, pool or
+/// `direct` entry is unavailable — connect error or timeout, CONNECT answered
+/// with 502/503 — the next entry gets the untouched connection. Other errors
+/// and the last entry are handled like a single upstream.
+pub(crate) async fn process_with_fallback(
+    chain: &[(&str, &Upstream)],
+    inbound: TcpStream,
+    via: &ViaUpstream,
+    connect_target: Option<String>,
+    ctx: &ConnectionContext,
+) -> Result<(), Box<dyn Error>> {
+    let Some(((last_name, last), fallbacks)) = chain.split_last() else {
+        return Err("no upstream to process the connection".into());
+    };
+    for (i, (name, upstream)) in fallbacks.iter().enumerate() {
+        let ctx = &ctx.for_upstream(name);
+        if matches!(
+            upstream,
+            Upstream::Ban | Upstream::Echo | Upstream::Health(_)
+        ) {
+            return upstream.process(inbound, via, connect_target, ctx).await;
+        }
+        let connected = match upstream.connect(via, connect_target.as_deref(), ctx).await {
+            Ok(tunnel) => Ok(tunnel),
+            Err(e) if e.is::<UpstreamUnavailable>() => {
+                warn!(
+                    "Upstream {} failed for {}, falling back to {}: {}",
+                    name,
+                    ctx.client_addr,
+                    chain[i + 1].0,
+                    e
+                );
+                continue;
+            }
+            Err(e) if ctx.reply_to_connect.is_none() => return Err(e),
+            Err(e) => Err(e.to_string()),
+        };
+        return match connected {
+            Ok(tunnel) => tunnel.run(inbound, via, ctx).await,
+            Err(failed) => refuse(inbound, ctx, failed).await,
+        };
+    }
+    last.process(inbound, via, connect_target, &ctx.for_upstream(last_name))
+        .await
+}
+
+/// Tell a waiting `http-connect`/`socks5` client that its tunnel failed.
+async fn refuse(
+    mut inbound: TcpStream,
+    ctx: &ConnectionContext,
+    error: String,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(reply) = ctx.reply_to_connect {
+        let _ = reply.send(&mut inbound, ConnectOutcome::Failed).await;
+    }
+    Err(Box::new(ProxyError(error)))
 }
 
 async fn copy<'a, R, W>(reader: &'a mut R, writer: &'a mut W) -> io::Result<u64>
@@ -110,8 +339,10 @@ where
 async fn health_handler(
     req: Request<hyper::body::Incoming>,
     metrics: Metrics,
+    local: SocketAddr,
 ) -> Result<Response<Full<Bytes>>, Infallible> {
     match req.uri().path() {
+        path if path.starts_with("/admin/") => Ok(admin::handle(&req, &metrics, local)),
         "/metrics" => {
             let mut body = String::new();
             writeln!(
@@ -120,7 +351,7 @@ async fn health_handler(
             )
             .unwrap();
             writeln!(body, "# TYPE tpt_active_connections gauge").unwrap();
-            for e in metrics.iter() {
+            for e in metrics.servers.iter() {
                 let active = e
                     .maxclients_limit
                     .saturating_sub(e.semaphore.available_permits());
@@ -137,7 +368,7 @@ async fn health_handler(
             )
             .unwrap();
             writeln!(body, "# TYPE tpt_maxclients gauge").unwrap();
-            for e in metrics.iter() {
+            for e in metrics.servers.iter() {
                 writeln!(
                     body,
                     r#"tpt_maxclients{{name="{}",listen="{}"}} {}"#,
@@ -145,15 +376,153 @@ async fn health_handler(
                 )
                 .unwrap();
             }
+            writeln!(
+                body,
+                "# HELP tpt_client_hello_timeouts_total Connections closed or defaulted because no ClientHello arrived in time"
+            )
+            .unwrap();
+            writeln!(body, "# TYPE tpt_client_hello_timeouts_total counter").unwrap();
+            for e in metrics.servers.iter() {
+                writeln!(
+                    body,
+                    r#"tpt_client_hello_timeouts_total{{name="{}",listen="{}"}} {}"#,
+                    e.name,
+                    e.listen,
+                    e.client_hello_timeouts.load(Ordering::Relaxed)
+                )
+                .unwrap();
+            }
+            write_upstream_health_metrics(&mut body, &metrics.upstreams);
+            write_circuit_breaker_metrics(&mut body, &metrics.upstreams);
+            metrics::registry().write(&mut body);
             Ok(Response::builder()
                 .header("Content-Type", "text/plain; version=0.0.4; charset=utf-8")
                 .body(Full::new(Bytes::from(body)))
                 .unwrap())
         }
+        "/status" => {
+            let upstreams: Vec<serde_json::Value> = metrics
+                .upstreams
+                .iter()
+                .map(|t| {
+                    let mut entry = serde_json::json!({
+                        "upstream": t.upstream,
+                        "address": t.proxy.addr,
+                    });
+                    if let Some(h) = t.proxy.health() {
+                        entry["healthy"] = h.is_healthy().into();
+                        entry["consecutive_successes"] = h.consecutive_successes().into();
+                        entry["consecutive_failures"] = h.consecutive_failures().into();
+                        entry["checks_total"] = h.checks_total().into();
+                        entry["failures_total"] = h.failures_total().into();
+                        entry["last_error"] = h.last_error().into();
+                    }
+                    if let Some(b) = t.proxy.circuit_breaker() {
+                        entry["circuit_breaker"] = serde_json::json!({
+                            "state": format!("{:?}", b.state()).to_lowercase(),
+                            "trips_total": b.trips_total(),
+                            "rejections_total": b.rejections_total(),
+                        });
+                    }
+                    entry
+                })
+                .collect();
+            let body = serde_json::json!({ "upstreams": upstreams }).to_string();
+            Ok(Response::builder()
+                .header("Content-Type", "application/json")
+                .body(Full::new(Bytes::from(body)))
+                .unwrap())
+        }
         _ => Ok(Response::new(Full::new(Bytes::from("OK")))),
     }
 }
 
+fn write_upstream_health_metrics(body: &mut String, targets: &[UpstreamTarget]) {
+    if targets.iter().all(|t| t.proxy.health().is_none()) {
+        return;
+    }
+    let series = [
+        (
+            "tpt_upstream_healthy",
+            "Whether the upstream passes its health check (1) or not (0)",
+            "gauge",
+        ),
+        (
+            "tpt_upstream_health_checks_total",
+            "Health check probes run against the upstream",
+            "counter",
+        ),
+        (
+            "tpt_upstream_health_check_failures_total",
+            "Health check probes that failed",
+            "counter",
+        ),
+    ];
+    for (metric, help, kind) in series {
+        writeln!(body, "# HELP {} {}", metric, help).unwrap();
+        writeln!(body, "# TYPE {} {}", metric, kind).unwrap();
+        for t in targets {
+            let Some(h) = t.proxy.health() else {
+                continue;
+            };
+            let value = match metric {
+                "tpt_upstream_healthy" => h.is_healthy() as u64,
+                "tpt_upstream_health_checks_total" => h.checks_total(),
+                _ => h.failures_total(),
+            };
+            writeln!(
+                body,
+                r#"{}{{upstream="{}",address="{}"}} {}"#,
+                metric, t.upstream, t.proxy.addr, value
+            )
+            .unwrap();
+        }
+    }
+}
+
+fn write_circuit_breaker_metrics(body: &mut String, targets: &[UpstreamTarget]) {
+    if targets.iter().all(|t| t.proxy.circuit_breaker().is_none()) {
+        return;
+    }
+    let series = [
+        (
+            "tpt_upstream_circuit_state",
+            "Circuit breaker state: 0 closed, 1 open, 2 half-open",
+            "gauge",
+        ),
+        (
+            "tpt_upstream_circuit_trips_total",
+            "Times the circuit breaker opened",
+            "counter",
+        ),
+        (
+            "tpt_upstream_circuit_rejections_total",
+            "Connects rejected by an open circuit breaker",
+            "counter",
+        ),
+    ];
+    for (metric, help, kind) in series {
+        writeln!(body, "# HELP {} {}", metric, help).unwrap();
+        writeln!(body, "# TYPE {} {}", metric, kind).unwrap();
+        for t in targets {
+            let Some(b) = t.proxy.circuit_breaker() else {
+                continue;
+            };
+            let value = match metric {
+                "tpt_upstream_circuit_state" => b.state().as_metric() as u64,
+                "tpt_upstream_circuit_trips_total" => b.trips_total(),
+                _ => b.rejections_total(),
+            };
+            writeln!(
+                body,
+                r#"{}{{upstream="{}",address="{}"}} {}"#,
+                metric, t.upstream, t.proxy.addr, value
+            )
+            .unwrap();
+        }
+    }
+}
+
 #[cfg(test)]
 #[path = "tests.rs"]
 mod tests;
@@ -0,0 +1,170 @@
+use log::debug;
+use std::error::Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::config::{PoolStrategy, ViaUpstream};
+use crate::upstreams::proxy_to_upstream::UpstreamUnavailable;
+use crate::upstreams::{ConnectionContext, ProxyToUpstream, Tunnel};
+
+// ---------------------------------------------------------------------------
+// UpstreamPool — several ProxyToUpstream behind one upstream name
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone)]
+pub struct PoolMember {
+    pub upstream: ProxyToUpstream,
+    pub weight: u32,
+    /// Tunnels currently open through this member.
+    active: Arc<AtomicUsize>,
+}
+
+impl PoolMember {
+    pub fn new(upstream: ProxyToUpstream, weight: u32) -> Self {
+        PoolMember {
+            upstream,
+            weight,
+            active: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// The same member (sharing its connection count) with another weight.
+    pub fn with_weight(&self, weight: u32) -> Self {
+        PoolMember {
+            weight,
+            ..self.clone()
+        }
+    }
+
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UpstreamPool {
+    pub strategy: PoolStrategy,
+    pub members: Vec<PoolMember>,
+    /// Round-robin position, shared by all clones of the pool.
+    next: Arc<AtomicUsize>,
+    /// Current weights for smooth weighted round-robin.
+    current_weights: Arc<Mutex<Vec<i64>>>,
+}
+
+/// Decrements a member's active count when the tunnel ends.
+#[derive(Debug)]
+pub(super) struct ActiveGuard(Arc<AtomicUsize>);
+
+impl Drop for ActiveGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl UpstreamPool {
+    pub fn new(strategy: PoolStrategy, members: Vec<PoolMember>) -> Self {
+        let current_weights = vec![0; members.len()];
+        UpstreamPool {
+            strategy,
+            members,
+            next: Arc::new(AtomicUsize::new(0)),
+            current_weights: Arc::new(Mutex::new(current_weights)),
+        }
+    }
+
+    /// Index of the member the next connection should use. Members marked
+    /// unhealthy by their health check or held back by an open circuit
+    /// breaker are skipped.
+    pub fn select(&self) -> Option<usize> {
+        let candidates: Vec<usize> = (0..self.members.len())
+            .filter(|&i| self.members[i].upstream.is_available())
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+        let index = match self.strategy {
+            PoolStrategy::RoundRobin => {
+                let n = self.next.fetch_add(1, Ordering::Relaxed);
+                candidates[n % candidates.len()]
+            }
+            PoolStrategy::LeastConnections => {
+                // Start at a rotating offset so ties are spread evenly.
+                let offset = self.next.fetch_add(1, Ordering::Relaxed) % candidates.len();
+                candidates
+                    .iter()
+                    .cycle()
+                    .skip(offset)
+                    .take(candidates.len())
+                    .copied()
+                    .min_by_key(|&i| self.members[i].active())?
+            }
+            PoolStrategy::Random => {
+                let total: u64 = candidates
+                    .iter()
+                    .map(|&i| self.members[i].weight as u64)
+                    .sum();
+                let mut pick = fastrand::u64(0..total.max(1));
+                let mut chosen = candidates[candidates.len() - 1];
+                for &i in &candidates {
+                    let weight = self.members[i].weight as u64;
+                    if pick < weight {
+                        chosen = i;
+                        break;
+                    }
+                    pick -= weight;
+                }
+                chosen
+            }
+            PoolStrategy::Weighted => {
+                // nginx-style smooth weighted round-robin: every candidate
+                // gains its weight, the largest wins and pays back the total.
+                let mut current = self
+                    .current_weights
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner());
+                let mut total = 0i64;
+                let mut best = candidates[0];
+                for &i in &candidates {
+                    let weight = self.members[i].weight as i64;
+                    current[i] += weight;
+                    total += weight;
+                    if current[i] > current[best] {
+                        best = i;
+                    }
+                }
+                current[best] -= total;
+                best
+            }
+        };
+        Some(index)
+    }
+
+    /// Connect through the selected member; see `ProxyToUpstream::connect`.
+    pub(crate) async fn connect(
+        &self,
+        via: &ViaUpstream,
+        connect_target: Option<&str>,
+        ctx: &ConnectionContext,
+    ) -> Result<Tunnel, Box<dyn Error>> {
+        let index = self.select().ok_or_else(|| {
+            UpstreamUnavailable("upstream pool has no available members".to_string())
+        })?;
+        let member = &self.members[index];
+        debug!(
+            "Pool ({:?}) selected member {} ({} active)",
+            self.strategy,
+            member.upstream.addr,
+            member.active()
+        );
+
+        member.active.fetch_add(1, Ordering::Relaxed);
+        let guard = ActiveGuard(member.active.clone());
+        let mut tunnel = member.upstream.connect(via, connect_target, ctx).await?;
+        tunnel.active = Some(guard);
+        Ok(tunnel)
+    }
+}
+
+#[cfg(test)]
+#[path = "pool_tests.rs"]
+mod tests;
@@ -0,0 +1,160 @@
+use super::*;
+
+fn pool(strategy: PoolStrategy, weights: &[u32]) -> UpstreamPool {
+    let members = weights
+        .iter()
+        .enumerate()
+        .map(|(i, &w)| {
+            PoolMember::new(
+                ProxyToUpstream::new(format!("10.0.0.{}:3128", i + 1), "tcp".to_string()),
+                w,
+            )
+        })
+        .collect();
+    UpstreamPool::new(strategy, members)
+}
+
+fn picks(pool: &UpstreamPool, n: usize) -> Vec<usize> {
+    (0..n).map(|_| pool.select().unwrap()).collect()
+}
+
+#[test]
+fn test_round_robin_cycles() {
+    let p = pool(PoolStrategy::RoundRobin, &[1, 1, 1]);
+    assert_eq!(picks(&p, 6), vec![0, 1, 2, 0, 1, 2]);
+}
+
+#[test]
+fn test_round_robin_shared_between_clones() {
+    let p = pool(PoolStrategy::RoundRobin, &[1, 1]);
+    let clone = p.clone();
+    assert_eq!(p.select(), Some(0));
+    assert_eq!(clone.select(), Some(1));
+}
+
+#[test]
+fn test_weighted_is_smooth() {
+    let p = pool(PoolStrategy::Weighted, &[5, 1, 1]);
+    // nginx's reference sequence for weights 5/1/1.
+    assert_eq!(picks(&p, 7), vec![0, 0, 1, 0, 2, 0, 0]);
+}
+
+#[test]
+fn test_least_connections_prefers_idle_member() {
+    let p = pool(PoolStrategy::LeastConnections, &[1, 1, 1]);
+    p.members[0].active.store(3, Ordering::Relaxed);
+    p.members[2].active.store(1, Ordering::Relaxed);
+    assert_eq!(picks(&p, 3), vec![1, 1, 1]);
+}
+
+#[test]
+fn test_least_connections_spreads_ties() {
+    let p = pool(PoolStrategy::LeastConnections, &[1, 1, 1]);
+    assert_eq!(picks(&p, 3), vec![0, 1, 2]);
+}
+
+#[test]
+fn test_random_respects_weights() {
+    let p = pool(PoolStrategy::Random, &[1, 0, 3]);
+    let mut counts = [0usize; 3];
+    for i in picks(&p, 4000) {
+        counts[i] += 1;
+    }
+    assert_eq!(counts[1], 0);
+    assert!(counts[2] > counts[0] * 2, "{:?}", counts);
+}
+
+#[test]
+fn test_unhealthy_members_are_skipped() {
+    let check: crate::config::HealthCheckConfig = serde_yaml_ng::from_str("{fall: 1}").unwrap();
+    let members = (1..=3)
+        .map(|i| {
+            PoolMember::new(
+                ProxyToUpstream::new(format!("10.0.0.{}:3128", i), "tcp".to_string())
+                    .with_health_check(check.clone()),
+                1,
+            )
+        })
+        .collect();
+    let p = UpstreamPool::new(PoolStrategy::RoundRobin, members);
+    p.members[1]
+        .upstream
+        .health()
+        .unwrap()
+        .record(Err("down".into()));
+    assert_eq!(picks(&p, 4), vec![0, 2, 0, 2]);
+
+    for m in &p.members {
+        m.upstream.health().unwrap().record(Err("down".into()));
+    }
+    assert_eq!(p.select(), None);
+}
+
+#[test]
+fn test_members_with_open_breaker_are_skipped() {
+    let breaker: crate::config::CircuitBreakerConfig =
+        serde_yaml_ng::from_str("{consecutive_failures: 1}").unwrap();
+    let members = (1..=2)
+        .map(|i| {
+            PoolMember::new(
+                ProxyToUpstream::new(format!("10.0.0.{}:3128", i), "tcp".to_string())
+                    .with_circuit_breaker(breaker.clone()),
+                1,
+            )
+        })
+        .collect();
+    let p = UpstreamPool::new(PoolStrategy::RoundRobin, members);
+    p.members[0]
+        .upstream
+        .circuit_breaker()
+        .unwrap()
+        .record(true);
+    assert_eq!(picks(&p, 3), vec![1, 1, 1]);
+}
+
+#[test]
+fn test_empty_pool_selects_nothing() {
+    assert_eq!(pool(PoolStrategy::RoundRobin, &[]).select(), None);
+}
+
+#[tokio::test]
+async fn test_proxy_tracks_active_connections() {
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::{TcpListener, TcpStream};
+
+    let backend = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let backend_addr = backend.local_addr().unwrap();
+    let p = UpstreamPool::new(
+        PoolStrategy::RoundRobin,
+        vec![PoolMember::new(
+            ProxyToUpstream::new(backend_addr.to_string(), "tcp".to_string()),
+            1,
+        )],
+    );
+
+    let front = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut client = TcpStream::connect(front.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (inbound, _) = front.accept().await.unwrap();
+    let ctx = ConnectionContext::new(client.local_addr().unwrap(), inbound.local_addr().unwrap());
+
+    let tunnel = {
+        let p = p.clone();
+        tokio::spawn(async move {
+            let via = ViaUpstream::default();
+            crate::upstreams::Upstream::Pool(p)
+                .process(inbound, &via, None, &ctx)
+                .await
+                .map_err(|e| e.to_string())
+        })
+    };
+    let (mut server_side, _) = backend.accept().await.unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    assert_eq!(p.members[0].active(), 1);
+
+    client.shutdown().await.unwrap();
+    server_side.shutdown().await.unwrap();
+    tunnel.await.unwrap().unwrap();
+    assert_eq!(p.members[0].active(), 0);
+}
@@ -1,11 +1,19 @@
 use crate::servers::upstream_address::UpstreamAddress;
+use futures::stream::{FuturesUnordered, StreamExt};
 use log::{debug, error};
 use std::error::Error;
+use std::io;
+use std::net::SocketAddr;
 use std::time::Duration;
 use tokio::net::TcpStream;
+use tracing::field::{Empty, display};
+use tracing::{Instrument, info_span};
 
 use super::ProxyError;
 
+/// Connection Attempt Delay recommended by RFC 8305, used by health checks.
+pub(super) const DEFAULT_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
 // ---------------------------------------------------------------------------
 // Connect to the upstream TCP server with a timeout.
 // ---------------------------------------------------------------------------
@@ -14,36 +22,142 @@ pub(super) async fn connect_upstream(
     addresses: &UpstreamAddress,
     protocol: &str,
     timeout: Duration,
+    attempt_delay: Duration,
 ) -> Result<TcpStream, Box<dyn Error>> {
     match protocol {
-        "tcp4" | "tcp6" | "tcp" => {}
+        "tcp4" | "tcp6" | "tcp" | "socks5" => {}
         _ => {
             error!("Reached unknown protocol: {:?}", protocol);
             return Err("Reached unknown protocol".into());
         }
     }
 
-    match tokio::time::timeout(
-        timeout,
-        TcpStream::connect(addresses.resolve(protocol.into()).await?.as_slice()),
-    )
-    .await
+    let addrs = {
+        let span = info_span!("resolve", upstream = addr, addresses = Empty, error = Empty);
+        match addresses
+            .resolve(protocol.into())
+            .instrument(span.clone())
+            .await
+        {
+            Ok(addrs) => {
+                span.record("addresses", addrs.len());
+                addrs
+            }
+            Err(e) => {
+                span.record("error", display(&e));
+                return Err(e.into());
+            }
+        }
+    };
+
+    let span = info_span!("connect", upstream = addr, peer = Empty, error = Empty);
+    match tokio::time::timeout(timeout, happy_eyeballs(&addrs, attempt_delay))
+        .instrument(span.clone())
+        .await
     {
         Ok(Ok(stream)) => {
             debug!("Connected to {:?}", stream.peer_addr());
+            if let Ok(peer) = stream.peer_addr() {
+                span.record("peer", display(peer));
+            }
             Ok(stream)
         }
         Ok(Err(e)) => {
             error!("Failed to connect to upstream {}: {:?}", addr, e);
+            span.record("error", display(&e));
             Err(ProxyError(format!("failed to connect to upstream: {}", e)).into())
         }
         Err(e) => {
             error!("Connection to upstream {} timed out: {:?}", addr, e);
+            span.record("error", "timed out");
             Err(ProxyError(format!("connection to upstream timed out: {:?}", e)).into())
         }
     }
 }
 
+// ---------------------------------------------------------------------------
+// Happy Eyeballs (RFC 8305): staggered parallel connection attempts.
+// ---------------------------------------------------------------------------
+
+/// Connect to the first address that answers. A new attempt is started every
+/// `attempt_delay`, or as soon as the previous one fails, while earlier
+/// attempts keep running; the first established connection wins and the
+/// others are dropped. Address families are interleaved so a blackholed IPv6
+/// (or IPv4) network only costs one delay. `Duration::ZERO` = try the
+/// addresses one after another.
+pub(super) async fn happy_eyeballs(
+    addrs: &[SocketAddr],
+    attempt_delay: Duration,
+) -> io::Result<TcpStream> {
+    if attempt_delay.is_zero() || addrs.len() < 2 {
+        return TcpStream::connect(addrs).await;
+    }
+
+    let mut remaining = interleave_families(addrs).into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut last_error = None;
+    loop {
+        if attempts.is_empty() {
+            match remaining.next() {
+                Some(addr) => attempts.push(attempt(addr)),
+                None => break,
+            }
+        }
+        tokio::select! {
+            Some((addr, result)) = attempts.next() => match result {
+                Ok(stream) => {
+                    debug!("Happy Eyeballs: connected to {}", addr);
+                    return Ok(stream);
+                }
+                Err(e) => {
+                    debug!("Happy Eyeballs: attempt to {} failed: {}", addr, e);
+                    last_error = Some(e);
+                    if let Some(addr) = remaining.next() {
+                        attempts.push(attempt(addr));
+                    }
+                }
+            },
+            _ = tokio::time::sleep(attempt_delay), if remaining.len() > 0 => {
+                if let Some(addr) = remaining.next() {
+                    attempts.push(attempt(addr));
+                }
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "could not resolve to any addresses",
+        )
+    }))
+}
+
+async fn attempt(addr: SocketAddr) -> (SocketAddr, io::Result<TcpStream>) {
+    debug!("Happy Eyeballs: connecting to {}", addr);
+    (addr, TcpStream::connect(addr).await)
+}
+
+/// Alternate address families, starting with the family of the first
+/// address (the resolver's preference), keeping the order within a family.
+fn interleave_families(addrs: &[SocketAddr]) -> Vec<SocketAddr> {
+    let Some(first) = addrs.first() else {
+        return Vec::new();
+    };
+    let (preferred, other): (Vec<SocketAddr>, Vec<SocketAddr>) = addrs
+        .iter()
+        .copied()
+        .partition(|a| a.is_ipv6() == first.is_ipv6());
+    let (mut preferred, mut other) = (preferred.into_iter(), other.into_iter());
+    let mut interleaved = Vec::with_capacity(addrs.len());
+    loop {
+        match (preferred.next(), other.next()) {
+            (None, None) => break,
+            (a, b) => interleaved.extend(a.into_iter().chain(b)),
+        }
+    }
+    interleaved
+}
+
 #[cfg(test)]
 #[path = "connect_tests.rs"]
 mod tests;
@@ -1,11 +1,99 @@
 use super::*;
 use crate::servers::upstream_address::UpstreamAddress;
+use std::net::SocketAddr;
 use std::time::Duration;
 
 #[tokio::test]
 async fn test_unknown_protocol_returns_err() {
     let addr = UpstreamAddress::new("127.0.0.1:12345".to_string());
-    let result = connect_upstream("127.0.0.1:12345", &addr, "udp", Duration::from_secs(1)).await;
+    let result = connect_upstream(
+        "127.0.0.1:12345",
+        &addr,
+        "udp",
+        Duration::from_secs(1),
+        DEFAULT_ATTEMPT_DELAY,
+    )
+    .await;
     assert!(result.is_err());
     assert!(result.unwrap_err().to_string().contains("unknown protocol"));
 }
+
+#[test]
+fn test_interleave_families() {
+    let addrs: Vec<SocketAddr> = [
+        "[2001:db8::1]:443",
+        "[2001:db8::2]:443",
+        "192.0.2.1:443",
+        "192.0.2.2:443",
+        "192.0.2.3:443",
+    ]
+    .iter()
+    .map(|a| a.parse().unwrap())
+    .collect();
+    let order: Vec<String> = interleave_families(&addrs)
+        .iter()
+        .map(|a| a.to_string())
+        .collect();
+    assert_eq!(
+        order,
+        [
+            "[2001:db8::1]:443",
+            "192.0.2.1:443",
+            "[2001:db8::2]:443",
+            "192.0.2.2:443",
+            "192.0.2.3:443",
+        ]
+    );
+
+    let v4_first = [addrs[2], addrs[0]];
+    assert_eq!(interleave_families(&v4_first), v4_first);
+}
+
+async fn closed_port() -> SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    listener.local_addr().unwrap()
+}
+
+#[tokio::test]
+async fn test_happy_eyeballs_skips_unresponsive_address() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let live = listener.local_addr().unwrap();
+    // TEST-NET-1 is never routed: the attempt either hangs or fails at once.
+    let blackhole: SocketAddr = "192.0.2.1:9".parse().unwrap();
+
+    let stream = tokio::time::timeout(
+        Duration::from_secs(2),
+        happy_eyeballs(&[blackhole, live], Duration::from_millis(50)),
+    )
+    .await
+    .expect("blackholed address was not raced")
+    .unwrap();
+    assert_eq!(stream.peer_addr().unwrap(), live);
+}
+
+#[tokio::test]
+async fn test_happy_eyeballs_moves_on_after_refusal() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let live = listener.local_addr().unwrap();
+    let refused = closed_port().await;
+
+    let stream = happy_eyeballs(&[refused, live], Duration::from_secs(10))
+        .await
+        .unwrap();
+    assert_eq!(stream.peer_addr().unwrap(), live);
+}
+
+#[tokio::test]
+async fn test_happy_eyeballs_all_fail() {
+    let addrs = [closed_port().await, closed_port().await];
+    let result = happy_eyeballs(&addrs, Duration::from_millis(50)).await;
+    assert_eq!(
+        result.unwrap_err().kind(),
+        std::io::ErrorKind::ConnectionRefused
+    );
+    assert!(
+        happy_eyeballs(&[], Duration::from_millis(50))
+            .await
+            .is_err()
+    );
+}
@@ -3,16 +3,23 @@ use std::collections::HashMap;
 use std::error::Error;
 use tokio::io::{self};
 use tokio::net::TcpStream;
+use tracing::Span;
 
-use super::ProxyError;
+use super::{ProxyError, UpstreamUnavailable};
+use crate::metrics;
+use crate::telemetry;
+use crate::upstreams::ConnectionContext;
 
 // ---------------------------------------------------------------------------
 // Send an HTTP CONNECT request and verify the upstream returns 2xx.
+// For a client's tunnel (`ctx`), the response status is recorded in its
+// metrics and access log.
 // ---------------------------------------------------------------------------
 pub(super) async fn http_connect(
     outbound: &TcpStream,
     target: &str,
     headers: &HashMap<String, String>,
+    ctx: Option<&ConnectionContext>,
 ) -> Result<(), Box<dyn Error>> {
     // Build request -----------------------------------------------------------
     let mut buf = String::with_capacity(256);
@@ -26,6 +33,18 @@ pub(super) async fn http_connect(
         buf.push_str(&resolve_header_value(value)?);
         buf.push_str("\r\n");
     }
+    // A client's tunnel continues its trace at the next proxy, unless the
+    // config sets the header itself.
+    if ctx.is_some()
+        && !headers
+            .keys()
+            .any(|name| name.eq_ignore_ascii_case("traceparent"))
+        && let Some(traceparent) = telemetry::traceparent()
+    {
+        buf.push_str("traceparent: ");
+        buf.push_str(&traceparent);
+        buf.push_str("\r\n");
+    }
     buf.push_str("\r\n");
 
     debug!("Send to via proxy: {:?}", buf.as_str());
@@ -76,6 +95,11 @@ pub(super) async fn http_connect(
         }
     };
     debug!("CONNECT response status: {}", status);
+    Span::current().record("status", status);
+    if let Some(ctx) = ctx {
+        metrics::registry().connect_status(&ctx.labels, status);
+        ctx.tunnel.update(|t| t.connect_status = Some(status));
+    }
     match status {
         200..=299 => {}
         403 => {
@@ -86,11 +110,21 @@ pub(super) async fn http_connect(
         }
         502 => {
             info!("Got: 502 Bad Gateway.");
-            return Err(Box::new(ProxyError("Got: 502 Bad Gateway.".into())));
+            return Err(Box::new(UpstreamUnavailable(
+                "Got: 502 Bad Gateway.".into(),
+            )));
         }
         503 => {
             info!("Got: 503 Service Unavailable.");
-            return Err(Box::new(ProxyError("Got: 503 Service Unavailable.".into())));
+            return Err(Box::new(UpstreamUnavailable(
+                "Got: 503 Service Unavailable.".into(),
+            )));
+        }
+        504 => {
+            info!("Got: 504 Gateway Timeout.");
+            return Err(Box::new(UpstreamUnavailable(
+                "Got: 504 Gateway Timeout.".into(),
+            )));
         }
         other => {
             info!(
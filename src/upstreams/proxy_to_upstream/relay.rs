@@ -6,6 +6,10 @@ use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
 use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::TcpStream;
+use tokio_util::task::AbortOnDropHandle;
+
+use crate::access_log::ConnectionId;
+use crate::connection_id;
 
 // ---------------------------------------------------------------------------
 // Bidirectional relay with optional periodic rx/tx stats logging.
@@ -15,16 +19,22 @@ use tokio::net::TcpStream;
 //   bytes_rx = outbound → inbound
 //
 // If `stats_interval` is non-zero a background task logs the running counters
-// at that interval until the relay completes.
+// at that interval until the relay completes, under the connection `id`.
+//
+// `counters` are this tunnel's (tx, rx) counters and `totals` the ones shared
+// by all tunnels with the same metrics labels; both are advanced as data
+// flows. The stats logger stops when the relay completes or is dropped.
 // ---------------------------------------------------------------------------
 pub(super) async fn relay(
     inbound: TcpStream,
     outbound: TcpStream,
+    id: ConnectionId,
     label: String,
     stats_interval: Duration,
+    counters: (Arc<AtomicU64>, Arc<AtomicU64>),
+    totals: (Arc<AtomicU64>, Arc<AtomicU64>),
 ) -> Result<(u64, u64), Box<dyn Error>> {
-    let bytes_tx = Arc::new(AtomicU64::new(0));
-    let bytes_rx = Arc::new(AtomicU64::new(0));
+    let (bytes_tx, bytes_rx) = counters;
 
     let (mut ri, mut wi) = io::split(inbound);
     let (mut ro, mut wo) = io::split(outbound);
@@ -33,45 +43,45 @@ pub(super) async fn relay(
     let log_handle = if stats_interval > Duration::ZERO {
         let tx = bytes_tx.clone();
         let rx = bytes_rx.clone();
-        Some(tokio::spawn(async move {
-            let mut ticker = tokio::time::interval(stats_interval);
-            ticker.tick().await; // skip the first immediate tick
-            loop {
-                ticker.tick().await;
-                info!(
-                    "[relay:{}] in-flight tx={} rx={}",
-                    label,
-                    tx.load(Ordering::Relaxed),
-                    rx.load(Ordering::Relaxed),
-                );
-            }
-        }))
+        Some(AbortOnDropHandle::new(tokio::spawn(connection_id::scope(
+            id,
+            async move {
+                let mut ticker = tokio::time::interval(stats_interval);
+                ticker.tick().await; // skip the first immediate tick
+                loop {
+                    ticker.tick().await;
+                    info!(
+                        "[relay:{}] in-flight tx={} rx={}",
+                        label,
+                        tx.load(Ordering::Relaxed),
+                        rx.load(Ordering::Relaxed),
+                    );
+                }
+            },
+        ))))
     } else {
         None
     };
 
     let result = try_join(
-        copy_counted(&mut ri, &mut wo, bytes_tx.clone()),
-        copy_counted(&mut ro, &mut wi, bytes_rx.clone()),
+        copy_counted(&mut ri, &mut wo, [bytes_tx, totals.0]),
+        copy_counted(&mut ro, &mut wi, [bytes_rx, totals.1]),
     )
     .await;
-
-    if let Some(h) = log_handle {
-        h.abort();
-    }
+    drop(log_handle);
 
     let (tx, rx) = result?;
     Ok((tx, rx))
 }
 
 // ---------------------------------------------------------------------------
-// Copy bytes from reader to writer, updating an atomic counter as we go.
+// Copy bytes from reader to writer, updating the atomic counters as we go.
 // Shuts down the writer when the reader closes or errors.
 // ---------------------------------------------------------------------------
-async fn copy_counted(
+async fn copy_counted<const N: usize>(
     reader: &mut (impl AsyncRead + Unpin),
     writer: &mut (impl AsyncWrite + Unpin),
-    counter: Arc<AtomicU64>,
+    counters: [Arc<AtomicU64>; N],
 ) -> io::Result<u64> {
     let mut buf = vec![0u8; 16 * 1024];
     let mut total = 0u64;
@@ -91,7 +101,9 @@ async fn copy_counted(
             return Ok(total);
         }
         total += n as u64;
-        counter.fetch_add(n as u64, Ordering::Relaxed);
+        for counter in &counters {
+            counter.fetch_add(n as u64, Ordering::Relaxed);
+        }
     }
     let _ = writer.shutdown().await;
     Ok(total)
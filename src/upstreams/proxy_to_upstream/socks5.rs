@@ -0,0 +1,181 @@
+use log::debug;
+use std::error::Error;
+use std::net::IpAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use super::http::resolve_header_value;
+use super::{ProxyError, UpstreamUnavailable};
+
+const VERSION: u8 = 0x05;
+const METHOD_NO_AUTH: u8 = 0x00;
+const METHOD_USER_PASS: u8 = 0x02;
+const METHOD_NONE_ACCEPTABLE: u8 = 0xff;
+const USER_PASS_VERSION: u8 = 0x01;
+const CMD_CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+/// Username/password for RFC 1929 authentication. The password may contain a
+/// `$VARNAME` placeholder, resolved from the environment like `via` headers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Socks5Auth {
+    pub username: String,
+    pub password: String,
+}
+
+// ---------------------------------------------------------------------------
+// Run the SOCKS5 handshake and CONNECT to `target` ("host:port").
+// ---------------------------------------------------------------------------
+pub(super) async fn socks5_connect(
+    outbound: &mut TcpStream,
+    target: &str,
+    auth: Option<&Socks5Auth>,
+) -> Result<(), Box<dyn Error>> {
+    // Method negotiation ------------------------------------------------------
+    let greeting: &[u8] = match auth {
+        Some(_) => &[VERSION, 2, METHOD_NO_AUTH, METHOD_USER_PASS],
+        None => &[VERSION, 1, METHOD_NO_AUTH],
+    };
+    outbound.write_all(greeting).await?;
+
+    let mut choice = [0u8; 2];
+    outbound.read_exact(&mut choice).await?;
+    if choice[0] != VERSION {
+        return Err(Box::new(ProxyError(format!(
+            "SOCKS5 server replied with version {}",
+            choice[0]
+        ))));
+    }
+    debug!("SOCKS5 method selected: {}", choice[1]);
+
+    match (choice[1], auth) {
+        (METHOD_NO_AUTH, _) => {}
+        (METHOD_USER_PASS, Some(auth)) => authenticate(outbound, auth).await?,
+        (METHOD_NONE_ACCEPTABLE, _) => {
+            return Err(Box::new(ProxyError(
+                "SOCKS5 server accepted none of the offered auth methods".into(),
+            )));
+        }
+        (other, _) => {
+            return Err(Box::new(ProxyError(format!(
+                "SOCKS5 server selected unexpected auth method {}",
+                other
+            ))));
+        }
+    }
+
+    // CONNECT request ---------------------------------------------------------
+    let request = encode_connect(target)?;
+    debug!("SOCKS5 CONNECT {}", target);
+    outbound.write_all(&request).await?;
+
+    // Reply: VER REP RSV ATYP BND.ADDR BND.PORT --------------------------------
+    let mut reply = [0u8; 4];
+    outbound.read_exact(&mut reply).await?;
+    if reply[1] != 0 {
+        let message = format!(
+            "SOCKS5 CONNECT {} failed: {}",
+            target,
+            reply_message(reply[1])
+        );
+        // Failures to reach the target may succeed through another upstream;
+        // "not allowed" and unsupported requests will not.
+        return Err(match reply[1] {
+            0x01 | 0x03..=0x06 => Box::new(UpstreamUnavailable(message)),
+            _ => Box::new(ProxyError(message)),
+        });
+    }
+    let addr_len = match reply[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => outbound.read_u8().await? as usize,
+        other => {
+            return Err(Box::new(ProxyError(format!(
+                "SOCKS5 reply with unknown address type {}",
+                other
+            ))));
+        }
+    };
+    let mut bound = vec![0u8; addr_len + 2];
+    outbound.read_exact(&mut bound).await?;
+    debug!("SOCKS5 CONNECT {} established", target);
+
+    Ok(())
+}
+
+/// RFC 1929 username/password sub-negotiation.
+async fn authenticate(outbound: &mut TcpStream, auth: &Socks5Auth) -> Result<(), Box<dyn Error>> {
+    let password = resolve_header_value(&auth.password)?;
+    if auth.username.len() > 255 || password.len() > 255 {
+        return Err(Box::new(ProxyError(
+            "SOCKS5 username and password must be at most 255 bytes".into(),
+        )));
+    }
+
+    let mut buf = Vec::with_capacity(3 + auth.username.len() + password.len());
+    buf.push(USER_PASS_VERSION);
+    buf.push(auth.username.len() as u8);
+    buf.extend_from_slice(auth.username.as_bytes());
+    buf.push(password.len() as u8);
+    buf.extend_from_slice(password.as_bytes());
+    outbound.write_all(&buf).await?;
+
+    let mut status = [0u8; 2];
+    outbound.read_exact(&mut status).await?;
+    if status[1] != 0 {
+        return Err(Box::new(ProxyError(format!(
+            "SOCKS5 authentication failed for user {:?}",
+            auth.username
+        ))));
+    }
+    Ok(())
+}
+
+/// Build the CONNECT request. IP literals are sent as addresses, everything
+/// else as a domain name so the SOCKS server resolves it.
+fn encode_connect(target: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let invalid = || ProxyError(format!("invalid SOCKS5 target {:?}", target));
+    let (host, port) = target.rsplit_once(':').ok_or_else(invalid)?;
+    let port: u16 = port.parse().map_err(|_| invalid())?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    let mut buf = vec![VERSION, CMD_CONNECT, 0x00];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            buf.push(ATYP_IPV4);
+            buf.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            buf.push(ATYP_IPV6);
+            buf.extend_from_slice(&ip.octets());
+        }
+        Err(_) if host.is_empty() || host.len() > 255 => return Err(Box::new(invalid())),
+        Err(_) => {
+            buf.push(ATYP_DOMAIN);
+            buf.push(host.len() as u8);
+            buf.extend_from_slice(host.as_bytes());
+        }
+    }
+    buf.extend_from_slice(&port.to_be_bytes());
+    Ok(buf)
+}
+
+fn reply_message(code: u8) -> &'static str {
+    match code {
+        0x01 => "general SOCKS server failure",
+        0x02 => "connection not allowed by ruleset",
+        0x03 => "network unreachable",
+        0x04 => "host unreachable",
+        0x05 => "connection refused",
+        0x06 => "TTL expired",
+        0x07 => "command not supported",
+        0x08 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+#[cfg(test)]
+#[path = "socks5_tests.rs"]
+mod tests;
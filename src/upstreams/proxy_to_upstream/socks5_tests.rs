@@ -0,0 +1,132 @@
+use super::*;
+use tokio::net::TcpListener;
+
+/// Start a one-shot SOCKS5 server that, per step, reads `reads[i]` bytes and
+/// answers with `script[i]`. The handle yields everything it received.
+async fn mock_server(
+    script: Vec<Vec<u8>>,
+    reads: Vec<usize>,
+) -> (TcpStream, tokio::task::JoinHandle<Vec<u8>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handle = tokio::spawn(async move {
+        let (mut s, _) = listener.accept().await.unwrap();
+        let mut received = Vec::new();
+        for (reply, len) in script.into_iter().zip(reads) {
+            let mut buf = vec![0u8; len];
+            s.read_exact(&mut buf).await.unwrap();
+            received.extend_from_slice(&buf);
+            s.write_all(&reply).await.unwrap();
+        }
+        received
+    });
+    (TcpStream::connect(addr).await.unwrap(), handle)
+}
+
+const SUCCESS_REPLY: [u8; 10] = [5, 0, 0, ATYP_IPV4, 10, 0, 0, 1, 0x1f, 0x90];
+
+#[tokio::test]
+async fn test_socks5_connect_no_auth_domain() {
+    let request_len = 4 + 1 + "www.example.com".len() + 2;
+    let (mut stream, server) = mock_server(
+        vec![vec![5, METHOD_NO_AUTH], SUCCESS_REPLY.to_vec()],
+        vec![3, request_len],
+    )
+    .await;
+
+    socks5_connect(&mut stream, "www.example.com:443", None)
+        .await
+        .unwrap();
+
+    let received = server.await.unwrap();
+    assert_eq!(&received[..3], &[5, 1, METHOD_NO_AUTH]);
+    let request = &received[3..];
+    assert_eq!(&request[..5], &[5, CMD_CONNECT, 0, ATYP_DOMAIN, 15]);
+    assert_eq!(&request[5..20], b"www.example.com");
+    assert_eq!(&request[20..], &443u16.to_be_bytes());
+}
+
+#[tokio::test]
+async fn test_socks5_connect_user_pass() {
+    let auth = Socks5Auth {
+        username: "alice".to_string(),
+        password: "s3cret".to_string(),
+    };
+    let (mut stream, server) = mock_server(
+        vec![
+            vec![5, METHOD_USER_PASS],
+            vec![USER_PASS_VERSION, 0],
+            SUCCESS_REPLY.to_vec(),
+        ],
+        vec![4, 3 + 5 + 6, 10],
+    )
+    .await;
+
+    socks5_connect(&mut stream, "192.0.2.1:8443", Some(&auth))
+        .await
+        .unwrap();
+
+    let received = server.await.unwrap();
+    assert_eq!(&received[..4], &[5, 2, METHOD_NO_AUTH, METHOD_USER_PASS]);
+    assert_eq!(&received[4..18], b"\x01\x05alice\x06s3cret");
+    assert_eq!(
+        &received[18..],
+        &[5, 1, 0, ATYP_IPV4, 192, 0, 2, 1, 0x20, 0xfb]
+    );
+}
+
+#[tokio::test]
+async fn test_socks5_auth_rejected() {
+    let auth = Socks5Auth {
+        username: "alice".to_string(),
+        password: "wrong".to_string(),
+    };
+    let (mut stream, _server) = mock_server(
+        vec![vec![5, METHOD_USER_PASS], vec![USER_PASS_VERSION, 1]],
+        vec![4, 3 + 5 + 5],
+    )
+    .await;
+
+    let err = socks5_connect(&mut stream, "www.example.com:443", Some(&auth))
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("authentication failed"));
+}
+
+#[tokio::test]
+async fn test_socks5_no_acceptable_method() {
+    let (mut stream, _server) = mock_server(vec![vec![5, METHOD_NONE_ACCEPTABLE]], vec![3]).await;
+
+    let err = socks5_connect(&mut stream, "www.example.com:443", None)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("none of the offered auth methods"));
+}
+
+#[tokio::test]
+async fn test_socks5_connect_refused_by_server() {
+    let (mut stream, _server) = mock_server(
+        vec![vec![5, METHOD_NO_AUTH], vec![5, 0x02, 0, ATYP_IPV4]],
+        vec![3, 4 + 16 + 2],
+    )
+    .await;
+
+    let err = socks5_connect(&mut stream, "[2001:db8::1]:443", None)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("not allowed by ruleset"));
+}
+
+#[test]
+fn test_encode_connect_ipv6() {
+    let buf = encode_connect("[2001:db8::1]:443").unwrap();
+    assert_eq!(&buf[..4], &[5, CMD_CONNECT, 0, ATYP_IPV6]);
+    assert_eq!(buf.len(), 4 + 16 + 2);
+}
+
+#[test]
+fn test_encode_connect_invalid_target() {
+    assert!(encode_connect("www.example.com").is_err());
+    assert!(encode_connect(":443").is_err());
+    assert!(encode_connect("www.example.com:http").is_err());
+}
@@ -45,7 +45,7 @@ async fn test_copy_counted_ok() {
     let counter = Arc::new(AtomicU64::new(0));
     let (mut sink_write, _sink_read) = tokio::io::duplex(1024);
 
-    let n = copy_counted(&mut read_end, &mut sink_write, counter.clone())
+    let n = copy_counted(&mut read_end, &mut sink_write, [counter.clone()])
         .await
         .unwrap();
     assert_eq!(n, 11);
@@ -58,7 +58,7 @@ async fn test_copy_counted_read_error() {
     let counter = Arc::new(AtomicU64::new(0));
     let (mut sink_write, _sink_read) = tokio::io::duplex(1024);
 
-    let n = copy_counted(&mut ErrReader, &mut sink_write, counter.clone())
+    let n = copy_counted(&mut ErrReader, &mut sink_write, [counter.clone()])
         .await
         .unwrap();
     assert_eq!(n, 0);
@@ -73,7 +73,7 @@ async fn test_copy_counted_write_error() {
     drop(write_end);
 
     let counter = Arc::new(AtomicU64::new(0));
-    let n = copy_counted(&mut read_end, &mut ErrWriter, counter.clone())
+    let n = copy_counted(&mut read_end, &mut ErrWriter, [counter.clone()])
         .await
         .unwrap();
     assert_eq!(n, 0);
@@ -105,9 +105,17 @@ async fn test_relay_no_stats() {
     });
 
     let outbound = tokio::net::TcpStream::connect(echo_addr).await.unwrap();
-    let (tx, rx) = relay(inbound, outbound, "test".to_string(), Duration::ZERO)
-        .await
-        .unwrap();
+    let (tx, rx) = relay(
+        inbound,
+        outbound,
+        ConnectionId::new(),
+        "test".to_string(),
+        Duration::ZERO,
+        Default::default(),
+        Default::default(),
+    )
+    .await
+    .unwrap();
 
     let echoed = client_task.await.unwrap();
     let _ = echo_task.await;
@@ -143,8 +151,11 @@ async fn test_relay_with_stats_interval() {
     let (tx, rx) = relay(
         inbound,
         outbound,
+        ConnectionId::new(),
         "stats_test".to_string(),
         Duration::from_secs(3600),
+        Default::default(),
+        Default::default(),
     )
     .await
     .unwrap();
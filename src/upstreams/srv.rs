@@ -0,0 +1,174 @@
+use log::{debug, info, warn};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::sync::{Arc, RwLock};
+use tokio_util::sync::CancellationToken;
+
+use crate::config::{CircuitBreakerConfig, PoolStrategy, ViaUpstream};
+use crate::servers::upstream_address::{self, SrvRecord};
+use crate::upstreams::proxy_to_upstream::UpstreamUnavailable;
+use crate::upstreams::{ConnectionContext, PoolMember, ProxyToUpstream, Tunnel, UpstreamPool};
+
+// ---------------------------------------------------------------------------
+// SrvUpstream — pool discovered from DNS SRV records
+//
+// `srv://_proxy._tcp.corp.org` looks up the SRV records and groups the
+// targets by priority. The lowest priority is tried first, spreading load by
+// weight within it; when that connect fails, the next priority is tried.
+// The records are looked up again when their TTL expires. Targets that stay
+// keep their circuit breaker and connection counts.
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone)]
+pub struct SrvUpstream {
+    /// The SRV name, e.g. `_proxy._tcp.corp.org`.
+    pub name: String,
+    circuit_breaker: Option<CircuitBreakerConfig>,
+    /// One weighted pool per priority, lowest priority first. Shared by all
+    /// clones so the refresh task updates every server's view.
+    groups: Arc<RwLock<Vec<(u16, UpstreamPool)>>>,
+}
+
+impl SrvUpstream {
+    pub fn new(name: String) -> Self {
+        SrvUpstream {
+            name,
+            circuit_breaker: None,
+            groups: Arc::default(),
+        }
+    }
+
+    /// Give every target its own circuit breaker.
+    pub fn with_circuit_breaker(mut self, config: CircuitBreakerConfig) -> Self {
+        self.circuit_breaker = Some(config);
+        self
+    }
+
+    /// Current targets with their priority, lowest priority first.
+    pub fn members(&self) -> Vec<(u16, PoolMember)> {
+        self.groups()
+            .into_iter()
+            .flat_map(|(priority, pool)| pool.members.into_iter().map(move |m| (priority, m)))
+            .collect()
+    }
+
+    fn groups(&self) -> Vec<(u16, UpstreamPool)> {
+        self.groups
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Replace the targets with `records`.
+    pub(crate) fn update(&self, records: &[SrvRecord]) {
+        let current: HashMap<String, PoolMember> = self
+            .members()
+            .into_iter()
+            .map(|(_, m)| (m.upstream.addr.clone(), m))
+            .collect();
+
+        let mut by_priority: BTreeMap<u16, Vec<PoolMember>> = BTreeMap::new();
+        for record in records {
+            let addr = record.address();
+            // Weight 0 means "only if nothing else is available"; 1 is the
+            // closest a weighted pool gets.
+            let weight = u32::from(record.weight).max(1);
+            let member = match current.get(&addr) {
+                Some(member) => member.with_weight(weight),
+                None => {
+                    let mut proxy = ProxyToUpstream::new(addr, "tcp".to_string());
+                    if let Some(breaker) = &self.circuit_breaker {
+                        proxy = proxy.with_circuit_breaker(breaker.clone());
+                    }
+                    PoolMember::new(proxy, weight)
+                }
+            };
+            by_priority.entry(record.priority).or_default().push(member);
+        }
+
+        let groups = by_priority
+            .into_iter()
+            .map(|(priority, members)| {
+                (priority, UpstreamPool::new(PoolStrategy::Weighted, members))
+            })
+            .collect();
+        *self.groups.write().unwrap_or_else(|e| e.into_inner()) = groups;
+    }
+
+    /// Look the records up again and resolve the targets. On failure the
+    /// current targets are kept. Returns how long the records are valid.
+    pub(crate) async fn refresh(&self) -> std::io::Result<std::time::Duration> {
+        let (records, ttl) = upstream_address::lookup_srv(&self.name).await?;
+        debug!(
+            "SRV {} has {} targets, valid for {:?}: {:?}",
+            self.name,
+            records.len(),
+            ttl,
+            records
+        );
+        self.update(&records);
+        for (_, member) in self.members() {
+            if let Err(e) = member.upstream.resolve_dns().await {
+                debug!("Failed resolving {}: {}", member.upstream.addr, e);
+            }
+        }
+        Ok(ttl)
+    }
+
+    /// Refresh shortly before the records expire, until `token` is cancelled.
+    pub(crate) async fn keep_fresh(&self, token: CancellationToken) {
+        loop {
+            let wait = match self.refresh().await {
+                Ok(ttl) => ttl.mul_f64(0.9),
+                Err(e) => {
+                    warn!("Failed refreshing SRV upstream {}: {}", self.name, e);
+                    upstream_address::negative_ttl()
+                }
+            }
+            .max(upstream_address::MIN_REFRESH_INTERVAL);
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = token.cancelled() => return,
+            }
+        }
+    }
+
+    /// Connect through the lowest priority whose selected target can be
+    /// reached; see `UpstreamPool::connect`.
+    pub(crate) async fn connect(
+        &self,
+        via: &ViaUpstream,
+        connect_target: Option<&str>,
+        ctx: &ConnectionContext,
+    ) -> Result<Tunnel, Box<dyn Error>> {
+        let mut groups = self.groups();
+        if groups.is_empty() {
+            // Only before the first successful lookup.
+            info!("Resolving SRV upstream {} on first use", self.name);
+            if let Err(e) = self.refresh().await {
+                return Err(Box::new(UpstreamUnavailable(e.to_string())));
+            }
+            groups = self.groups();
+        }
+
+        let mut last_error = String::new();
+        for (priority, pool) in &groups {
+            match pool.connect(via, connect_target, ctx).await {
+                Ok(tunnel) => return Ok(tunnel),
+                Err(e) if e.is::<UpstreamUnavailable>() => {
+                    debug!("SRV {} priority {} failed: {}", self.name, priority, e);
+                    last_error = e.to_string();
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(Box::new(UpstreamUnavailable(format!(
+            "no target of SRV {} could be reached: {}",
+            self.name, last_error
+        ))))
+    }
+}
+
+#[cfg(test)]
+#[path = "srv_tests.rs"]
+mod tests;
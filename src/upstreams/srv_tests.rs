@@ -0,0 +1,112 @@
+use super::*;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+
+fn record(priority: u16, weight: u16, target: &str, port: u16) -> SrvRecord {
+    SrvRecord {
+        priority,
+        weight,
+        port,
+        target: target.to_string(),
+    }
+}
+
+fn addrs(srv: &SrvUpstream) -> Vec<(u16, String, u32)> {
+    srv.members()
+        .into_iter()
+        .map(|(p, m)| (p, m.upstream.addr.clone(), m.weight))
+        .collect()
+}
+
+#[test]
+fn test_update_groups_by_priority() {
+    let srv = SrvUpstream::new("_proxy._tcp.corp.org".to_string());
+    srv.update(&[
+        record(20, 10, "backup.corp.org", 3128),
+        record(10, 60, "proxy1.corp.org", 3128),
+        record(10, 0, "proxy2.corp.org", 8080),
+    ]);
+    assert_eq!(
+        addrs(&srv),
+        vec![
+            (10, "proxy1.corp.org:3128".to_string(), 60),
+            (10, "proxy2.corp.org:8080".to_string(), 1),
+            (20, "backup.corp.org:3128".to_string(), 10),
+        ]
+    );
+}
+
+#[test]
+fn test_update_keeps_existing_targets() {
+    let breaker: CircuitBreakerConfig =
+        serde_yaml_ng::from_str("{consecutive_failures: 1}").unwrap();
+    let srv = SrvUpstream::new("_proxy._tcp.corp.org".to_string()).with_circuit_breaker(breaker);
+    srv.update(&[
+        record(10, 1, "proxy1.corp.org", 3128),
+        record(10, 1, "proxy2.corp.org", 3128),
+    ]);
+    let (_, proxy1) = srv.members().remove(0);
+    proxy1.upstream.circuit_breaker().unwrap().record(true);
+
+    srv.update(&[
+        record(10, 5, "proxy1.corp.org", 3128),
+        record(10, 1, "proxy3.corp.org", 3128),
+    ]);
+    let members = srv.members();
+    assert_eq!(members.len(), 2);
+    assert_eq!(members[0].1.weight, 5);
+    assert!(!members[0].1.upstream.is_available(), "breaker state lost");
+    assert!(members[1].1.upstream.is_available());
+}
+
+#[test]
+fn test_clones_share_targets() {
+    let srv = SrvUpstream::new("_proxy._tcp.corp.org".to_string());
+    let clone = srv.clone();
+    srv.update(&[record(10, 1, "proxy1.corp.org", 3128)]);
+    assert_eq!(clone.members().len(), 1);
+}
+
+#[test]
+fn test_record_address_brackets_ipv6() {
+    assert_eq!(record(0, 0, "::1", 3128).address(), "[::1]:3128");
+    assert_eq!(
+        record(0, 0, "proxy.corp.org", 3128).address(),
+        "proxy.corp.org:3128"
+    );
+}
+
+#[tokio::test]
+async fn test_connect_falls_back_to_next_priority() {
+    // Bind and drop to get a port nothing listens on.
+    let dead = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let dead_port = dead.local_addr().unwrap().port();
+    drop(dead);
+    let backend = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let backend_port = backend.local_addr().unwrap().port();
+
+    let srv = SrvUpstream::new("_proxy._tcp.corp.org".to_string());
+    srv.update(&[
+        record(10, 1, "127.0.0.1", dead_port),
+        record(20, 1, "127.0.0.1", backend_port),
+    ]);
+
+    let front = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut client = TcpStream::connect(front.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (inbound, _) = front.accept().await.unwrap();
+    let ctx = ConnectionContext::new(client.local_addr().unwrap(), inbound.local_addr().unwrap());
+
+    let tunnel = tokio::spawn(async move {
+        let via = ViaUpstream::default();
+        crate::upstreams::Upstream::Srv(srv)
+            .process(inbound, &via, None, &ctx)
+            .await
+            .map_err(|e| e.to_string())
+    });
+    let (mut server_side, _) = backend.accept().await.unwrap();
+    client.shutdown().await.unwrap();
+    server_side.shutdown().await.unwrap();
+    tunnel.await.unwrap().unwrap();
+}
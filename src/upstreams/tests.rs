@@ -1,5 +1,5 @@
 use super::*;
-use crate::config::ViaUpstream;
+use crate::config::{PoolStrategy, ViaUpstream};
 use std::pin::Pin;
 use std::task::{Context, Poll};
 use tokio::io::{AsyncReadExt, AsyncWriteExt, ReadBuf};
@@ -33,6 +33,13 @@ impl AsyncWrite for NullWriter {
 }
 impl Unpin for NullWriter {}
 
+fn ctx() -> ConnectionContext {
+    ConnectionContext::new(
+        "127.0.0.1:50000".parse().unwrap(),
+        "127.0.0.1:443".parse().unwrap(),
+    )
+}
+
 // Covers: copy() Ok branch
 #[tokio::test]
 async fn test_copy_success() {
@@ -58,7 +65,7 @@ async fn test_upstream_ban() {
     let _client = TcpStream::connect(addr).await.unwrap();
     let (server, _) = listener.accept().await.unwrap();
     let result = Upstream::Ban
-        .process(server, &ViaUpstream::default(), None)
+        .process(server, &ViaUpstream::default(), None, &ctx())
         .await;
     assert!(result.is_ok());
 }
@@ -78,7 +85,7 @@ async fn test_upstream_echo() {
     });
     let (server, _) = listener.accept().await.unwrap();
     Upstream::Echo
-        .process(server, &ViaUpstream::default(), None)
+        .process(server, &ViaUpstream::default(), None, &ctx())
         .await
         .unwrap();
     assert_eq!(client_task.await.unwrap(), b"ping");
@@ -100,8 +107,8 @@ async fn test_upstream_health_ok_path() {
         String::from_utf8_lossy(&resp).to_string()
     });
     let (server, _) = listener.accept().await.unwrap();
-    Upstream::Health(Arc::new(vec![]))
-        .process(server, &ViaUpstream::default(), None)
+    Upstream::Health(Arc::default())
+        .process(server, &ViaUpstream::default(), None, &ctx())
         .await
         .unwrap();
     let resp = client_task.await.unwrap();
@@ -125,19 +132,409 @@ async fn test_upstream_health_metrics_path() {
         String::from_utf8_lossy(&resp).to_string()
     });
     let (server, _) = listener.accept().await.unwrap();
-    let metrics = Arc::new(vec![MetricsEntry {
-        name: "test_server".to_string(),
-        listen: "127.0.0.1:9999".to_string(),
-        maxclients_limit: 50,
-        semaphore: Arc::new(Semaphore::new(45)),
-    }]);
+    let metrics = Arc::new(MetricsData {
+        servers: vec![MetricsEntry {
+            name: "test_server".to_string(),
+            listen: "127.0.0.1:9999".to_string(),
+            maxclients_limit: 50,
+            semaphore: Arc::new(Semaphore::new(45)),
+            client_hello_timeouts: Arc::new(AtomicU64::new(0)),
+        }],
+        upstreams: Vec::new(),
+        admin: Default::default(),
+    });
     Upstream::Health(metrics)
-        .process(server, &ViaUpstream::default(), None)
+        .process(server, &ViaUpstream::default(), None, &ctx())
         .await
         .unwrap();
     let resp = client_task.await.unwrap();
     assert!(resp.contains("tpt_active_connections"));
     assert!(resp.contains("tpt_maxclients"));
+    assert!(resp.contains("tpt_client_hello_timeouts_total"));
     assert!(resp.contains("test_server"));
     assert!(resp.contains("text/plain; version=0.0.4"));
 }
+
+async fn health_request(path: &'static str, metrics: Metrics) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client_task = tokio::spawn(async move {
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            path
+        );
+        client.write_all(request.as_bytes()).await.unwrap();
+        let mut resp = Vec::new();
+        client.read_to_end(&mut resp).await.unwrap();
+        String::from_utf8_lossy(&resp).to_string()
+    });
+    let (server, _) = listener.accept().await.unwrap();
+    Upstream::Health(metrics)
+        .process(server, &ViaUpstream::default(), None, &ctx())
+        .await
+        .unwrap();
+    client_task.await.unwrap()
+}
+
+// Covers: /status JSON and tpt_upstream_* series for health-checked upstreams
+#[tokio::test]
+async fn test_upstream_health_status_reports_checks() {
+    let check: crate::config::HealthCheckConfig = serde_yaml_ng::from_str("{fall: 1}").unwrap();
+    let proxy = ProxyToUpstream::new("10.0.0.1:3128".to_string(), "tcp".to_string())
+        .with_health_check(check);
+    proxy
+        .health()
+        .unwrap()
+        .record(Err("connection refused".into()));
+    let metrics = Arc::new(MetricsData {
+        servers: Vec::new(),
+        upstreams: vec![UpstreamTarget {
+            upstream: "corp".to_string(),
+            proxy,
+        }],
+        admin: Default::default(),
+    });
+
+    let resp = health_request("/status", metrics.clone()).await;
+    assert!(resp.contains("application/json"));
+    let body = resp.split("\r\n\r\n").nth(1).unwrap();
+    let status: serde_json::Value = serde_json::from_str(body).unwrap();
+    let upstream = &status["upstreams"][0];
+    assert_eq!(upstream["upstream"], "corp");
+    assert_eq!(upstream["address"], "10.0.0.1:3128");
+    assert_eq!(upstream["healthy"], false);
+    assert_eq!(upstream["failures_total"], 1);
+    assert_eq!(upstream["last_error"], "connection refused");
+
+    let resp = health_request("/metrics", metrics).await;
+    assert!(resp.contains(r#"tpt_upstream_healthy{upstream="corp",address="10.0.0.1:3128"} 0"#));
+    assert!(resp.contains(
+        r#"tpt_upstream_health_check_failures_total{upstream="corp",address="10.0.0.1:3128"} 1"#
+    ));
+}
+
+// Covers: Upstream::Proxy with via.proxy_protocol → header precedes client data
+#[tokio::test]
+async fn test_upstream_proxy_sends_proxy_protocol_v1() {
+    use crate::proxy_protocol::ProxyProtocolVersion;
+
+    let backend = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let backend_addr = backend.local_addr().unwrap();
+    let backend_task = tokio::spawn(async move {
+        let (mut conn, _) = backend.accept().await.unwrap();
+        let mut buf = Vec::new();
+        conn.read_to_end(&mut buf).await.unwrap();
+        buf
+    });
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(b"hello").await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut buf = Vec::new();
+        let _ = client.read_to_end(&mut buf).await;
+    });
+    let (server, _) = listener.accept().await.unwrap();
+
+    let via = ViaUpstream {
+        proxy_protocol: Some(ProxyProtocolVersion::V1),
+        ..Default::default()
+    };
+    let upstream = Upstream::Proxy(ProxyToUpstream::new(
+        backend_addr.to_string(),
+        "tcp".to_string(),
+    ));
+    upstream.process(server, &via, None, &ctx()).await.unwrap();
+
+    let received = backend_task.await.unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&received),
+        "PROXY TCP4 127.0.0.1 127.0.0.1 50000 443\r\nhello"
+    );
+}
+
+/// Accept one connection from the proxy under test and return its inbound side;
+/// the client sends `hello` and then closes.
+async fn client_connection() -> TcpStream {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(b"hello").await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut buf = Vec::new();
+        let _ = client.read_to_end(&mut buf).await;
+    });
+    listener.accept().await.unwrap().0
+}
+
+/// An HTTP proxy that answers one CONNECT with `status` and, on 200, returns
+/// everything relayed after it.
+async fn connect_proxy(status: u16) -> (String, tokio::task::JoinHandle<Vec<u8>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let task = tokio::spawn(async move {
+        let (mut conn, _) = listener.accept().await.unwrap();
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            head.push(conn.read_u8().await.unwrap());
+        }
+        let response = format!("HTTP/1.1 {} Status\r\n\r\n", status);
+        conn.write_all(response.as_bytes()).await.unwrap();
+        let mut relayed = Vec::new();
+        if status == 200 {
+            conn.read_to_end(&mut relayed).await.unwrap();
+        }
+        relayed
+    });
+    (addr, task)
+}
+
+fn proxy_to(addr: &str) -> Upstream {
+    Upstream::Proxy(ProxyToUpstream::new(addr.to_string(), "tcp".to_string()))
+}
+
+// Covers: process_with_fallback() → refused connect falls back to the next upstream
+#[tokio::test]
+async fn test_fallback_after_connect_failure() {
+    let dead = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let dead_addr = dead.local_addr().unwrap().to_string();
+    drop(dead);
+    let (backup_addr, backup) = connect_proxy(200).await;
+
+    let (first, second) = (proxy_to(&dead_addr), proxy_to(&backup_addr));
+    let chain = [("dead", &first), ("backup", &second)];
+    process_with_fallback(
+        &chain,
+        client_connection().await,
+        &ViaUpstream::default(),
+        Some("www.corp.org:443".to_string()),
+        &ctx(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(backup.await.unwrap(), b"hello");
+}
+
+// Covers: process_with_fallback() → 502 to CONNECT falls back to the next upstream
+#[tokio::test]
+async fn test_fallback_after_bad_gateway() {
+    let (failing_addr, failing) = connect_proxy(502).await;
+    let (backup_addr, backup) = connect_proxy(200).await;
+
+    let (first, second) = (proxy_to(&failing_addr), proxy_to(&backup_addr));
+    let chain = [("failing", &first), ("backup", &second)];
+    process_with_fallback(
+        &chain,
+        client_connection().await,
+        &ViaUpstream::default(),
+        Some("www.corp.org:443".to_string()),
+        &ctx(),
+    )
+    .await
+    .unwrap();
+
+    failing.await.unwrap();
+    assert_eq!(backup.await.unwrap(), b"hello");
+}
+
+// Covers: process_with_fallback() → 403 is final, the fallback is not tried
+#[tokio::test]
+async fn test_fallback_not_used_for_forbidden() {
+    let (denying_addr, denying) = connect_proxy(403).await;
+    let unused = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let unused_addr = unused.local_addr().unwrap().to_string();
+
+    let (first, second) = (proxy_to(&denying_addr), proxy_to(&unused_addr));
+    let chain = [("denying", &first), ("unused", &second)];
+    let result = process_with_fallback(
+        &chain,
+        client_connection().await,
+        &ViaUpstream::default(),
+        Some("www.corp.org:443".to_string()),
+        &ctx(),
+    )
+    .await;
+
+    assert!(
+        result
+            .unwrap_err()
+            .to_string()
+            .contains("ERR_ACCESS_DENIED")
+    );
+    denying.await.unwrap();
+    let accepted =
+        tokio::time::timeout(std::time::Duration::from_millis(50), unused.accept()).await;
+    assert!(accepted.is_err(), "fallback upstream was contacted");
+}
+
+// Covers: Upstream::Direct → connects straight to the CONNECT target
+#[tokio::test]
+async fn test_upstream_direct_connects_to_target() {
+    let backend = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let backend_addr = backend.local_addr().unwrap();
+    let backend_task = tokio::spawn(async move {
+        let (mut conn, _) = backend.accept().await.unwrap();
+        let mut buf = Vec::new();
+        conn.read_to_end(&mut buf).await.unwrap();
+        buf
+    });
+
+    Upstream::Direct
+        .process(
+            client_connection().await,
+            &ViaUpstream::default(),
+            Some(backend_addr.to_string()),
+            &ctx(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(backend_task.await.unwrap(), b"hello");
+}
+
+// Covers: Upstream::Direct without a target → error
+#[tokio::test]
+async fn test_upstream_direct_requires_target() {
+    let result = Upstream::Direct
+        .process(
+            client_connection().await,
+            &ViaUpstream::default(),
+            None,
+            &ctx(),
+        )
+        .await;
+    assert!(result.unwrap_err().to_string().contains("needs a target"));
+}
+
+fn health_check() -> crate::config::HealthCheckConfig {
+    serde_yaml_ng::from_str("{}").unwrap()
+}
+
+#[test]
+fn test_monitored_upstreams_include_pool_members() {
+    let single = ProxyToUpstream::new("127.0.0.1:1".to_string(), "tcp".to_string())
+        .with_health_check(health_check());
+    let unchecked = ProxyToUpstream::new("127.0.0.1:2".to_string(), "tcp".to_string());
+    let guarded = ProxyToUpstream::new("127.0.0.1:5".to_string(), "tcp".to_string())
+        .with_circuit_breaker(serde_yaml_ng::from_str("{}").unwrap());
+    let pool = UpstreamPool::new(
+        PoolStrategy::RoundRobin,
+        vec![
+            PoolMember::new(
+                ProxyToUpstream::new("127.0.0.1:4".to_string(), "tcp".to_string())
+                    .with_health_check(health_check()),
+                1,
+            ),
+            PoolMember::new(
+                ProxyToUpstream::new("127.0.0.1:3".to_string(), "tcp".to_string())
+                    .with_health_check(health_check()),
+                1,
+            ),
+        ],
+    );
+    let map = HashMap::from([
+        ("single".to_string(), Upstream::Proxy(single)),
+        ("unchecked".to_string(), Upstream::Proxy(unchecked)),
+        ("guarded".to_string(), Upstream::Proxy(guarded)),
+        ("pool".to_string(), Upstream::Pool(pool)),
+        ("ban".to_string(), Upstream::Ban),
+    ]);
+
+    let targets: Vec<(String, String)> = monitored_upstreams(&map)
+        .into_iter()
+        .map(|t| (t.upstream, t.proxy.addr))
+        .collect();
+    assert_eq!(
+        targets,
+        vec![
+            ("guarded".to_string(), "127.0.0.1:5".to_string()),
+            ("pool".to_string(), "127.0.0.1:3".to_string()),
+            ("pool".to_string(), "127.0.0.1:4".to_string()),
+            ("single".to_string(), "127.0.0.1:1".to_string()),
+        ]
+    );
+}
+
+// Covers: open circuit breaker fails fast and diverts to the fallback
+#[tokio::test]
+async fn test_circuit_breaker_diverts_to_fallback() {
+    let dead = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let dead_addr = dead.local_addr().unwrap().to_string();
+    drop(dead);
+    let guarded = ProxyToUpstream::new(dead_addr, "tcp".to_string())
+        .with_circuit_breaker(serde_yaml_ng::from_str("{consecutive_failures: 1}").unwrap());
+    let breaker = guarded.circuit_breaker().unwrap().clone();
+    let first = Upstream::Proxy(guarded);
+
+    let result = first
+        .process(
+            client_connection().await,
+            &ViaUpstream::default(),
+            None,
+            &ctx(),
+        )
+        .await;
+    assert!(result.is_err());
+    assert_eq!(
+        breaker.state(),
+        crate::upstreams::circuit_breaker::CircuitState::Open
+    );
+
+    let (backup_addr, backup) = connect_proxy(200).await;
+    let second = proxy_to(&backup_addr);
+    let chain = [("guarded", &first), ("backup", &second)];
+    process_with_fallback(
+        &chain,
+        client_connection().await,
+        &ViaUpstream::default(),
+        Some("www.corp.org:443".to_string()),
+        &ctx(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(backup.await.unwrap(), b"hello");
+    assert_eq!(breaker.rejections_total(), 1);
+
+    let metrics = Arc::new(MetricsData {
+        servers: Vec::new(),
+        upstreams: monitored_upstreams(&HashMap::from([("guarded".to_string(), first)])),
+        admin: Default::default(),
+    });
+    let resp = health_request("/metrics", metrics.clone()).await;
+    assert!(resp.contains("tpt_upstream_circuit_state{upstream=\"guarded\""));
+    assert!(resp.contains("tpt_upstream_circuit_rejections_total{upstream=\"guarded\""));
+    assert!(!resp.contains("tpt_upstream_healthy"));
+    let resp = health_request("/status", metrics).await;
+    assert!(
+        resp.contains(r#""circuit_breaker":{"rejections_total":1,"state":"open","trips_total":1}"#)
+    );
+}
+
+// Covers: a 502 reports a dead target, not a dead proxy → breaker stays closed
+#[tokio::test]
+async fn test_circuit_breaker_ignores_bad_gateway() {
+    let (failing_addr, failing) = connect_proxy(502).await;
+    let guarded = ProxyToUpstream::new(failing_addr, "tcp".to_string())
+        .with_circuit_breaker(serde_yaml_ng::from_str("{consecutive_failures: 1}").unwrap());
+    let breaker = guarded.circuit_breaker().unwrap().clone();
+
+    let result = Upstream::Proxy(guarded)
+        .process(
+            client_connection().await,
+            &ViaUpstream::default(),
+            Some("www.corp.org:443".to_string()),
+            &ctx(),
+        )
+        .await;
+    assert!(result.is_err());
+    failing.await.unwrap();
+    assert_eq!(
+        breaker.state(),
+        crate::upstreams::circuit_breaker::CircuitState::Closed
+    );
+}